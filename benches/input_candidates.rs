@@ -0,0 +1,121 @@
+use std::hint::black_box;
+
+use bdk_tx::{
+    bitcoin::{hashes::Hash, secp256k1::Secp256k1, Amount, FeeRate, OutPoint, TxOut, Txid},
+    group_by_spk, miniscript, ChangeScript, ConfirmationStatus, Input, InputCandidates, Output,
+    Selector, SelectorParams,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use miniscript::{plan::Assets, Descriptor, DescriptorPublicKey};
+
+const WPKH_XPRV: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84h/1h/0h/0/*)";
+
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+/// The wallet descriptor and the assets to spend from it.
+fn wallet() -> (Descriptor<DescriptorPublicKey>, Assets) {
+    let (desc, keymap) = Descriptor::parse_descriptor(&Secp256k1::new(), WPKH_XPRV).unwrap();
+    let assets = keymap.keys().fold(Assets::new(), |a, k| a.add(k.clone()));
+    (desc, assets)
+}
+
+/// `count` inputs spending outputs of one of 1000 script pubkeys.
+fn inputs(count: usize) -> Vec<Input> {
+    let (desc, assets) = wallet();
+    let plans = (0..1000)
+        .map(|index| {
+            let desc = desc.at_derivation_index(index).unwrap();
+            let plan = desc.clone().plan(&assets).unwrap();
+            (desc.script_pubkey(), plan)
+        })
+        .collect::<Vec<_>>();
+    let status = Some(ConfirmationStatus::new(1, None).unwrap());
+    (0..count)
+        .map(|n| {
+            let (script_pubkey, plan) = &plans[n % plans.len()];
+            let mut txid = [0_u8; 32];
+            txid[..8].copy_from_slice(&(n as u64).to_le_bytes());
+            let prev_outpoint = OutPoint::new(Txid::from_byte_array(txid), 0);
+            let prev_txout = TxOut {
+                value: Amount::from_sat(10_000 + n as u64),
+                script_pubkey: script_pubkey.clone(),
+            };
+            Input::from_prev_txout(plan.clone(), prev_outpoint, prev_txout, status, false)
+        })
+        .collect()
+}
+
+fn params() -> SelectorParams {
+    let (desc, _) = wallet();
+    let recipient = desc.at_derivation_index(1001).unwrap().script_pubkey();
+    SelectorParams::new(
+        FeeRate::from_sat_per_vb_u32(2),
+        vec![Output::with_script(recipient, Amount::from_sat(100_000))],
+        ChangeScript::from_descriptor(desc.at_derivation_index(1000).unwrap()),
+    )
+}
+
+fn bench_input_candidates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("input_candidates");
+    group.sample_size(10);
+    for size in SIZES {
+        let inputs = inputs(size);
+        let removed = inputs[size / 2].clone();
+        let candidates = InputCandidates::new([], inputs.clone());
+
+        group.bench_with_input(BenchmarkId::new("new", size), &inputs, |b, inputs| {
+            b.iter_batched(
+                || inputs.clone(),
+                |inputs| InputCandidates::new([], inputs),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("filter", size), &candidates, |b, cands| {
+            b.iter_batched(
+                || cands.clone(),
+                |cands| cands.filter(|input| input.prev_txout().value.to_sat() % 10 != 0),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(
+            BenchmarkId::new("regroup", size),
+            &candidates,
+            |b, cands| {
+                b.iter_batched(
+                    || cands.clone(),
+                    |cands| cands.regroup(group_by_spk()),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("remove_insert", size),
+            &candidates,
+            |b, cands| {
+                b.iter_batched_ref(
+                    || cands.clone(),
+                    |cands| {
+                        cands.remove(removed.prev_outpoint());
+                        cands.insert(removed.clone())
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("selector_new", size),
+            &candidates,
+            |b, cands| {
+                b.iter_batched(
+                    params,
+                    |params| black_box(Selector::new(cands, params).is_ok()),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_input_candidates);
+criterion_main!(benches);
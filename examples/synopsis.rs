@@ -141,8 +141,12 @@ fn main() -> anyhow::Result<()> {
                     change_longterm_feerate: Some(longterm_feerate),
                     change_min_value: None,
                     change_dust_relay_feerate: None,
+                    change_split: None,
+                    change_script_alternatives: Default::default(),
                     // This ensures that we satisfy mempool-replacement policy rules 4 and 6.
                     replace: Some(rbf_params),
+                    fee_caps: Default::default(),
+                    prefer_changeless: None,
                 },
             )?;
 
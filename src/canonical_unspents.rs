@@ -1,14 +1,14 @@
 use alloc::sync::Arc;
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::fmt;
 
-use bitcoin::{absolute, psbt, Amount, OutPoint, Sequence, Transaction, TxOut, Txid};
+use bitcoin::{absolute, psbt, Amount, OutPoint, Sequence, Transaction, TxOut, Txid, Weight};
 use miniscript::{bitcoin, plan::Plan};
 
 use crate::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     input::CoinbaseMismatch,
-    ConfirmationStatus, FromPsbtInputError, Input, RbfSet,
+    ConfirmationStatus, FromPsbtInputError, Input, RbfParams, RbfSet, ReplacementError,
 };
 
 /// Tx with confirmation status.
@@ -72,90 +72,334 @@ impl CanonicalUnspents {
 
         // Find descendants of the original txs. Descendants are evicted by RBF and count toward
         // the required replacement fee.
-        let mut descendants = HashMap::<Txid, Arc<Transaction>>::new();
-        let mut visited = HashSet::<Txid>::new();
-        let mut to_visit = rbf_txs
-            .iter()
-            .map(|(txid, tx)| (*txid, tx.clone()))
-            .collect::<Vec<_>>();
-        while let Some((txid, tx)) = to_visit.pop() {
-            if !visited.insert(txid) {
-                continue;
-            }
-
-            for vout in 0..tx.output.len() as u32 {
-                let spent_outpoint = OutPoint::new(txid, vout);
-                let Some(child_txid) = self.spends.get(&spent_outpoint).copied() else {
-                    continue;
-                };
-                let Some(child_tx) = self.txs.get(&child_txid).cloned() else {
-                    continue;
-                };
-
-                descendants
-                    .entry(child_txid)
-                    .or_insert_with(|| child_tx.clone());
-                to_visit.push((child_txid, child_tx));
-            }
-        }
+        let descendants = self.descendants_of(rbf_txs.keys().copied());
 
         for txid in descendants.keys() {
             rbf_txs.remove(txid);
         }
 
-        // Find prev outputs of all txs in the set.
+        let descendant_fee = descendants
+            .values()
+            .map(|tx| self.fee_of(tx))
+            .sum::<Result<Amount, _>>()
+            .map_err(ExtractReplacementsError::PreviousOutputNotFound)?;
+
+        // Find prev outputs of the original txs.
         // Fail when a prev output is not found. We need to use the prevouts to determine fee for RBF!
         let prev_txouts = rbf_txs
             .values()
-            .chain(descendants.values())
             .flat_map(|tx| &tx.input)
             .map(|txin| txin.previous_output)
             .map(|op| -> Result<(OutPoint, TxOut), _> {
                 let txout = self
-                    .txs
-                    .get(&op.txid)
-                    .and_then(|tx| tx.output.get(op.vout as usize))
+                    .prev_txout(op)
                     .cloned()
                     .ok_or(ExtractReplacementsError::PreviousOutputNotFound(op))?;
                 Ok((op, txout))
             })
             .collect::<Result<HashMap<_, _>, _>>()?;
 
-        let descendant_fee = descendants
-            .values()
-            .map(|tx| {
-                let input_sum: Amount = tx
-                    .input
-                    .iter()
-                    .map(|txin| prev_txouts[&txin.previous_output].value)
-                    .sum();
-                let output_sum: Amount = tx.output.iter().map(|txout| txout.value).sum();
-                input_sum - output_sum
-            })
-            .sum::<Amount>();
-
         // Remove rbf txs (and their descendants) from canonical unspents.
         let to_remove_from_canonical_unspents = rbf_txs.keys().chain(descendants.keys());
-        for txid in to_remove_from_canonical_unspents {
-            if let Some(tx) = self.txs.remove(txid) {
-                self.statuses.remove(txid);
-                for txin in &tx.input {
-                    self.spends.remove(&txin.previous_output);
+        let extracted = to_remove_from_canonical_unspents
+            .filter_map(|&txid| self.remove_single(txid))
+            .collect::<Vec<_>>();
+
+        Ok(
+            RbfSet::new(rbf_txs.into_values(), descendant_fee, prev_txouts)
+                .expect("must not have missing prevouts")
+                .with_extracted(extracted),
+        )
+    }
+
+    /// Undo [`extract_replacements`](Self::extract_replacements) for an abandoned replacement.
+    ///
+    /// The extracted txs are inserted back with their statuses. Txs inserted since that
+    /// conflict with them (e.g. the replacement) are evicted and returned, see
+    /// [`insert_tx`](Self::insert_tx). Only the txs recorded in [`RbfSet::extracted`] are
+    /// restored, so this does nothing for an [`RbfSet`] created with [`RbfSet::new`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`InsertTxError::ConflictsWithConfirmed`] if an unconfirmed extracted tx
+    /// conflicts with a confirmed tx, e.g. because the replacement confirmed. Nothing is
+    /// restored then.
+    pub fn restore_replacements(
+        &mut self,
+        rbf_set: &RbfSet,
+    ) -> Result<Vec<Arc<Transaction>>, InsertTxError> {
+        for (tx, status) in rbf_set.extracted() {
+            if status.is_none() {
+                self.check_no_confirmed_conflict(tx)?;
+            }
+        }
+        Ok(rbf_set
+            .extracted()
+            .iter()
+            .flat_map(|(tx, status)| self.insert_tx_unchecked(tx.clone(), *status))
+            .collect())
+    }
+
+    /// Insert `tx` with `status` into the canonical view.
+    ///
+    /// Txs that spend any of the same outputs as `tx` are evicted along with their
+    /// descendants, as `tx` is now canonical. Returns the evicted txs. Inserting a tx that is
+    /// already contained only updates its status.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`InsertTxError::ConflictsWithConfirmed`] if `tx` is unconfirmed and conflicts
+    /// with a confirmed tx, which it cannot replace. A confirmed `tx` evicts confirmed conflicts
+    /// too, as in a reorg, see [`apply_block`](Self::apply_block).
+    pub fn insert_tx<T>(
+        &mut self,
+        tx: T,
+        status: Option<ConfirmationStatus>,
+    ) -> Result<Vec<Arc<Transaction>>, InsertTxError>
+    where
+        T: Into<Arc<Transaction>>,
+    {
+        let tx: Arc<Transaction> = tx.into();
+        if status.is_none() {
+            self.check_no_confirmed_conflict(&tx)?;
+        }
+        Ok(self.insert_tx_unchecked(tx, status))
+    }
+
+    /// Insert `tx`, evicting all of its conflicts whether confirmed or not.
+    fn insert_tx_unchecked(
+        &mut self,
+        tx: Arc<Transaction>,
+        status: Option<ConfirmationStatus>,
+    ) -> Vec<Arc<Transaction>> {
+        let txid = tx.compute_txid();
+        if self.txs.contains_key(&txid) {
+            self.update_status(txid, status);
+            return Vec::new();
+        }
+        let conflicts = self.direct_conflicts(&tx).collect::<Vec<_>>();
+        let evicted = conflicts
+            .into_iter()
+            .flat_map(|conflict| self.remove_tx(conflict))
+            .collect();
+        self.spends
+            .extend(tx.input.iter().map(|txin| (txin.previous_output, txid)));
+        self.txs.insert(txid, tx);
+        if let Some(status) = status {
+            self.statuses.insert(txid, status);
+        }
+        evicted
+    }
+
+    /// Txids of the txs spending any of the same outputs as `tx`, other than `tx` itself.
+    fn direct_conflicts<'a>(&'a self, tx: &'a Transaction) -> impl Iterator<Item = Txid> + 'a {
+        let txid = tx.compute_txid();
+        tx.input
+            .iter()
+            .filter_map(|txin| self.spends.get(&txin.previous_output).copied())
+            .filter(move |&conflict| conflict != txid)
+    }
+
+    /// Fail if `tx` conflicts with a confirmed tx.
+    fn check_no_confirmed_conflict(&self, tx: &Transaction) -> Result<(), InsertTxError> {
+        match self
+            .direct_conflicts(tx)
+            .find(|conflict| self.statuses.contains_key(conflict))
+        {
+            Some(conflict) => Err(InsertTxError::ConflictsWithConfirmed(conflict)),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the confirmation status of the tx of `txid`, e.g. when it confirms or is reorged out
+    /// of the chain.
+    ///
+    /// Returns `false` if the tx is not contained.
+    pub fn update_status(&mut self, txid: Txid, status: Option<ConfirmationStatus>) -> bool {
+        if !self.txs.contains_key(&txid) {
+            return false;
+        }
+        match status {
+            Some(status) => self.statuses.insert(txid, status),
+            None => self.statuses.remove(&txid),
+        };
+        true
+    }
+
+    /// Remove the tx of `txid` and all of its descendants, e.g. when it is evicted from the
+    /// mempool.
+    ///
+    /// Returns the removed txs, starting with the tx of `txid`. Returns nothing if the tx is not
+    /// contained.
+    pub fn remove_tx(&mut self, txid: Txid) -> Vec<Arc<Transaction>> {
+        let mut removed = Vec::new();
+        let mut to_remove = vec![txid];
+        while let Some(txid) = to_remove.pop() {
+            let Some((tx, _)) = self.remove_single(txid) else {
+                continue;
+            };
+            to_remove.extend(
+                (0..tx.output.len() as u32)
+                    .filter_map(|vout| self.spends.get(&OutPoint::new(txid, vout)).copied()),
+            );
+            removed.push(tx);
+        }
+        removed
+    }
+
+    /// Insert the txs of a block, all confirmed with `status`.
+    ///
+    /// Conflicting txs are evicted as in [`insert_tx`](Self::insert_tx), and returned. As the
+    /// block is authoritative, this also evicts confirmed conflicts.
+    pub fn apply_block<T>(
+        &mut self,
+        block_txs: impl IntoIterator<Item = T>,
+        status: ConfirmationStatus,
+    ) -> Vec<Arc<Transaction>>
+    where
+        T: Into<Arc<Transaction>>,
+    {
+        block_txs
+            .into_iter()
+            .flat_map(|tx| self.insert_tx_unchecked(tx.into(), Some(status)))
+            .collect()
+    }
+
+    /// Find the txs that `tx` conflicts with, i.e. the txs it would evict from the canonical
+    /// view.
+    ///
+    /// These are the unconfirmed txs spending any of the same outputs as `tx`, in the order of
+    /// the inputs of `tx`, and their descendants, by txid. Confirmed txs spending the same
+    /// outputs cannot be replaced, so they are reported separately, see
+    /// [`Conflicts::confirmed`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ConflictsError::PreviousOutputNotFound`] if the fee of a conflicting tx
+    /// cannot be computed.
+    pub fn conflicts_of(&self, tx: &Transaction) -> Result<Conflicts, ConflictsError> {
+        let mut direct = Vec::<(Txid, Arc<Transaction>)>::new();
+        let mut confirmed = Vec::<Txid>::new();
+        for conflict_txid in self.direct_conflicts(tx) {
+            if self.statuses.contains_key(&conflict_txid) {
+                if !confirmed.contains(&conflict_txid) {
+                    confirmed.push(conflict_txid);
+                }
+            } else if !direct.iter().any(|(txid, _)| *txid == conflict_txid) {
+                if let Some(conflict) = self.txs.get(&conflict_txid) {
+                    direct.push((conflict_txid, conflict.clone()));
                 }
             }
         }
+        let mut descendants = self
+            .descendants_of(direct.iter().map(|(txid, _)| *txid))
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        for (txid, _) in &direct {
+            descendants.remove(txid);
+        }
 
-        let prev_txouts: HashMap<_, _> = rbf_txs
+        let direct = direct
+            .into_iter()
+            .map(|(_, tx)| self.fee_of(&tx).map(|fee| (tx, fee)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ConflictsError::PreviousOutputNotFound)?;
+        let descendant_fee = descendants
             .values()
-            .flat_map(|tx| &tx.input)
-            .map(|txin| txin.previous_output)
-            .filter_map(|op| prev_txouts.get(&op).map(|txout| (op, txout.clone())))
-            .collect();
+            .map(|tx| self.fee_of(tx))
+            .sum::<Result<Amount, _>>()
+            .map_err(ConflictsError::PreviousOutputNotFound)?;
+        Ok(Conflicts {
+            direct,
+            descendants: descendants.into_values().collect(),
+            descendant_fee,
+            confirmed,
+        })
+    }
 
-        Ok(
-            RbfSet::new(rbf_txs.into_values(), descendant_fee, prev_txouts)
-                .expect("must not have missing prevouts"),
-        )
+    /// Extract the txs that `tx` conflicts with as an [`RbfSet`], so that `tx` can be checked
+    /// against, or rebuilt to follow, the replacement rules.
+    ///
+    /// This is [`extract_replacements`](Self::extract_replacements) with the direct conflicts
+    /// of [`conflicts_of`](Self::conflicts_of). The extraction can be undone with
+    /// [`restore_replacements`](Self::restore_replacements).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ConflictsError::ConflictsWithConfirmed`] if `tx` conflicts with a confirmed
+    /// tx, in which case nothing is extracted.
+    pub fn extract_conflicts(&mut self, tx: &Transaction) -> Result<RbfSet, ConflictsError> {
+        let conflicts = self.conflicts_of(tx)?;
+        if let Some(&txid) = conflicts.confirmed().first() {
+            return Err(ConflictsError::ConflictsWithConfirmed(txid));
+        }
+        self.extract_replacements(conflicts.direct_txids())
+            .map_err(ConflictsError::ExtractReplacements)
+    }
+
+    /// The output spent by `outpoint`, if its tx is contained.
+    fn prev_txout(&self, outpoint: OutPoint) -> Option<&TxOut> {
+        self.txs
+            .get(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize))
+    }
+
+    /// The fee of `tx`, or the first outpoint it spends that is not contained.
+    fn fee_of(&self, tx: &Transaction) -> Result<Amount, OutPoint> {
+        let mut input_sum = Amount::ZERO;
+        for txin in &tx.input {
+            let op = txin.previous_output;
+            input_sum += self.prev_txout(op).ok_or(op)?.value;
+        }
+        let output_sum: Amount = tx.output.iter().map(|txout| txout.value).sum();
+        Ok(input_sum - output_sum)
+    }
+
+    /// Find all descendants of the txs of `txids`, including any of `txids` that descend from
+    /// the others.
+    fn descendants_of(
+        &self,
+        txids: impl IntoIterator<Item = Txid>,
+    ) -> HashMap<Txid, Arc<Transaction>> {
+        let mut descendants = HashMap::<Txid, Arc<Transaction>>::new();
+        let mut visited = HashSet::<Txid>::new();
+        let mut to_visit = txids
+            .into_iter()
+            .filter_map(|txid| Some((txid, self.txs.get(&txid)?.clone())))
+            .collect::<Vec<_>>();
+        while let Some((txid, tx)) = to_visit.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+
+            for vout in 0..tx.output.len() as u32 {
+                let spent_outpoint = OutPoint::new(txid, vout);
+                let Some(child_txid) = self.spends.get(&spent_outpoint).copied() else {
+                    continue;
+                };
+                let Some(child_tx) = self.txs.get(&child_txid).cloned() else {
+                    continue;
+                };
+
+                descendants
+                    .entry(child_txid)
+                    .or_insert_with(|| child_tx.clone());
+                to_visit.push((child_txid, child_tx));
+            }
+        }
+        descendants
+    }
+
+    /// Remove only the tx of `txid`, keeping `spends` and `statuses` consistent.
+    fn remove_single(&mut self, txid: Txid) -> Option<TxWithStatus<Arc<Transaction>>> {
+        let tx = self.txs.remove(&txid)?;
+        let status = self.statuses.remove(&txid);
+        for txin in &tx.input {
+            if self.spends.get(&txin.previous_output) == Some(&txid) {
+                self.spends.remove(&txin.previous_output);
+            }
+        }
+        Some((tx, status))
     }
 
     /// Whether outpoint is a leaf (unspent).
@@ -269,6 +513,104 @@ impl CanonicalUnspents {
     }
 }
 
+/// Bitcoin Core's maximum number of txs a replacement may evict, counting the direct
+/// conflicts and their descendants.
+pub const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// Txs in [`CanonicalUnspents`] that a tx conflicts with, see
+/// [`CanonicalUnspents::conflicts_of`].
+#[derive(Debug, Clone)]
+pub struct Conflicts {
+    /// Unconfirmed txs spending any of the same outputs, with their fees.
+    direct: Vec<(Arc<Transaction>, Amount)>,
+    /// Descendants of the direct conflicts.
+    descendants: Vec<Arc<Transaction>>,
+    /// Sum of fees of the descendants.
+    descendant_fee: Amount,
+    /// Confirmed txs spending any of the same outputs.
+    confirmed: Vec<Txid>,
+}
+
+impl Conflicts {
+    /// Whether there are no unconfirmed conflicts to evict.
+    ///
+    /// This does not account for [`confirmed`](Self::confirmed) conflicts.
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty()
+    }
+
+    /// Number of txs that would be evicted, direct conflicts and descendants.
+    pub fn len(&self) -> usize {
+        self.direct.len() + self.descendants.len()
+    }
+
+    /// Unconfirmed txs spending any of the same outputs as the tx.
+    pub fn direct(&self) -> impl ExactSizeIterator<Item = &Arc<Transaction>> + '_ {
+        self.direct.iter().map(|(tx, _)| tx)
+    }
+
+    /// Txids of the direct conflicts.
+    pub fn direct_txids(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
+        self.direct().map(|tx| tx.compute_txid())
+    }
+
+    /// Descendants of the direct conflicts, which are evicted along with them.
+    pub fn descendants(&self) -> &[Arc<Transaction>] {
+        &self.descendants
+    }
+
+    /// Txids of the confirmed txs spending any of the same outputs as the tx.
+    ///
+    /// The tx cannot replace these, so it is invalid unless they are reorged out.
+    pub fn confirmed(&self) -> &[Txid] {
+        &self.confirmed
+    }
+
+    /// Txids of all txs that would be evicted, direct conflicts first.
+    pub fn txids(&self) -> impl Iterator<Item = Txid> + '_ {
+        self.direct_txids()
+            .chain(self.descendants.iter().map(|tx| tx.compute_txid()))
+    }
+
+    /// Total fee of all txs that would be evicted.
+    pub fn fee(&self) -> Amount {
+        self.direct.iter().map(|(_, fee)| *fee).sum::<Amount>() + self.descendant_fee
+    }
+
+    /// Total weight of all txs that would be evicted.
+    pub fn weight(&self) -> Weight {
+        self.direct()
+            .chain(&self.descendants)
+            .map(|tx| tx.weight())
+            .sum()
+    }
+
+    /// RBF parameters for replacing the conflicts.
+    ///
+    /// Use [`check_replacement`](Self::check_replacement) to check whether the tx may replace
+    /// them.
+    pub fn rbf_params(&self) -> RbfParams {
+        RbfParams::new(
+            self.direct.iter().map(|(tx, fee)| (tx.as_ref(), *fee)),
+            self.descendant_fee,
+        )
+    }
+
+    /// Check whether a replacement tx with `fee` and `weight` may replace the conflicts.
+    ///
+    /// On top of the fee checks of [`RbfParams::check_replacement`], the replacement must not
+    /// conflict with confirmed txs, nor evict more than [`MAX_REPLACEMENT_CANDIDATES`] txs.
+    pub fn check_replacement(&self, fee: Amount, weight: Weight) -> Result<(), ReplacementError> {
+        if let Some(&txid) = self.confirmed.first() {
+            return Err(ReplacementError::ConflictsWithConfirmed(txid));
+        }
+        if self.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err(ReplacementError::TooManyEvictions { count: self.len() });
+        }
+        self.rbf_params().check_replacement(fee, weight)
+    }
+}
+
 /// Canonical unspents error
 #[derive(Debug)]
 pub enum GetForeignUnspentError {
@@ -298,6 +640,53 @@ impl fmt::Display for GetForeignUnspentError {
 #[cfg(feature = "std")]
 impl std::error::Error for GetForeignUnspentError {}
 
+/// Error when finding or extracting the conflicts of a tx, see
+/// [`CanonicalUnspents::conflicts_of`] and [`CanonicalUnspents::extract_conflicts`].
+#[derive(Debug)]
+pub enum ConflictsError {
+    /// Previous output not found for an input of a conflicting tx, so its fee is unknown.
+    PreviousOutputNotFound(OutPoint),
+    /// The tx conflicts with the confirmed tx of this txid, which cannot be replaced.
+    ConflictsWithConfirmed(Txid),
+    /// Extracting the conflicts failed.
+    ExtractReplacements(ExtractReplacementsError),
+}
+
+impl fmt::Display for ConflictsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreviousOutputNotFound(op) => write!(f, "previous output not found: {op}"),
+            Self::ConflictsWithConfirmed(txid) => {
+                write!(f, "conflicts with confirmed transaction: {txid}")
+            }
+            Self::ExtractReplacements(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConflictsError {}
+
+/// Error when inserting a tx into [`CanonicalUnspents`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertTxError {
+    /// An unconfirmed tx conflicts with the confirmed tx of this txid.
+    ConflictsWithConfirmed(Txid),
+}
+
+impl fmt::Display for InsertTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConflictsWithConfirmed(txid) => {
+                write!(f, "conflicts with confirmed transaction: {txid}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InsertTxError {}
+
 /// Error when attempting to do [`extract_replacements`](CanonicalUnspents::extract_replacements).
 #[derive(Debug)]
 pub enum ExtractReplacementsError {
@@ -326,6 +715,7 @@ impl std::error::Error for ExtractReplacementsError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ReplacementError;
     use bitcoin::{absolute, transaction, Amount, ScriptBuf, TxIn};
 
     fn funding_tx(output_values: &[u64]) -> Transaction {
@@ -418,4 +808,242 @@ mod tests {
         assert!(!txids.contains(&child_txid));
         assert_eq!(rbf_set.selector_rbf_params().to_cs_replace().fee, 3_000);
     }
+
+    /// Keeps `spends` and `statuses` consistent across incremental updates.
+    #[test]
+    fn test_incremental_updates() {
+        let funding = funding_tx(&[50_000, 20_000]);
+        let parent = tx_spending(&[prevout(&funding, 0)], &[49_000]);
+        let child = tx_spending(&[prevout(&parent, 0)], &[47_000]);
+        let conflict = tx_spending(&[prevout(&funding, 0)], &[48_000]);
+        let txids =
+            |txs: Vec<Arc<Transaction>>| txs.iter().map(|tx| tx.compute_txid()).collect::<Vec<_>>();
+        let mut canonical_unspents = CanonicalUnspents::new(unconfirmed_txs(vec![funding.clone()]));
+        assert_eq!(
+            canonical_unspents.insert_tx(parent.clone(), None),
+            Ok(vec![])
+        );
+        assert_eq!(
+            canonical_unspents.insert_tx(child.clone(), None),
+            Ok(vec![])
+        );
+        assert!(!canonical_unspents.is_unspent(prevout(&parent, 0)));
+        assert!(canonical_unspents.is_unspent(prevout(&child, 0)));
+
+        // A conflicting tx evicts the parent and its child.
+        let evicted = canonical_unspents
+            .insert_tx(conflict.clone(), None)
+            .expect("conflicts are unconfirmed");
+        assert_eq!(
+            txids(evicted),
+            vec![parent.compute_txid(), child.compute_txid()]
+        );
+        assert!(!canonical_unspents.is_unspent(prevout(&child, 0)));
+        assert!(canonical_unspents.is_unspent(prevout(&conflict, 0)));
+
+        // Confirming the conflict in a block.
+        let status = ConfirmationStatus::new(100, None).expect("valid height");
+        assert!(canonical_unspents
+            .apply_block([conflict.clone()], status)
+            .is_empty());
+        // An unconfirmed tx cannot replace a confirmed one.
+        assert_eq!(
+            canonical_unspents.insert_tx(parent.clone(), None),
+            Err(InsertTxError::ConflictsWithConfirmed(
+                conflict.compute_txid()
+            ))
+        );
+        assert!(canonical_unspents.is_unspent(prevout(&conflict, 0)));
+        let input = canonical_unspents
+            .try_get_foreign_unspent(
+                prevout(&conflict, 0),
+                Sequence::MAX,
+                psbt::Input {
+                    witness_utxo: Some(conflict.output[0].clone()),
+                    ..Default::default()
+                },
+                0,
+                false,
+                None,
+            )
+            .expect("must be unspent");
+        assert_eq!(
+            input.status().map(|s| s.height.to_consensus_u32()),
+            Some(100)
+        );
+        assert!(canonical_unspents.update_status(conflict.compute_txid(), None));
+        assert!(!canonical_unspents.update_status(parent.compute_txid(), None));
+
+        let removed = canonical_unspents.remove_tx(conflict.compute_txid());
+        assert_eq!(txids(removed), vec![conflict.compute_txid()]);
+        assert!(canonical_unspents.is_unspent(prevout(&funding, 0)));
+        assert!(canonical_unspents
+            .remove_tx(conflict.compute_txid())
+            .is_empty());
+    }
+
+    /// Restores the extracted txs when a replacement is abandoned.
+    #[test]
+    fn test_restore_replacements() {
+        let funding = funding_tx(&[50_000]);
+        let parent = tx_spending(&[prevout(&funding, 0)], &[49_000]);
+        let child = tx_spending(&[prevout(&parent, 0)], &[47_000]);
+        let replacement = tx_spending(&[prevout(&funding, 0)], &[45_000]);
+        let mut canonical_unspents = CanonicalUnspents::new(unconfirmed_txs(vec![
+            funding,
+            parent.clone(),
+            child.clone(),
+        ]));
+
+        let rbf_set = canonical_unspents
+            .extract_replacements([parent.compute_txid()])
+            .expect("replacement set should extract");
+        assert_eq!(rbf_set.extracted().len(), 2);
+        assert!(!canonical_unspents.is_unspent(prevout(&child, 0)));
+        canonical_unspents
+            .insert_tx(replacement.clone(), None)
+            .expect("originals are extracted");
+
+        // Nothing is restored once the replacement is confirmed.
+        let status = ConfirmationStatus::new(100, None).expect("valid height");
+        let mut confirmed = canonical_unspents.clone();
+        assert!(confirmed.update_status(replacement.compute_txid(), Some(status)));
+        assert_eq!(
+            confirmed.restore_replacements(&rbf_set),
+            Err(InsertTxError::ConflictsWithConfirmed(
+                replacement.compute_txid()
+            ))
+        );
+        assert!(confirmed.is_unspent(prevout(&replacement, 0)));
+
+        let evicted = canonical_unspents
+            .restore_replacements(&rbf_set)
+            .expect("replacement is unconfirmed");
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].compute_txid(), replacement.compute_txid());
+        assert!(canonical_unspents.is_unspent(prevout(&child, 0)));
+        assert!(!canonical_unspents.is_unspent(prevout(&parent, 0)));
+    }
+
+    /// Finds the conflict set of a tx and checks a replacement against it.
+    #[test]
+    fn test_conflicts_of() {
+        let funding = funding_tx(&[50_000, 20_000]);
+        let parent = tx_spending(&[prevout(&funding, 0)], &[49_000]);
+        let child = tx_spending(&[prevout(&parent, 0)], &[47_000]);
+        let mut canonical_unspents = CanonicalUnspents::new(unconfirmed_txs(vec![
+            funding.clone(),
+            parent.clone(),
+            child.clone(),
+        ]));
+
+        let unrelated = tx_spending(&[prevout(&funding, 1)], &[19_000]);
+        assert!(canonical_unspents
+            .conflicts_of(&unrelated)
+            .expect("must find conflicts")
+            .is_empty());
+
+        let replacement = tx_spending(&[prevout(&funding, 0)], &[46_000]);
+        let conflicts = canonical_unspents
+            .conflicts_of(&replacement)
+            .expect("must find conflicts");
+        assert_eq!(
+            conflicts.txids().collect::<Vec<_>>(),
+            vec![parent.compute_txid(), child.compute_txid()]
+        );
+        assert_eq!(conflicts.fee(), Amount::from_sat(3_000));
+        assert_eq!(conflicts.weight(), parent.weight() + child.weight());
+
+        // The replacement pays 4_000, but needs 3_000 plus its own vsize at 1 sat/vb.
+        let weight = replacement.weight();
+        assert_eq!(
+            conflicts.check_replacement(Amount::from_sat(4_000), weight),
+            Ok(())
+        );
+        let min_fee = Amount::from_sat(3_000 + weight.to_vbytes_ceil());
+        assert_eq!(
+            conflicts.check_replacement(Amount::from_sat(3_000), weight),
+            Err(ReplacementError::InsufficientFee {
+                fee: Amount::from_sat(3_000),
+                min_fee,
+            })
+        );
+
+        // Confirmed conflicts are reported separately and cannot be replaced.
+        let mut confirmed = canonical_unspents.clone();
+        let status = ConfirmationStatus::new(100, None).expect("valid height");
+        assert!(confirmed.update_status(parent.compute_txid(), Some(status)));
+        let conflicts = confirmed
+            .conflicts_of(&replacement)
+            .expect("must find conflicts");
+        assert!(conflicts.is_empty());
+        assert_eq!(conflicts.confirmed(), &[parent.compute_txid()]);
+        assert_eq!(
+            conflicts.check_replacement(Amount::from_sat(4_000), weight),
+            Err(ReplacementError::ConflictsWithConfirmed(
+                parent.compute_txid()
+            ))
+        );
+        assert!(matches!(
+            confirmed.extract_conflicts(&replacement),
+            Err(ConflictsError::ConflictsWithConfirmed(txid)) if txid == parent.compute_txid()
+        ));
+        assert!(!confirmed.is_unspent(prevout(&parent, 0)));
+
+        let rbf_set = canonical_unspents
+            .extract_conflicts(&replacement)
+            .expect("must extract conflicts");
+        assert_eq!(
+            rbf_set.txids().collect::<Vec<_>>(),
+            vec![parent.compute_txid()]
+        );
+        assert!(canonical_unspents
+            .conflicts_of(&replacement)
+            .expect("must find conflicts")
+            .is_empty());
+    }
+
+    /// Orders descendants by txid and limits the number of evicted txs.
+    #[test]
+    fn test_conflicts_of_order_and_limit() {
+        let funding = funding_tx(&[1_000_000]);
+        let values = (0..MAX_REPLACEMENT_CANDIDATES as u64)
+            .map(|i| 9_000 - i)
+            .collect::<Vec<_>>();
+        let parent = tx_spending(&[prevout(&funding, 0)], &values);
+        let children = (0..values.len() as u32)
+            .map(|vout| tx_spending(&[prevout(&parent, vout)], &[1_000]))
+            .collect::<Vec<_>>();
+        let canonical_unspents = CanonicalUnspents::new(unconfirmed_txs(
+            [funding.clone(), parent.clone()]
+                .into_iter()
+                .chain(children.clone())
+                .collect(),
+        ));
+
+        let replacement = tx_spending(&[prevout(&funding, 0)], &[500_000]);
+        let conflicts = canonical_unspents
+            .conflicts_of(&replacement)
+            .expect("must find conflicts");
+        let mut child_txids = children
+            .iter()
+            .map(Transaction::compute_txid)
+            .collect::<Vec<_>>();
+        child_txids.sort();
+        let descendant_txids = conflicts
+            .descendants()
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<_>>();
+        assert_eq!(descendant_txids, child_txids);
+
+        // The parent and its children are one more than the limit.
+        assert_eq!(conflicts.len(), MAX_REPLACEMENT_CANDIDATES + 1);
+        assert_eq!(
+            conflicts.check_replacement(Amount::from_sat(500_000), replacement.weight()),
+            Err(ReplacementError::TooManyEvictions {
+                count: MAX_REPLACEMENT_CANDIDATES + 1
+            })
+        );
+    }
 }
@@ -0,0 +1,535 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_coin_select::metrics::{Changeless, LowestFee};
+use bitcoin::{Amount, FeeRate, OutPoint, Psbt, Weight};
+use miniscript::bitcoin;
+
+use crate::collections::BTreeSet;
+use crate::input::{COMMON_FIELDS_WEIGHT, TXIN_BASE_WEIGHT};
+use crate::{
+    CannotMeetTarget, ChangeScript, FeeRateExt, Input, InputCandidates, Output, ScriptSource,
+    Selection, Selector, SelectorError, SelectorParams,
+};
+
+/// Parameters for planning our part of an equal-amount collaborative transaction.
+#[derive(Debug)]
+pub struct CoinjoinParams {
+    /// Value of each equal output.
+    pub denomination: Amount,
+
+    /// Feerate of the collaborative transaction.
+    pub feerate: FeeRate,
+
+    /// Number of participants sharing the common transaction fields. Must be at least 1.
+    pub participants: usize,
+
+    /// Scripts of our equal outputs. The planner creates at most this many equal outputs.
+    pub equal_output_scripts: Vec<ScriptSource>,
+
+    /// Minimum number of equal outputs to create. Must be at least 1.
+    pub min_equal_outputs: usize,
+
+    /// Source of the change output script.
+    pub change_script: ChangeScript,
+
+    /// Leftover value below this (or the dust threshold, if higher) is given up as fee instead
+    /// of creating a change output.
+    pub changeless_tolerance: Amount,
+
+    /// Maximum branch and bound rounds per attempted number of equal outputs.
+    pub max_rounds: usize,
+}
+
+impl CoinjoinParams {
+    /// With default params.
+    pub fn new(
+        denomination: Amount,
+        feerate: FeeRate,
+        participants: usize,
+        equal_output_scripts: Vec<ScriptSource>,
+        change_script: ChangeScript,
+    ) -> Self {
+        Self {
+            denomination,
+            feerate,
+            participants,
+            equal_output_scripts,
+            min_equal_outputs: 1,
+            change_script,
+            changeless_tolerance: Amount::ZERO,
+            max_rounds: 100_000,
+        }
+    }
+
+    /// Our share of the fee when contributing `inputs` and `outputs`.
+    ///
+    /// We pay for the weight of our own inputs and outputs, and an equal share of the common
+    /// transaction fields.
+    pub fn fee_share(&self, inputs: &[Input], outputs: &[Output]) -> Amount {
+        let input_weight = inputs
+            .iter()
+            .map(|input| TXIN_BASE_WEIGHT + input.satisfaction_weight())
+            .sum::<u64>();
+        let output_weight = outputs
+            .iter()
+            .map(|output| output.txout().weight().to_wu())
+            .sum::<u64>();
+        let common_weight = COMMON_FIELDS_WEIGHT.div_ceil(self.participants.max(1) as u64);
+        self.feerate
+            .fee_wu(Weight::from_wu(
+                input_weight + output_weight + common_weight,
+            ))
+            .expect("fee must not overflow")
+    }
+}
+
+/// Our part of an equal-amount collaborative transaction.
+///
+/// Create a PSBT from the [`Selection`] and merge it with the other participants' PSBTs using
+/// [`merge_coinjoin_psbts`].
+#[derive(Debug, Clone)]
+pub struct CoinjoinFragment {
+    selection: Selection,
+    equal_outputs: usize,
+    has_change: bool,
+}
+
+impl CoinjoinFragment {
+    /// Inputs and outputs of this fragment.
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    /// Into the [`Selection`] of this fragment.
+    pub fn into_selection(self) -> Selection {
+        self.selection
+    }
+
+    /// Number of equal outputs.
+    pub fn equal_output_count(&self) -> usize {
+        self.equal_outputs
+    }
+
+    /// The change output, if any.
+    pub fn change(&self) -> Option<&Output> {
+        self.has_change
+            .then(|| self.selection.outputs().last())
+            .flatten()
+    }
+
+    /// Fee paid by this fragment.
+    ///
+    /// This is [`CoinjoinParams::fee_share`] plus any leftover given up to avoid change.
+    pub fn fee(&self) -> Amount {
+        let input_value = self
+            .selection
+            .inputs()
+            .iter()
+            .map(|input| input.prev_txout().value)
+            .sum::<Amount>();
+        let output_value = self
+            .selection
+            .outputs()
+            .iter()
+            .map(|output| output.value)
+            .sum::<Amount>();
+        input_value - output_value
+    }
+}
+
+/// Error planning a [`CoinjoinFragment`].
+#[derive(Debug)]
+pub enum CoinjoinError {
+    /// The params are invalid, e.g. there are fewer equal output scripts than
+    /// `min_equal_outputs`.
+    InvalidParams,
+    /// Selector error.
+    Selector(SelectorError),
+    /// The candidates cannot fund `min_equal_outputs` equal outputs.
+    CannotMeetTarget(CannotMeetTarget),
+}
+
+impl fmt::Display for CoinjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParams => write!(f, "invalid coinjoin params"),
+            Self::Selector(e) => fmt::Display::fmt(e, f),
+            Self::CannotMeetTarget(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CoinjoinError {}
+
+impl InputCandidates {
+    /// Plan our fragment of an equal-amount collaborative transaction.
+    ///
+    /// Tries to fund as many equal outputs as possible, from the number of
+    /// [`equal_output_scripts`] down to [`min_equal_outputs`]. As a unique change amount links
+    /// the change to our inputs, a changeless input set is preferred over one with change, even
+    /// if it funds fewer equal outputs. If all solutions have change, the one with the most equal
+    /// outputs is returned.
+    ///
+    /// Our fee is the [`fee_share`] of the selected inputs and outputs, so the change is larger
+    /// than if we paid for the whole transaction.
+    ///
+    /// [`equal_output_scripts`]: CoinjoinParams::equal_output_scripts
+    /// [`min_equal_outputs`]: CoinjoinParams::min_equal_outputs
+    /// [`fee_share`]: CoinjoinParams::fee_share
+    pub fn plan_coinjoin(
+        &self,
+        params: &CoinjoinParams,
+    ) -> Result<CoinjoinFragment, CoinjoinError> {
+        if params.participants == 0
+            || params.min_equal_outputs == 0
+            || params.min_equal_outputs > params.equal_output_scripts.len()
+        {
+            return Err(CoinjoinError::InvalidParams);
+        }
+        let change_source = params.change_script.source();
+        let change_satisfaction_weight = params
+            .change_script
+            .satisfaction_weight()
+            .map_err(CoinjoinError::Selector)?;
+
+        let mut with_change = Option::<CoinjoinFragment>::None;
+        // Why the last (and smallest) number of equal outputs cannot be funded.
+        let mut cannot_meet_target = Option::<CannotMeetTarget>::None;
+        for count in (params.min_equal_outputs..=params.equal_output_scripts.len()).rev() {
+            let equal_outputs = params.equal_output_scripts[..count]
+                .iter()
+                .map(|script| Output::from((script.clone(), params.denomination)))
+                .collect::<Vec<_>>();
+            let selector_params = SelectorParams {
+                change_min_value: Some(params.changeless_tolerance),
+                ..SelectorParams::new(
+                    params.feerate,
+                    equal_outputs.clone(),
+                    ChangeScript::from_script(change_source.script(), change_satisfaction_weight),
+                )
+            };
+            let mut selector = match Selector::new(self, selector_params) {
+                Ok(selector) => selector,
+                Err(SelectorError::CannotMeetTarget(e)) => {
+                    cannot_meet_target = Some(e);
+                    continue;
+                }
+                Err(e) => return Err(CoinjoinError::Selector(e)),
+            };
+            let target = selector.target();
+            let change_policy = selector.cs_change_policy();
+
+            let mut changeless = selector.clone();
+            if changeless
+                .inner_mut()
+                .run_bnb(
+                    Changeless {
+                        target,
+                        change_policy,
+                    },
+                    params.max_rounds,
+                )
+                .is_ok()
+            {
+                // A selection with outputs beyond the equal outputs is not changeless, and is
+                // considered below with the other selections with change.
+                let changeless = changeless
+                    .try_finalize()
+                    .filter(|selection| selection.outputs().len() == count);
+                if let Some(selection) = changeless {
+                    return Ok(CoinjoinFragment {
+                        selection,
+                        equal_outputs: count,
+                        has_change: false,
+                    });
+                }
+            }
+
+            if with_change.is_some() {
+                continue;
+            }
+            let lowest_fee = LowestFee {
+                target,
+                long_term_feerate: params.feerate.into_cs_feerate(),
+                change_policy,
+            };
+            if selector
+                .inner_mut()
+                .run_bnb(lowest_fee, params.max_rounds)
+                .is_err()
+                && selector.select_until_target_met().is_err()
+            {
+                cannot_meet_target = Some(selector.cannot_meet_target());
+                continue;
+            }
+            let Some(selection) = selector.try_finalize() else {
+                cannot_meet_target = Some(selector.cannot_meet_target());
+                continue;
+            };
+            let inputs = selection.inputs().to_vec();
+            let mut outputs = equal_outputs;
+            let has_change = selection.outputs().len() > count;
+            if has_change {
+                // The selector charges us for the whole transaction, so recompute the change
+                // with our fee share.
+                outputs.push(Output::from((change_source.clone(), Amount::ZERO)));
+                let fee = params.fee_share(&inputs, &outputs);
+                let input_value = inputs
+                    .iter()
+                    .map(|input| input.prev_txout().value)
+                    .sum::<Amount>();
+                outputs.last_mut().expect("just pushed").value =
+                    input_value - params.denomination * count as u64 - fee;
+            }
+            with_change = Some(CoinjoinFragment {
+                selection: Selection::new(inputs, outputs),
+                equal_outputs: count,
+                has_change,
+            });
+        }
+        with_change.ok_or_else(|| {
+            CoinjoinError::CannotMeetTarget(
+                cannot_meet_target.expect("every attempt without a fragment records the reason"),
+            )
+        })
+    }
+}
+
+/// Error merging coinjoin PSBTs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinjoinMergeError {
+    /// No PSBTs to merge.
+    Empty,
+    /// The PSBTs do not agree on the transaction version.
+    VersionMismatch,
+    /// The PSBTs do not agree on the transaction locktime.
+    LockTimeMismatch,
+    /// The outpoint is spent by more than one input.
+    DuplicateInput(OutPoint),
+}
+
+impl fmt::Display for CoinjoinMergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "no psbts to merge"),
+            Self::VersionMismatch => write!(f, "psbts have different transaction versions"),
+            Self::LockTimeMismatch => write!(f, "psbts have different transaction locktimes"),
+            Self::DuplicateInput(op) => write!(f, "outpoint {op} is spent more than once"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CoinjoinMergeError {}
+
+/// Merge the unsigned PSBTs of all participants into the collaborative transaction.
+///
+/// Inputs are sorted by outpoint and outputs by value and script pubkey, so that the order does
+/// not reveal which participant contributed what. Global fields of the PSBTs are not merged.
+pub fn merge_coinjoin_psbts(
+    psbts: impl IntoIterator<Item = Psbt>,
+) -> Result<Psbt, CoinjoinMergeError> {
+    let mut psbts = psbts.into_iter();
+    let mut merged = psbts.next().ok_or(CoinjoinMergeError::Empty)?;
+    for psbt in psbts {
+        if psbt.unsigned_tx.version != merged.unsigned_tx.version {
+            return Err(CoinjoinMergeError::VersionMismatch);
+        }
+        if psbt.unsigned_tx.lock_time != merged.unsigned_tx.lock_time {
+            return Err(CoinjoinMergeError::LockTimeMismatch);
+        }
+        merged.unsigned_tx.input.extend(psbt.unsigned_tx.input);
+        merged.inputs.extend(psbt.inputs);
+        merged.unsigned_tx.output.extend(psbt.unsigned_tx.output);
+        merged.outputs.extend(psbt.outputs);
+    }
+
+    let mut seen = BTreeSet::new();
+    if let Some(txin) = merged
+        .unsigned_tx
+        .input
+        .iter()
+        .find(|txin| !seen.insert(txin.previous_output))
+    {
+        return Err(CoinjoinMergeError::DuplicateInput(txin.previous_output));
+    }
+
+    let mut inputs = core::mem::take(&mut merged.unsigned_tx.input)
+        .into_iter()
+        .zip(core::mem::take(&mut merged.inputs))
+        .collect::<Vec<_>>();
+    inputs.sort_by_key(|(txin, _)| txin.previous_output);
+    (merged.unsigned_tx.input, merged.inputs) = inputs.into_iter().unzip();
+
+    let mut outputs = core::mem::take(&mut merged.unsigned_tx.output)
+        .into_iter()
+        .zip(core::mem::take(&mut merged.outputs))
+        .collect::<Vec<_>>();
+    outputs.sort_by(|(a, _), (b, _)| (a.value, &a.script_pubkey).cmp(&(b.value, &b.script_pubkey)));
+    (merged.unsigned_tx.output, merged.outputs) = outputs.into_iter().unzip();
+
+    Ok(merged)
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, input, parse_descriptor, TR_XPRV, TR_XPRV_2};
+    use crate::PsbtParams;
+    use miniscript::{plan::Assets, Descriptor, DescriptorPublicKey};
+
+    struct Wallet {
+        descriptor: Descriptor<DescriptorPublicKey>,
+        assets: Assets,
+    }
+
+    impl Wallet {
+        fn new(descriptor: &str) -> anyhow::Result<Self> {
+            let (descriptor, keymap) = parse_descriptor(descriptor)?;
+            Ok(Self {
+                descriptor,
+                assets: assets(&keymap),
+            })
+        }
+
+        fn candidates(&self, values: &[u64]) -> anyhow::Result<InputCandidates> {
+            let mut inputs = Vec::new();
+            for (i, &value) in values.iter().enumerate() {
+                let desc = self.descriptor.at_derivation_index(i as u32)?;
+                let value = Amount::from_sat(value);
+                inputs.push(input(&desc, &self.assets, value, i as u32, None)?);
+            }
+            Ok(InputCandidates::new([], inputs))
+        }
+
+        fn params(&self, participants: usize) -> anyhow::Result<CoinjoinParams> {
+            let scripts = (100..103)
+                .map(|i| {
+                    Ok(ScriptSource::from_descriptor(
+                        self.descriptor.at_derivation_index(i)?,
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(CoinjoinParams::new(
+                Amount::from_sat(50_000),
+                FeeRate::from_sat_per_vb_u32(2),
+                participants,
+                scripts,
+                ChangeScript::from_descriptor(self.descriptor.at_derivation_index(200)?),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_prefers_changeless_over_more_outputs() -> anyhow::Result<()> {
+        let wallet = Wallet::new(TR_XPRV)?;
+        let candidates = wallet.candidates(&[100_400, 73_000, 12_345])?;
+        let fragment = candidates.plan_coinjoin(&wallet.params(5)?)?;
+
+        assert!(fragment.change().is_none());
+        assert_eq!(fragment.equal_output_count(), 2);
+        assert_eq!(fragment.selection().inputs().len(), 1);
+        assert_eq!(
+            fragment.selection().inputs()[0].prev_txout().value,
+            Amount::from_sat(100_400)
+        );
+        assert!(fragment
+            .selection()
+            .outputs()
+            .iter()
+            .all(|output| output.value == Amount::from_sat(50_000)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_pays_fee_share() -> anyhow::Result<()> {
+        let wallet = Wallet::new(TR_XPRV)?;
+        let candidates = wallet.candidates(&[130_000])?;
+        let params = wallet.params(5)?;
+        let fragment = candidates.plan_coinjoin(&params)?;
+
+        assert_eq!(fragment.equal_output_count(), 2);
+        let change = fragment.change().expect("must have change");
+        assert!(change.descriptor().is_some());
+        let fee = params.fee_share(
+            fragment.selection().inputs(),
+            fragment.selection().outputs(),
+        );
+        assert_eq!(fragment.fee(), fee);
+        assert_eq!(change.value, Amount::from_sat(30_000) - fee);
+
+        // Sharing the common fields with more participants lowers our fee.
+        let solo = CoinjoinParams {
+            participants: 1,
+            ..wallet.params(1)?
+        };
+        assert!(
+            solo.fee_share(
+                fragment.selection().inputs(),
+                fragment.selection().outputs()
+            ) > fee
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cannot_fund_min_outputs() -> anyhow::Result<()> {
+        let wallet = Wallet::new(TR_XPRV)?;
+        let candidates = wallet.candidates(&[40_000])?;
+        assert!(matches!(
+            candidates.plan_coinjoin(&wallet.params(5)?),
+            Err(CoinjoinError::CannotMeetTarget(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_fragments() -> anyhow::Result<()> {
+        let alice = Wallet::new(TR_XPRV)?;
+        let bob = Wallet::new(TR_XPRV_2)?;
+        let psbt_params = || PsbtParams {
+            mandate_full_tx_for_segwit_v0: false,
+            ..Default::default()
+        };
+        let alice_psbt = alice
+            .candidates(&[130_000])?
+            .plan_coinjoin(&alice.params(2)?)?
+            .into_selection()
+            .create_psbt(psbt_params())?;
+        let bob_psbt = bob
+            .candidates(&[100_400])?
+            .plan_coinjoin(&bob.params(2)?)?
+            .into_selection()
+            .create_psbt(psbt_params())?;
+
+        let alice_outpoint = alice_psbt.unsigned_tx.input[0].previous_output;
+        let merged = merge_coinjoin_psbts([alice_psbt.clone(), bob_psbt])?;
+        assert_eq!(merged.inputs.len(), 2);
+        assert_eq!(merged.outputs.len(), 5);
+        let outputs = &merged.unsigned_tx.output;
+        assert!(outputs.windows(2).all(|w| w[0].value <= w[1].value));
+        assert_eq!(
+            outputs
+                .iter()
+                .filter(|txout| txout.value == Amount::from_sat(50_000))
+                .count(),
+            4
+        );
+        for (txin, psbt_input) in merged.unsigned_tx.input.iter().zip(&merged.inputs) {
+            let prev_tx = psbt_input
+                .non_witness_utxo
+                .as_ref()
+                .expect("must have prev tx");
+            assert_eq!(prev_tx.compute_txid(), txin.previous_output.txid);
+        }
+
+        assert_eq!(
+            merge_coinjoin_psbts([alice_psbt.clone(), alice_psbt]),
+            Err(CoinjoinMergeError::DuplicateInput(alice_outpoint))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use core::fmt;
+
+use bitcoin::{Amount, FeeRate, Weight};
+use miniscript::bitcoin;
+
+/// Safety caps on the fee of a transaction.
+///
+/// Guards against paying an absurd fee, e.g. because of a miscalculated replacement or a wrong
+/// feerate. Every cap is optional and disabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FeeCaps {
+    /// Maximum absolute fee.
+    pub max_fee: Option<Amount>,
+    /// Maximum feerate.
+    pub max_feerate: Option<FeeRate>,
+    /// Maximum fee as a fraction of the amount sent, e.g. `0.1` for 10%.
+    pub max_fee_fraction_of_send: Option<f64>,
+}
+
+impl FeeCaps {
+    /// Check a transaction paying `fee` with the given `weight` and sending `send_amount`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`FeeCapError`] of the caps that are exceeded.
+    pub fn check(
+        &self,
+        fee: Amount,
+        weight: Weight,
+        send_amount: Amount,
+    ) -> Result<(), FeeCapError> {
+        if let Some(max_fee) = self.max_fee {
+            if fee > max_fee {
+                return Err(FeeCapError::Fee { fee, max_fee });
+            }
+        }
+        if let Some(max_feerate) = self.max_feerate {
+            let feerate = fee / weight;
+            if feerate > max_feerate {
+                return Err(FeeCapError::FeeRate {
+                    feerate,
+                    max_feerate,
+                });
+            }
+        }
+        if let Some(max_fraction) = self.max_fee_fraction_of_send {
+            if fee.to_sat() as f64 > send_amount.to_sat() as f64 * max_fraction {
+                return Err(FeeCapError::FractionOfSend {
+                    fee,
+                    send_amount,
+                    max_fraction,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error when a [`FeeCaps`] cap is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeCapError {
+    /// The fee exceeds [`FeeCaps::max_fee`].
+    Fee {
+        /// The fee.
+        fee: Amount,
+        /// The maximum fee.
+        max_fee: Amount,
+    },
+    /// The feerate exceeds [`FeeCaps::max_feerate`].
+    FeeRate {
+        /// The feerate.
+        feerate: FeeRate,
+        /// The maximum feerate.
+        max_feerate: FeeRate,
+    },
+    /// The fee exceeds [`FeeCaps::max_fee_fraction_of_send`] of the amount sent.
+    FractionOfSend {
+        /// The fee.
+        fee: Amount,
+        /// The amount sent.
+        send_amount: Amount,
+        /// The maximum fraction.
+        max_fraction: f64,
+    },
+}
+
+impl fmt::Display for FeeCapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fee { fee, max_fee } => write!(f, "fee {fee} exceeds maximum of {max_fee}"),
+            Self::FeeRate {
+                feerate,
+                max_feerate,
+            } => write!(f, "feerate {feerate:#} exceeds maximum of {max_feerate:#}"),
+            Self::FractionOfSend {
+                fee,
+                send_amount,
+                max_fraction,
+            } => write!(
+                f,
+                "fee {fee} exceeds {max_fraction} of the amount sent ({send_amount})"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeeCapError {}
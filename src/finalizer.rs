@@ -1,6 +1,22 @@
-use crate::collections::{BTreeMap, HashMap};
-use bitcoin::{OutPoint, Psbt, Witness};
-use miniscript::{bitcoin, plan::Plan, psbt::PsbtInputSatisfier};
+use crate::collections::{BTreeMap, HashMap, HashSet};
+use alloc::vec::Vec;
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+use bitcoin::{
+    absolute,
+    psbt::{self, PsbtSighashType},
+    relative,
+    secp256k1::Secp256k1,
+    sighash::{EcdsaSighashType, TapSighashType},
+    transaction, OutPoint, Psbt, TapLeafHash, Witness,
+};
+use core::fmt;
+use miniscript::{
+    bitcoin,
+    descriptor::DefiniteDescriptorKey,
+    miniscript::satisfy::{Placeholder, SchnorrSigType},
+    plan::Plan,
+    psbt::{PsbtExt, PsbtInputSatisfier},
+};
 
 /// Type used to finalize inputs of a Partially Signed Bitcoin Transaction (PSBT) using
 /// a collection of pre-computed spending plans.
@@ -21,6 +37,13 @@ use miniscript::{bitcoin, plan::Plan, psbt::PsbtInputSatisfier};
 /// from non-essential fields of the PSBT inputs and outputs, ensuring that only the necessary
 /// information remains for transaction extraction.
 ///
+/// Inputs without a plan can be registered with [`with_foreign_inputs`], in which case they are
+/// finalized with miniscript's generic PSBT finalizer.
+///
+/// Only signatures of the sighash type requested for each input are used, as registered with
+/// [`with_sighash_types`], or of the default sighash type otherwise. The `sighash_type` field of
+/// the PSBT input is ignored, as whoever returns the signed PSBT could have changed it.
+///
 /// # Example
 ///
 /// ```rust,no_run
@@ -52,9 +75,13 @@ use miniscript::{bitcoin, plan::Plan, psbt::PsbtInputSatisfier};
 /// [`Transaction`]: bitcoin::Transaction
 /// [`finalize_input`]: Finalizer::finalize_input
 /// [`finalize`]: Finalizer::finalize
+/// [`with_foreign_inputs`]: Finalizer::with_foreign_inputs
+/// [`with_sighash_types`]: Finalizer::with_sighash_types
 #[derive(Debug)]
 pub struct Finalizer {
     pub(crate) plans: HashMap<OutPoint, Plan>,
+    pub(crate) foreign_inputs: HashSet<OutPoint>,
+    pub(crate) sighash_types: BTreeMap<OutPoint, PsbtSighashType>,
 }
 
 impl Finalizer {
@@ -62,16 +89,50 @@ impl Finalizer {
     pub fn new(plans: impl IntoIterator<Item = (OutPoint, Plan)>) -> Self {
         Self {
             plans: plans.into_iter().collect(),
+            foreign_inputs: HashSet::new(),
+            sighash_types: BTreeMap::new(),
         }
     }
 
+    /// Only use signatures of the given sighash type for the input spending each outpoint.
+    ///
+    /// This is typically the map passed as [`PsbtParams::sighash_types`]. Inputs without an entry
+    /// only use signatures of the default sighash type.
+    ///
+    /// [`PsbtParams::sighash_types`]: crate::PsbtParams::sighash_types
+    pub fn with_sighash_types(
+        mut self,
+        sighash_types: impl IntoIterator<Item = (OutPoint, PsbtSighashType)>,
+    ) -> Self {
+        self.sighash_types.extend(sighash_types);
+        self
+    }
+
+    /// Finalize the inputs spending `outpoints` without a [`Plan`].
+    ///
+    /// These are typically inputs created with [`Input::from_psbt_input`], and are finalized with
+    /// miniscript's generic PSBT finalizer using the scripts and signatures found in the PSBT
+    /// input. Inputs that are already finalized are kept as is.
+    ///
+    /// [`Input::from_psbt_input`]: crate::Input::from_psbt_input
+    pub fn with_foreign_inputs(mut self, outpoints: impl IntoIterator<Item = OutPoint>) -> Self {
+        self.foreign_inputs.extend(outpoints);
+        self
+    }
+
     /// Finalize a PSBT input and return whether finalization was successful or input was already
     /// finalized.
     ///
+    /// Only signatures of the sighash type requested for the input (or the default sighash type
+    /// if none is requested) are used to satisfy the input, see
+    /// [`with_sighash_types`](Self::with_sighash_types).
+    ///
+    /// See [`finalize_input_status`](Self::finalize_input_status) for a detailed status.
+    ///
     /// # Errors
     ///
-    /// If the spending plan associated with the PSBT input cannot be satisfied,
-    /// then a [`miniscript::Error`] is returned.
+    /// If the spending plan associated with the PSBT input cannot be satisfied, or a foreign
+    /// input cannot be finalized, then a [`FinalizeError`] is returned.
     ///
     /// # Panics
     ///
@@ -80,46 +141,89 @@ impl Finalizer {
         &self,
         psbt: &mut Psbt,
         input_index: usize,
-    ) -> Result<bool, miniscript::Error> {
-        // return true if already finalized.
+    ) -> Result<bool, FinalizeError> {
+        self.finalize_input_status(psbt, input_index).into_result()
+    }
+
+    /// Finalize a PSBT input and return its [`FinalizeStatus`].
+    ///
+    /// If the input cannot be finalized, the status describes what the plan is missing: the
+    /// signatures that were not provided, the hash preimages that are not known, or the
+    /// timelocks that the transaction does not satisfy.
+    ///
+    /// # Panics
+    ///
+    /// - If `input_index` is outside the bounds of the PSBT input vector.
+    pub fn finalize_input_status(&self, psbt: &mut Psbt, input_index: usize) -> FinalizeStatus {
         {
             let psbt_input = &psbt.inputs[input_index];
             if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
-                return Ok(true);
+                return FinalizeStatus::AlreadyFinalized;
             }
         }
 
-        let mut finalized = false;
         let outpoint = psbt
             .unsigned_tx
             .input
             .get(input_index)
             .expect("index out of range")
             .previous_output;
-        if let Some(plan) = self.plans.get(&outpoint) {
-            let stfr = PsbtInputSatisfier::new(psbt, input_index);
-            let (stack, script) = plan.satisfy(&stfr)?;
-            // clearing all fields and setting back the utxo, final scriptsig and witness
-            let original = core::mem::take(&mut psbt.inputs[input_index]);
-            let psbt_input = &mut psbt.inputs[input_index];
-            psbt_input.non_witness_utxo = original.non_witness_utxo;
-            psbt_input.witness_utxo = original.witness_utxo;
-            if !script.is_empty() {
-                psbt_input.final_script_sig = Some(script);
+        let requested = self.sighash_types.get(&outpoint).copied();
+        let plan = match self.plans.get(&outpoint) {
+            Some(plan) => plan,
+            None if self.foreign_inputs.contains(&outpoint) => {
+                // only satisfy with signatures of the requested sighash type
+                let original = core::mem::take(&mut psbt.inputs[input_index]);
+                psbt.inputs[input_index] = retain_requested_sighash_sigs(&original, requested);
+                let secp = Secp256k1::verification_only();
+                return match psbt.finalize_inp_mut(&secp, input_index) {
+                    Ok(()) => FinalizeStatus::Finalized,
+                    Err(e) => {
+                        psbt.inputs[input_index] = original;
+                        FinalizeStatus::ForeignInputError(e)
+                    }
+                };
             }
-            if !stack.is_empty() {
-                psbt_input.final_script_witness = Some(Witness::from_slice(&stack));
+            None => return FinalizeStatus::NoPlan(outpoint),
+        };
+
+        // only satisfy with signatures of the requested sighash type
+        let original = core::mem::take(&mut psbt.inputs[input_index]);
+        psbt.inputs[input_index] = retain_requested_sighash_sigs(&original, requested);
+
+        let missing = MissingRequirements::of(plan, psbt, input_index);
+        if !missing.is_empty() {
+            psbt.inputs[input_index] = original;
+            return FinalizeStatus::Unsatisfied(missing);
+        }
+
+        let stfr = PsbtInputSatisfier::new(psbt, input_index);
+        let (stack, script) = match plan.satisfy(&stfr) {
+            Ok(satisfaction) => satisfaction,
+            Err(e) => {
+                psbt.inputs[input_index] = original;
+                return FinalizeStatus::Error(e);
             }
-            finalized = true;
+        };
+        // clearing all fields and setting back the utxo, final scriptsig and witness
+        psbt.inputs[input_index] = Default::default();
+        let psbt_input = &mut psbt.inputs[input_index];
+        psbt_input.non_witness_utxo = original.non_witness_utxo;
+        psbt_input.witness_utxo = original.witness_utxo;
+        if !script.is_empty() {
+            psbt_input.final_script_sig = Some(script);
+        }
+        if !stack.is_empty() {
+            psbt_input.final_script_witness = Some(Witness::from_slice(&stack));
         }
 
-        Ok(finalized)
+        FinalizeStatus::Finalized
     }
 
     /// Attempt to finalize all of the inputs.
     ///
-    /// This method returns a [`FinalizeMap`] that contains the result of finalization
-    /// for each input.
+    /// This method returns a [`FinalizeMap`] that contains the [`FinalizeStatus`] of each input
+    /// that was not already finalized.
     pub fn finalize(&self, psbt: &mut Psbt) -> FinalizeMap {
         let mut result = FinalizeMap(BTreeMap::new());
 
@@ -130,7 +234,7 @@ impl Finalizer {
             }
             result
                 .0
-                .insert(input_index, self.finalize_input(psbt, input_index));
+                .insert(input_index, self.finalize_input_status(psbt, input_index));
         }
 
         // clear psbt outputs
@@ -146,32 +250,300 @@ impl Finalizer {
     }
 }
 
+/// Returns a copy of the PSBT input that only keeps signatures of the `requested` sighash type.
+///
+/// Signatures are kept if they use the `requested` sighash type, or the default sighash type if
+/// none is requested. The input's own `sighash_type` is not trusted, and is replaced with the
+/// `requested` one.
+pub(crate) fn retain_requested_sighash_sigs(
+    psbt_input: &psbt::Input,
+    requested: Option<PsbtSighashType>,
+) -> psbt::Input {
+    let mut psbt_input = psbt_input.clone();
+    psbt_input.sighash_type = requested;
+    let ecdsa_hash_ty = requested.map_or(Some(EcdsaSighashType::All), |ty| ty.ecdsa_hash_ty().ok());
+    psbt_input
+        .partial_sigs
+        .retain(|_, sig| Some(sig.sighash_type) == ecdsa_hash_ty);
+    let tap_hash_ty = requested.map_or(Some(TapSighashType::Default), |ty| {
+        ty.taproot_hash_ty().ok()
+    });
+    if psbt_input
+        .tap_key_sig
+        .is_some_and(|sig| Some(sig.sighash_type) != tap_hash_ty)
+    {
+        psbt_input.tap_key_sig = None;
+    }
+    psbt_input
+        .tap_script_sigs
+        .retain(|_, sig| Some(sig.sighash_type) == tap_hash_ty);
+    psbt_input
+}
+
+/// Status of finalizing a single PSBT input.
+#[derive(Debug)]
+pub enum FinalizeStatus {
+    /// The input was finalized.
+    Finalized,
+    /// The input was already finalized.
+    AlreadyFinalized,
+    /// There is no plan for the outpoint spent by the input.
+    NoPlan(OutPoint),
+    /// The plan of the input cannot be satisfied yet.
+    Unsatisfied(MissingRequirements),
+    /// Satisfying the plan failed.
+    Error(miniscript::Error),
+    /// Finalizing an input without a plan with the generic PSBT finalizer failed.
+    ForeignInputError(miniscript::psbt::Error),
+}
+
+impl FinalizeStatus {
+    /// Whether the input is finalized.
+    pub fn is_finalized(&self) -> bool {
+        matches!(self, Self::Finalized | Self::AlreadyFinalized)
+    }
+
+    /// Get the missing requirements if the plan of the input cannot be satisfied yet.
+    pub fn missing(&self) -> Option<&MissingRequirements> {
+        match self {
+            Self::Unsatisfied(missing) => Some(missing),
+            _ => None,
+        }
+    }
+
+    /// Convert into the result returned by [`Finalizer::finalize_input`].
+    pub fn into_result(self) -> Result<bool, FinalizeError> {
+        match self {
+            Self::Finalized | Self::AlreadyFinalized => Ok(true),
+            Self::NoPlan(_) => Ok(false),
+            Self::Unsatisfied(_) => Err(FinalizeError::Miniscript(
+                miniscript::Error::CouldNotSatisfy,
+            )),
+            Self::Error(e) => Err(FinalizeError::Miniscript(e)),
+            Self::ForeignInputError(e) => Err(FinalizeError::ForeignInput(e)),
+        }
+    }
+}
+
+/// Error when finalizing a PSBT input with [`Finalizer::finalize_input`].
+#[derive(Debug)]
+pub enum FinalizeError {
+    /// Satisfying the plan of the input failed.
+    Miniscript(miniscript::Error),
+    /// Finalizing an input without a plan with the generic PSBT finalizer failed.
+    ForeignInput(miniscript::psbt::Error),
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Miniscript(e) => write!(f, "failed to satisfy input: {e}"),
+            Self::ForeignInput(e) => write!(f, "failed to finalize foreign input: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FinalizeError {}
+
+/// What a [`Plan`] is missing to satisfy a PSBT input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MissingRequirements {
+    /// Signatures that were not provided, or not provided with the requested sighash type.
+    pub signatures: Vec<MissingSignature>,
+    /// Hash preimages that are not known.
+    pub preimages: Vec<MissingPreimage>,
+    /// The absolute timelock of the plan, if not satisfied by the transaction.
+    pub absolute_timelock: Option<absolute::LockTime>,
+    /// The relative timelock of the plan, if not satisfied by the input's sequence.
+    pub relative_timelock: Option<relative::LockTime>,
+}
+
+impl MissingRequirements {
+    /// Whether nothing is missing.
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+            && self.preimages.is_empty()
+            && self.absolute_timelock.is_none()
+            && self.relative_timelock.is_none()
+    }
+
+    /// Get what the `plan` is missing to satisfy the input at `input_index`.
+    ///
+    /// The PSBT input is expected to only contain signatures of the requested sighash type.
+    pub(crate) fn of(plan: &Plan, psbt: &Psbt, input_index: usize) -> Self {
+        let txin = &psbt.unsigned_tx.input[input_index];
+        let mut missing = MissingRequirements {
+            absolute_timelock: plan.absolute_timelock.filter(|&lock_time| {
+                !txin.sequence.enables_absolute_lock_time()
+                    || !lock_time.is_implied_by(psbt.unsigned_tx.lock_time)
+            }),
+            relative_timelock: plan.relative_timelock.filter(|lock_time| {
+                psbt.unsigned_tx.version < transaction::Version::TWO
+                    || !lock_time.is_implied_by_sequence(txin.sequence)
+            }),
+            ..Default::default()
+        };
+        let stfr = PsbtInputSatisfier::new(psbt, input_index);
+        for placeholder in plan.witness_template() {
+            if placeholder.satisfy_self(&stfr).is_none() {
+                missing.add(placeholder);
+            }
+        }
+        missing
+    }
+
+    fn add(&mut self, placeholder: &Placeholder<DefiniteDescriptorKey>) {
+        if let Some(sig) = MissingSignature::from_placeholder(placeholder) {
+            if !self.signatures.contains(&sig) {
+                self.signatures.push(sig);
+            }
+            return;
+        }
+        match placeholder {
+            Placeholder::PubkeyHash(hash, _) => {
+                let sig = MissingSignature::EcdsaKeyHash(*hash);
+                if !self.signatures.contains(&sig) {
+                    self.signatures.push(sig);
+                }
+            }
+            Placeholder::Sha256Preimage(hash) => {
+                self.preimages.push(MissingPreimage::Sha256(*hash))
+            }
+            Placeholder::Hash256Preimage(hash) => {
+                self.preimages.push(MissingPreimage::Hash256(*hash))
+            }
+            Placeholder::Ripemd160Preimage(hash) => {
+                self.preimages.push(MissingPreimage::Ripemd160(*hash))
+            }
+            Placeholder::Hash160Preimage(hash) => {
+                self.preimages.push(MissingPreimage::Hash160(*hash))
+            }
+            // These are either signatures or always satisfiable.
+            Placeholder::Pubkey(..)
+            | Placeholder::EcdsaSigPk(_)
+            | Placeholder::EcdsaSigPkHash(_)
+            | Placeholder::SchnorrSigPk(..)
+            | Placeholder::SchnorrSigPkHash(..)
+            | Placeholder::HashDissatisfaction
+            | Placeholder::PushOne
+            | Placeholder::PushZero
+            | Placeholder::TapScript(_)
+            | Placeholder::TapControlBlock(_) => {}
+        }
+    }
+}
+
+/// A signature required by a [`Plan`] that has not been provided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MissingSignature {
+    /// ECDSA signature by the key.
+    Ecdsa(DefiniteDescriptorKey),
+    /// ECDSA signature by the key with the given hash.
+    EcdsaKeyHash(hash160::Hash),
+    /// Schnorr signature by the key.
+    Schnorr {
+        /// The signing key.
+        key: DefiniteDescriptorKey,
+        /// Leaf hash of the script for a script path spend, `None` for a key path spend.
+        leaf_hash: Option<TapLeafHash>,
+    },
+    /// Schnorr signature by the key with the given hash.
+    SchnorrKeyHash {
+        /// Hash of the signing key.
+        hash: hash160::Hash,
+        /// Leaf hash of the script.
+        leaf_hash: TapLeafHash,
+    },
+}
+
+impl MissingSignature {
+    /// The signature required by the `placeholder`, if it is a signature placeholder.
+    pub(crate) fn from_placeholder(
+        placeholder: &Placeholder<DefiniteDescriptorKey>,
+    ) -> Option<Self> {
+        match placeholder {
+            Placeholder::EcdsaSigPk(pk) => Some(Self::Ecdsa(pk.clone())),
+            Placeholder::EcdsaSigPkHash(hash) => Some(Self::EcdsaKeyHash(*hash)),
+            Placeholder::SchnorrSigPk(pk, sig_type, _) => Some(Self::Schnorr {
+                key: pk.clone(),
+                leaf_hash: match sig_type {
+                    SchnorrSigType::KeySpend { .. } => None,
+                    SchnorrSigType::ScriptSpend { leaf_hash } => Some(*leaf_hash),
+                },
+            }),
+            Placeholder::SchnorrSigPkHash(hash, leaf_hash, _) => Some(Self::SchnorrKeyHash {
+                hash: *hash,
+                leaf_hash: *leaf_hash,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A hash preimage required by a [`Plan`] that is not known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingPreimage {
+    /// Preimage of a SHA256 hash.
+    Sha256(sha256::Hash),
+    /// Preimage of a HASH256 hash.
+    Hash256(miniscript::hash256::Hash),
+    /// Preimage of a RIPEMD160 hash.
+    Ripemd160(ripemd160::Hash),
+    /// Preimage of a HASH160 hash.
+    Hash160(hash160::Hash),
+}
+
 /// Holds the results of finalization
 #[derive(Debug)]
-pub struct FinalizeMap(BTreeMap<usize, Result<bool, miniscript::Error>>);
+pub struct FinalizeMap(BTreeMap<usize, FinalizeStatus>);
 
 impl FinalizeMap {
     /// Whether all inputs were finalized
     pub fn is_finalized(&self) -> bool {
-        self.0.values().all(|res| matches!(res, Ok(true)))
+        self.0.values().all(FinalizeStatus::is_finalized)
+    }
+
+    /// Get the [`FinalizeStatus`] of the input at `input_index`.
+    pub fn status(&self, input_index: usize) -> Option<&FinalizeStatus> {
+        self.0.get(&input_index)
+    }
+
+    /// Get the statuses as a map of `input_index` to [`FinalizeStatus`].
+    pub fn statuses(&self) -> &BTreeMap<usize, FinalizeStatus> {
+        &self.0
     }
 
     /// Get the results as a map of `input_index` to `finalize_input` result.
-    pub fn results(self) -> BTreeMap<usize, Result<bool, miniscript::Error>> {
+    pub fn results(self) -> BTreeMap<usize, Result<bool, FinalizeError>> {
         self.0
+            .into_iter()
+            .map(|(input_index, status)| (input_index, status.into_result()))
+            .collect()
     }
 }
 
 #[cfg_attr(coverage_nightly, coverage(off))]
 #[cfg(test)]
 mod tests {
-    use crate::{Finalizer, Output, PsbtParams, Selection, Signer};
+    use crate::collections::BTreeMap;
+    use crate::{
+        FinalizeError, FinalizeStatus, Finalizer, MissingPreimage, MissingSignature, Output,
+        PsbtParams, Selection, SignPsbtError, Signer,
+    };
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::psbt::PsbtSighashType;
     use bitcoin::secp256k1::Secp256k1;
-    use bitcoin::{absolute, transaction, Amount, ScriptBuf, TxIn, TxOut};
+    use bitcoin::{
+        absolute, relative, transaction, Amount, EcdsaSighashType, ScriptBuf, Sequence, TxIn, TxOut,
+    };
     use miniscript::bitcoin;
     use miniscript::bitcoin::Transaction;
     use miniscript::plan::Assets;
     use miniscript::Descriptor;
+    use std::string::String;
+    use std::vec::Vec;
 
     const TR_XPRV: &str = "tr(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/86h/1h/0h/0/*)";
     const WPKH_XPRV: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84h/1h/0h/0/*)";
@@ -180,14 +552,25 @@ mod tests {
     fn create_input_from_descriptor_at(
         descriptor: &str,
         derivation_index: u32,
+    ) -> anyhow::Result<(crate::Input, miniscript::descriptor::KeyMap)> {
+        create_input_with_assets(descriptor, derivation_index, |keymap| {
+            keymap.keys().fold(Assets::new(), |a, k| a.add(k.clone()))
+        })
+    }
+
+    fn create_input_with_assets(
+        descriptor: &str,
+        derivation_index: u32,
+        assets: impl FnOnce(&miniscript::descriptor::KeyMap) -> Assets,
     ) -> anyhow::Result<(crate::Input, miniscript::descriptor::KeyMap)> {
         let secp = Secp256k1::new();
         let (desc, keymap) = Descriptor::parse_descriptor(&secp, descriptor)?;
         let def_desc = desc.at_derivation_index(derivation_index)?;
         let script_pubkey = def_desc.script_pubkey();
 
-        let assets = keymap.keys().fold(Assets::new(), |a, k| a.add(k.clone()));
-        let plan = def_desc.plan(&assets).expect("failed to create plan");
+        let plan = def_desc
+            .plan(&assets(&keymap))
+            .expect("failed to create plan");
 
         let prev_tx = Transaction {
             version: transaction::Version::TWO,
@@ -337,6 +720,10 @@ mod tests {
 
         let finalized = finalizer.finalize(&mut psbt);
         assert!(!finalized.is_finalized());
+        assert!(matches!(
+            finalized.status(1),
+            Some(FinalizeStatus::NoPlan(outpoint)) if *outpoint == psbt.unsigned_tx.input[1].previous_output
+        ));
         let finalize_results = finalized.results();
 
         assert!(matches!(finalize_results.get(&0), Some(Ok(true))));
@@ -418,4 +805,312 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_finalize_with_requested_sighash_type() -> anyhow::Result<()> {
+        let (input, keymap) = create_input_from_descriptor_at(WPKH_XPRV, 0)?;
+        let outpoint = input.prev_outpoint();
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+
+        let sighash_types: BTreeMap<_, PsbtSighashType> =
+            [(outpoint, EcdsaSighashType::AllPlusAnyoneCanPay.into())].into();
+        let mut psbt = selection.create_psbt(PsbtParams {
+            sighash_types: sighash_types.clone(),
+            ..Default::default()
+        })?;
+        assert_eq!(
+            psbt.inputs[0].sighash_type,
+            Some(EcdsaSighashType::AllPlusAnyoneCanPay.into())
+        );
+        let finalizer = selection
+            .into_finalizer()
+            .with_sighash_types(sighash_types.clone());
+
+        let secp = Secp256k1::new();
+        let signer = Signer(keymap);
+
+        // The signer refuses inputs requesting a sighash type other than the expected one.
+        let mut tampered = psbt.clone();
+        tampered.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+        assert!(matches!(
+            signer.sign_psbt(&mut tampered, &sighash_types, &secp),
+            Err(SignPsbtError::SighashMismatch { input_index: 0, .. })
+        ));
+        assert!(tampered.inputs[0].partial_sigs.is_empty());
+
+        signer.sign_psbt(&mut psbt, &sighash_types, &secp)?;
+        assert!(psbt.inputs[0]
+            .partial_sigs
+            .values()
+            .all(|sig| sig.sighash_type == EcdsaSighashType::AllPlusAnyoneCanPay));
+
+        // Whoever returns the PSBT may swap the requested sighash type and the signature, which
+        // the finalizer refuses as it only trusts its own sighash types.
+        let mut tampered = psbt.clone();
+        tampered.inputs[0].partial_sigs.clear();
+        tampered.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+        tampered.sign(&signer, &secp).expect("signing failed");
+        assert!(tampered.inputs[0]
+            .partial_sigs
+            .values()
+            .all(|sig| sig.sighash_type == EcdsaSighashType::None));
+        let original = tampered.inputs[0].clone();
+        assert!(matches!(
+            finalizer.finalize_input_status(&mut tampered, 0),
+            FinalizeStatus::Unsatisfied(_)
+        ));
+        assert_eq!(tampered.inputs[0], original);
+
+        assert!(finalizer.finalize_input(&mut psbt, 0)?);
+        assert!(psbt.inputs[0].final_script_witness.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_rejects_signature_of_other_sighash_type() -> anyhow::Result<()> {
+        let (input, keymap) = create_input_from_descriptor_at(WPKH_XPRV, 0)?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+
+        // Sign with the default sighash type, then request ALL|ANYONECANPAY.
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let outpoint = psbt.unsigned_tx.input[0].previous_output;
+        let secp = Secp256k1::new();
+        Signer(keymap).sign_psbt(&mut psbt, &BTreeMap::new(), &secp)?;
+        let original = psbt.inputs[0].clone();
+
+        let finalizer = selection.into_finalizer();
+        let requesting = Finalizer::new(finalizer.plans.clone())
+            .with_sighash_types([(outpoint, EcdsaSighashType::AllPlusAnyoneCanPay.into())]);
+        assert!(requesting.finalize_input(&mut psbt, 0).is_err());
+        assert_eq!(psbt.inputs[0], original);
+
+        // The signature is used once the requested sighash type matches.
+        assert!(finalizer.finalize_input(&mut psbt, 0)?);
+
+        Ok(())
+    }
+
+    /// A `wsh(multi(..))` descriptor with keys from distinct master keys.
+    fn multisig_descriptor(threshold: usize, n: u8) -> anyhow::Result<String> {
+        let keys = (0..n)
+            .map(|i| {
+                let xprv = Xpriv::new_master(bitcoin::Network::Testnet, &[i; 32])?;
+                Ok(format!("{xprv}/0/*"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(format!("wsh(multi({threshold},{}))", keys.join(",")))
+    }
+
+    #[test]
+    fn test_finalize_status_reports_missing_signatures() -> anyhow::Result<()> {
+        // Plan to sign with the first two keys.
+        let (input, keymap) = create_input_with_assets(&multisig_descriptor(2, 3)?, 0, |keymap| {
+            keymap
+                .keys()
+                .take(2)
+                .fold(Assets::new(), |a, k| a.add(k.clone()))
+        })?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        // Only the first key signs.
+        let secp = Secp256k1::new();
+        let first = keymap.into_iter().take(1).collect();
+        psbt.sign(&Signer(first), &secp).expect("signing failed");
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+
+        let finalized = finalizer.finalize(&mut psbt);
+        assert!(!finalized.is_finalized());
+        let missing = finalized
+            .status(0)
+            .and_then(FinalizeStatus::missing)
+            .expect("must be unsatisfied");
+        assert_eq!(missing.signatures.len(), 1);
+        assert!(missing.preimages.is_empty());
+        assert!(missing.absolute_timelock.is_none());
+        assert!(missing.relative_timelock.is_none());
+        let MissingSignature::Ecdsa(key) = &missing.signatures[0] else {
+            panic!("expected a missing ECDSA signature");
+        };
+        let signed_key = psbt.inputs[0].partial_sigs.keys().next().expect("signed");
+        assert_ne!(key.derive_public_key(&secp)?, *signed_key);
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_status_reports_missing_preimage_and_timelock() -> anyhow::Result<()> {
+        let preimage = [42u8; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let descriptor = format!(
+            "wsh(and_v(v:pk({}),and_v(v:sha256({}),older(10))))",
+            "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84h/1h/0h/0/*",
+            hash,
+        );
+        let (input, keymap) = create_input_with_assets(&descriptor, 0, |keymap| {
+            keymap
+                .keys()
+                .fold(Assets::new(), |a, k| a.add(k.clone()))
+                .add(hash)
+                .older(relative::LockTime::from_height(10))
+        })?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        // Spend before the relative timelock matures.
+        psbt.unsigned_tx.input[0].sequence = Sequence::from_height(9);
+        let secp = Secp256k1::new();
+        psbt.sign(&Signer(keymap), &secp).expect("signing failed");
+
+        let status = finalizer.finalize_input_status(&mut psbt, 0);
+        let missing = status.missing().expect("must be unsatisfied");
+        assert!(missing.signatures.is_empty());
+        assert_eq!(missing.preimages, vec![MissingPreimage::Sha256(hash)]);
+        assert_eq!(
+            missing.relative_timelock,
+            Some(relative::LockTime::from_height(10))
+        );
+        assert!(missing.absolute_timelock.is_none());
+
+        psbt.inputs[0]
+            .sha256_preimages
+            .insert(hash, preimage.to_vec());
+        let missing = finalizer
+            .finalize_input_status(&mut psbt, 0)
+            .missing()
+            .cloned()
+            .expect("must be unsatisfied");
+        assert!(missing.preimages.is_empty());
+        assert!(missing.relative_timelock.is_some());
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_foreign_input() -> anyhow::Result<()> {
+        let (planned, planned_keymap) = create_input_from_descriptor_at(TR_XPRV, 0)?;
+        let (input, keymap) = create_input_from_descriptor_at(WPKH_XPRV, 0)?;
+
+        // Turn the wpkh input into a foreign input carrying its own psbt metadata.
+        let psbt = Selection::new(vec![input.clone()], vec![]).create_psbt(PsbtParams::default())?;
+        let foreign = crate::Input::from_psbt_input(
+            input.prev_outpoint(),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt.inputs[0].clone(),
+            input.satisfaction_weight() as usize,
+            None,
+            input.is_coinbase(),
+            None,
+        )?;
+
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![planned, foreign], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        let secp = Secp256k1::new();
+        let mut combined_keymap = planned_keymap;
+        combined_keymap.extend(keymap);
+        psbt.sign(&Signer(combined_keymap), &secp)
+            .expect("signing failed");
+
+        let finalized = finalizer.finalize(&mut psbt);
+        assert!(finalized.is_finalized());
+        assert!(matches!(
+            finalized.status(1),
+            Some(FinalizeStatus::Finalized)
+        ));
+        assert!(psbt.inputs[1].final_script_witness.is_some());
+        assert!(psbt.inputs[1].partial_sigs.is_empty());
+        psbt.extract_tx()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_foreign_input_with_requested_sighash_type() -> anyhow::Result<()> {
+        let (input, keymap) = create_input_from_descriptor_at(WPKH_XPRV, 0)?;
+        let psbt = Selection::new(vec![input.clone()], vec![]).create_psbt(PsbtParams::default())?;
+        let foreign = crate::Input::from_psbt_input(
+            input.prev_outpoint(),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt.inputs[0].clone(),
+            input.satisfaction_weight() as usize,
+            None,
+            input.is_coinbase(),
+            None,
+        )?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![foreign], vec![output]);
+
+        // Sign with the default sighash type, then request ALL|ANYONECANPAY.
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let outpoint = psbt.unsigned_tx.input[0].previous_output;
+        let secp = Secp256k1::new();
+        Signer(keymap).sign_psbt(&mut psbt, &BTreeMap::new(), &secp)?;
+        let original = psbt.inputs[0].clone();
+
+        let finalizer = selection.into_finalizer();
+        let requesting = Finalizer::new([])
+            .with_foreign_inputs([outpoint])
+            .with_sighash_types([(outpoint, EcdsaSighashType::AllPlusAnyoneCanPay.into())]);
+        assert!(matches!(
+            requesting.finalize_input(&mut psbt, 0),
+            Err(FinalizeError::ForeignInput(_))
+        ));
+        assert_eq!(psbt.inputs[0], original);
+
+        // The signature is used once the requested sighash type matches.
+        assert!(finalizer.finalize_input(&mut psbt, 0)?);
+        psbt.extract_tx()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finalize_keeps_finalized_foreign_input() -> anyhow::Result<()> {
+        let (input, keymap) = create_input_from_descriptor_at(WPKH_XPRV, 0)?;
+
+        // Finalize the input beforehand.
+        let selection = Selection::new(vec![input.clone()], vec![]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        psbt.sign(&Signer(keymap), &Secp256k1::new())
+            .expect("signing failed");
+        assert!(selection
+            .into_finalizer()
+            .finalize(&mut psbt)
+            .is_finalized());
+        let final_psbt_input = psbt.inputs[0].clone();
+
+        let foreign = crate::Input::from_psbt_input(
+            input.prev_outpoint(),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            final_psbt_input.clone(),
+            input.satisfaction_weight() as usize,
+            None,
+            input.is_coinbase(),
+            None,
+        )?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![foreign], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        assert!(matches!(
+            finalizer.finalize_input_status(&mut psbt, 0),
+            FinalizeStatus::AlreadyFinalized
+        ));
+        assert_eq!(psbt.inputs[0], final_psbt_input);
+
+        Ok(())
+    }
 }
@@ -5,11 +5,62 @@ use core::fmt;
 
 use bitcoin::constants::COINBASE_MATURITY;
 use bitcoin::transaction::OutputsIndexError;
-use bitcoin::{absolute, psbt, relative, Amount, Sequence, Txid};
+use bitcoin::{absolute, psbt, relative, Amount, Sequence, Txid, VarInt, Weight};
 use miniscript::bitcoin;
 use miniscript::bitcoin::{OutPoint, Transaction, TxOut};
 use miniscript::plan::Plan;
 
+/// Txin "base" fields include `outpoint` (32+4) and `nSequence` (4) and 1 byte for the scriptSig
+/// length.
+pub(crate) const TXIN_BASE_WEIGHT: u64 = (32 + 4 + 4 + 1) * 4;
+
+/// Weight of the fields of a segwit transaction that do not belong to any input or output:
+/// version, locktime, input and output counts (single byte) and the segwit marker and flag.
+pub(crate) const COMMON_FIELDS_WEIGHT: u64 = (4 + 4 + 1 + 1) * 4 + 2;
+
+/// The weight of the final scriptSig and witness of a PSBT input, counted the same way as
+/// [`Plan::satisfaction_weight`]: the scriptSig with its length prefix, plus the witness.
+pub(crate) fn final_satisfaction_weight(psbt_input: &psbt::Input) -> u64 {
+    let script_sig_size = psbt_input
+        .final_script_sig
+        .as_ref()
+        .map_or(1, |script_sig| {
+            VarInt::from(script_sig.len()).size() + script_sig.len()
+        });
+    let witness_size = psbt_input
+        .final_script_witness
+        .as_ref()
+        .map_or(0, |witness| witness.size());
+    (script_sig_size * 4 + witness_size) as u64
+}
+
+/// Estimate the weight of `unsigned_tx` once its inputs are satisfied.
+///
+/// `inputs` yields the satisfaction weight and whether it is segwit for each input of
+/// `unsigned_tx`. The empty scriptSig of the unsigned inputs is replaced by the satisfaction,
+/// and a segwit transaction also pays for the marker and flag and for the empty witness of each
+/// non-segwit input.
+pub(crate) fn estimate_tx_weight(
+    unsigned_tx: &Transaction,
+    inputs: impl IntoIterator<Item = (u64, bool)>,
+) -> Weight {
+    let mut weight = unsigned_tx.weight().to_wu();
+    let mut non_segwit_count = 0;
+    let mut any_segwit = false;
+    for (satisfaction_weight, is_segwit) in inputs {
+        weight = weight - 4 + satisfaction_weight;
+        if is_segwit {
+            any_segwit = true;
+        } else {
+            non_segwit_count += 1;
+        }
+    }
+    if any_segwit {
+        weight += 2 + non_segwit_count;
+    }
+    Weight::from_wu(weight)
+}
+
 /// Confirmation status of tx data.
 #[derive(Debug, Clone, Copy)]
 pub struct ConfirmationStatus {
@@ -175,7 +226,7 @@ impl PlanOrPsbtInput {
         match self {
             PlanOrPsbtInput::Plan { plan, .. } => plan.witness_version().is_some(),
             PlanOrPsbtInput::PsbtInput { psbt_input, .. } => {
-                psbt_input.final_script_witness.is_some()
+                psbt_input.final_script_witness.is_some() || psbt_input.witness_utxo.is_some()
             }
         }
     }
@@ -610,6 +661,9 @@ impl Input {
     }
 
     /// Is segwit.
+    ///
+    /// An input created from a [`psbt::Input`] is segwit if it has a final witness or a
+    /// `witness_utxo`, which BIP174 only allows for segwit inputs.
     pub fn is_segwit(&self) -> bool {
         self.plan.is_segwit()
     }
@@ -830,9 +884,6 @@ impl InputGroup {
 
     /// Total weight of all contained inputs (excluding input count varint).
     pub fn weight(&self) -> u64 {
-        /// Txin "base" fields include `outpoint` (32+4) and `nSequence` (4) and 1 byte for the scriptSig
-        /// length.
-        pub const TXIN_BASE_WEIGHT: u64 = (32 + 4 + 4 + 1) * 4;
         self.inputs()
             .iter()
             .map(|input| TXIN_BASE_WEIGHT + input.satisfaction_weight())
@@ -853,7 +904,7 @@ impl InputGroup {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxOut};
+    use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptBuf, Sequence, TxOut};
     use miniscript::{plan::Assets, Descriptor, DescriptorPublicKey};
     use std::str::FromStr;
 
@@ -974,4 +1025,63 @@ mod tests {
             Err(FromPsbtInputError::AbsoluteTimelockDisabled { .. })
         ));
     }
+
+    #[test]
+    fn test_psbt_input_with_witness_utxo_is_segwit() {
+        let prev_tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(100_000),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+                },
+                TxOut {
+                    value: Amount::from_sat(100_000),
+                    script_pubkey: ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros()),
+                },
+            ],
+        };
+        let txid = prev_tx.compute_txid();
+        let input = |vout, psbt_input| {
+            Input::from_psbt_input(
+                OutPoint::new(txid, vout),
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                psbt_input,
+                108,
+                None,
+                false,
+                None,
+            )
+            .unwrap()
+        };
+
+        // Not finalized yet, but the witness utxo tells that it will have a witness.
+        let segwit = input(
+            0,
+            psbt::Input {
+                witness_utxo: Some(prev_tx.output[0].clone()),
+                ..Default::default()
+            },
+        );
+        assert!(segwit.is_segwit());
+        let legacy = input(
+            1,
+            psbt::Input {
+                non_witness_utxo: Some(prev_tx),
+                ..Default::default()
+            },
+        );
+        assert!(!legacy.is_segwit());
+
+        // Coin selection accounts for the witness of a foreign candidate.
+        let candidates = crate::InputCandidates::new([], [segwit, legacy]);
+        let is_segwit = candidates
+            .coin_select_candidates()
+            .iter()
+            .map(|candidate| candidate.is_segwit)
+            .collect::<Vec<_>>();
+        assert_eq!(is_segwit, vec![true, false]);
+    }
 }
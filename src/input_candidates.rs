@@ -1,13 +1,15 @@
-use alloc::{vec, vec::Vec};
+use alloc::{string::String, vec, vec::Vec};
 use core::fmt;
 
 use bdk_coin_select::{metrics::LowestFee, Candidate, NoBnbSolution};
-use bitcoin::{absolute, FeeRate, OutPoint};
+use bitcoin::{absolute, Amount, FeeRate, OutPoint};
 use miniscript::bitcoin;
+use rand_core::RngCore;
 
-use crate::collections::{BTreeMap, HashSet};
+use crate::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::input::TXIN_BASE_WEIGHT;
 use crate::{
-    CannotMeetTarget, FeeRateExt, Input, InputGroup, Selection, Selector, SelectorError,
+    FeeRateExt, Input, InputGroup, InputScriptTypes, Selection, Selector, SelectorError,
     SelectorParams,
 };
 
@@ -21,8 +23,96 @@ pub struct InputCandidates {
     can_select: Vec<InputGroup>,
     /// Cached coin-select candidate metadata, kept in the same order as [`Self::groups`].
     cs_candidates: Vec<Candidate>,
-    /// Cached outpoints used for deduplication and O(1) membership checks.
-    contains: HashSet<OutPoint>,
+    /// Group of each contained input, used for deduplication and for finding the group of an
+    /// input: `None` for the `must_select` group, or the index of the group in `can_select`.
+    group_of: HashMap<OutPoint, Option<usize>>,
+    /// Inputs that were filtered out, with the reason.
+    excluded: BTreeMap<OutPoint, Exclusion>,
+    /// Absolute timelock units of the contained inputs, kept up to date on every change.
+    timelock_units: TimelockUnits,
+}
+
+/// Metadata of a group without inputs, to merge the metadata of other groups into.
+const EMPTY_CANDIDATE: Candidate = Candidate {
+    value: 0,
+    weight: 0,
+    input_count: 0,
+    is_segwit: false,
+};
+
+/// Number of contained inputs with an absolute timelock, by unit.
+#[derive(Debug, Clone, Copy, Default)]
+struct TimelockUnits {
+    blocks: usize,
+    seconds: usize,
+}
+
+impl TimelockUnits {
+    fn add(&mut self, input: &Input) {
+        match input.absolute_timelock() {
+            Some(absolute::LockTime::Blocks(_)) => self.blocks += 1,
+            Some(absolute::LockTime::Seconds(_)) => self.seconds += 1,
+            None => {}
+        }
+    }
+
+    fn remove(&mut self, input: &Input) {
+        match input.absolute_timelock() {
+            Some(absolute::LockTime::Blocks(_)) => self.blocks -= 1,
+            Some(absolute::LockTime::Seconds(_)) => self.seconds -= 1,
+            None => {}
+        }
+    }
+}
+
+/// Reason an input candidate was excluded.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExclusionReason {
+    /// Rejected by [`InputCandidates::filter`].
+    Filtered,
+    /// The input spends an immature coinbase output.
+    Immature,
+    /// The input is locked by a block-based timelock.
+    BlockTimelocked,
+    /// The input is locked by a time-based timelock, or whether it is locked cannot be
+    /// determined.
+    TimeTimelocked,
+    /// The input spends an output without enough confirmations, see
+    /// [`filter_unconfirmed_with_reason`].
+    Unconfirmed,
+    /// The input is frozen, see [`filter_frozen_with_reason`].
+    Frozen,
+    /// The input is in a partial group of a reused script pubkey, see
+    /// [`InputCandidates::filter_partial_groups`].
+    PartialGroup,
+    /// A reason named by the caller, e.g. `"address reuse"`.
+    Other(String),
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Filtered => write!(f, "filtered"),
+            Self::Immature => write!(f, "immature"),
+            Self::BlockTimelocked => write!(f, "block-timelocked"),
+            Self::TimeTimelocked => write!(f, "time-timelocked"),
+            Self::Unconfirmed => write!(f, "unconfirmed"),
+            Self::Frozen => write!(f, "frozen"),
+            Self::PartialGroup => write!(f, "partial group"),
+            Self::Other(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// An input excluded from the [`InputCandidates`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exclusion {
+    /// Value of the excluded input.
+    pub value: Amount,
+    /// Why the input was excluded.
+    ///
+    /// Inputs grouped with a rejected input share its reason.
+    pub reason: ExclusionReason,
 }
 
 impl InputCandidates {
@@ -34,23 +124,37 @@ impl InputCandidates {
         A: IntoIterator<Item = Input>,
         B: IntoIterator<Item = Input>,
     {
-        let mut contains = HashSet::<OutPoint>::new();
+        let mut group_of = HashMap::<OutPoint, Option<usize>>::new();
         let must_select = InputGroup::from_inputs(
             must_select
                 .into_iter()
-                .filter(|input| contains.insert(input.prev_outpoint())),
+                .filter(|input| group_of.insert(input.prev_outpoint(), None).is_none()),
         );
-        let can_select = can_select
-            .into_iter()
-            .filter(|input| contains.insert(input.prev_outpoint()))
-            .map(InputGroup::from_input)
-            .collect::<Vec<_>>();
+        let mut groups = Vec::new();
+        for input in can_select {
+            if group_of.contains_key(&input.prev_outpoint()) {
+                continue;
+            }
+            group_of.insert(input.prev_outpoint(), Some(groups.len()));
+            groups.push(InputGroup::from_input(input));
+        }
+        let can_select = groups;
         let cs_candidates = Self::build_cs_candidates(&must_select, &can_select);
+        let mut timelock_units = TimelockUnits::default();
+        for input in must_select
+            .iter()
+            .chain(&can_select)
+            .flat_map(InputGroup::inputs)
+        {
+            timelock_units.add(input);
+        }
         InputCandidates {
             must_select,
             can_select,
             cs_candidates,
-            contains,
+            group_of,
+            excluded: BTreeMap::new(),
+            timelock_units,
         }
     }
 
@@ -61,15 +165,105 @@ impl InputCandidates {
         must_select
             .iter()
             .chain(can_select)
-            .map(|group| Candidate {
-                value: group.value().to_sat(),
-                weight: group.weight(),
-                input_count: group.input_count(),
-                is_segwit: group.is_segwit(),
-            })
+            .map(Self::cs_candidate)
             .collect()
     }
 
+    fn cs_candidate(group: &InputGroup) -> Candidate {
+        Candidate {
+            value: group.value().to_sat(),
+            weight: group.weight(),
+            input_count: group.input_count(),
+            is_segwit: group.is_segwit(),
+        }
+    }
+
+    fn input_cs_candidate(input: &Input) -> Candidate {
+        Candidate {
+            value: input.prev_txout().value.to_sat(),
+            weight: TXIN_BASE_WEIGHT + input.satisfaction_weight(),
+            input_count: 1,
+            is_segwit: input.is_segwit(),
+        }
+    }
+
+    /// Add the metadata of `other` to `candidate`, as if their inputs were in one group.
+    fn merge_cs_candidate(candidate: &mut Candidate, other: Candidate) {
+        candidate.value += other.value;
+        candidate.weight += other.weight;
+        candidate.input_count += other.input_count;
+        candidate.is_segwit |= other.is_segwit;
+    }
+
+    /// Add `input` as a `can_select` group of its own.
+    ///
+    /// Returns `false` if the input is already contained. Unlike [`new`](Self::new), this only
+    /// computes the coin-select metadata of the new group.
+    pub fn insert(&mut self, input: Input) -> bool {
+        if self.group_of.contains_key(&input.prev_outpoint()) {
+            return false;
+        }
+        self.group_of
+            .insert(input.prev_outpoint(), Some(self.can_select.len()));
+        self.excluded.remove(&input.prev_outpoint());
+        self.timelock_units.add(&input);
+        let group = InputGroup::from_input(input);
+        self.cs_candidates.push(Self::cs_candidate(&group));
+        self.can_select.push(group);
+        true
+    }
+
+    /// Remove the input of `outpoint`, returning it if it was contained.
+    ///
+    /// The rest of its group is kept, and only the coin-select metadata of that group is
+    /// recomputed. The input is not recorded as excluded.
+    ///
+    /// The group of the input is looked up by outpoint, so this takes time proportional to the
+    /// size of that group. If the input was the last of its `can_select` group, the last group
+    /// takes the place of the removed one, changing the order of the groups. Emptying the
+    /// `must_select` group shifts the cached metadata and takes linear time.
+    pub fn remove(&mut self, outpoint: OutPoint) -> Option<Input> {
+        let group_index = self.group_of.remove(&outpoint)?;
+        let offset = usize::from(self.must_select.is_some());
+        let group = match group_index {
+            None => self.must_select.take(),
+            Some(index) => Some(self.can_select.swap_remove(index)),
+        }
+        .expect("contained input must be in its group");
+        let mut inputs = group.into_inputs();
+        let position = inputs
+            .iter()
+            .position(|input| input.prev_outpoint() == outpoint)
+            .expect("must exist");
+        let input = inputs.remove(position);
+        match (group_index, InputGroup::from_inputs(inputs)) {
+            (None, Some(group)) => {
+                self.cs_candidates[0] = Self::cs_candidate(&group);
+                self.must_select = Some(group);
+            }
+            (None, None) => {
+                self.cs_candidates.remove(0);
+            }
+            (Some(index), Some(group)) => {
+                // Undo the swap so that the groups keep their order.
+                self.cs_candidates[offset + index] = Self::cs_candidate(&group);
+                self.can_select.push(group);
+                let last = self.can_select.len() - 1;
+                self.can_select.swap(index, last);
+            }
+            (Some(index), None) => {
+                self.cs_candidates.swap_remove(offset + index);
+                if let Some(moved) = self.can_select.get(index) {
+                    for input in moved.inputs() {
+                        self.group_of.insert(input.prev_outpoint(), Some(index));
+                    }
+                }
+            }
+        }
+        self.timelock_units.remove(&input);
+        Some(input)
+    }
+
     /// Iterate over all contained inputs of all groups.
     pub fn inputs(&self) -> impl Iterator<Item = &Input> + '_ {
         self.groups().flat_map(InputGroup::inputs)
@@ -100,6 +294,13 @@ impl InputCandidates {
         self.must_select.as_ref()
     }
 
+    /// Whether the inputs have absolute timelocks of both units (height and time).
+    ///
+    /// This is tracked as inputs are added and removed, so it does not scan the inputs.
+    pub fn has_mixed_timelock_units(&self) -> bool {
+        self.timelock_units.blocks > 0 && self.timelock_units.seconds > 0
+    }
+
     /// Cached candidate metadata used by `bdk_coin_select`.
     pub fn coin_select_candidates(&self) -> &Vec<Candidate> {
         &self.cs_candidates
@@ -107,92 +308,244 @@ impl InputCandidates {
 
     /// Whether the outpoint is an input candidate.
     pub fn contains(&self, outpoint: OutPoint) -> bool {
-        self.contains.contains(&outpoint)
+        self.group_of.contains_key(&outpoint)
+    }
+
+    /// Inputs that were filtered out, by outpoint.
+    ///
+    /// Use this to explain why a coin was not used.
+    pub fn excluded(&self) -> &BTreeMap<OutPoint, Exclusion> {
+        &self.excluded
+    }
+
+    /// Why the input spending `outpoint` was filtered out, if it was.
+    pub fn exclusion_reason(&self, outpoint: OutPoint) -> Option<&ExclusionReason> {
+        self.excluded
+            .get(&outpoint)
+            .map(|exclusion| &exclusion.reason)
+    }
+
+    /// Total value of the excluded inputs by [`ExclusionReason`].
+    pub fn excluded_value(&self) -> BTreeMap<ExclusionReason, Amount> {
+        let mut excluded_value = BTreeMap::<ExclusionReason, Amount>::new();
+        for exclusion in self.excluded.values() {
+            *excluded_value.entry(exclusion.reason.clone()).or_default() += exclusion.value;
+        }
+        excluded_value
     }
 
     /// Regroup inputs with given `policy`.
     ///
     /// Anything grouped with `must_select` inputs also becomes `must_select`.
+    ///
+    /// The `policy` is called on every input, so this takes linear time. The coin-select metadata
+    /// of a new group is merged from the cached metadata of the groups it is made of, and is only
+    /// computed from the inputs of groups that the `policy` splits.
     pub fn regroup<P, G>(self, mut policy: P) -> Self
     where
         P: FnMut(&Input) -> G,
         G: Ord + Clone,
     {
-        let mut order = Vec::<G>::with_capacity(self.contains.len());
-        let mut groups = BTreeMap::<G, Vec<Input>>::new();
-        for input in self
-            .can_select
-            .into_iter()
-            .flat_map(InputGroup::into_inputs)
-        {
-            let group_id = policy(&input);
-            use crate::collections::btree_map::Entry;
-            let entry = match groups.entry(group_id.clone()) {
-                Entry::Vacant(entry) => {
-                    order.push(group_id.clone());
-                    entry.insert(vec![])
-                }
-                Entry::Occupied(entry) => entry.into_mut(),
-            };
-            entry.push(input);
-        }
+        let mut cached = self.cs_candidates.into_iter();
+        let mut must_select_candidate = match self.must_select {
+            Some(_) => cached
+                .next()
+                .expect("must have metadata of the must-select group"),
+            None => EMPTY_CANDIDATE,
+        };
 
+        // The policy is called once per input, must-select inputs first.
         let mut must_select = self.must_select.map_or(vec![], |g| g.into_inputs());
         let must_select_order = must_select.iter().map(&mut policy).collect::<Vec<_>>();
+
+        let mut order = Vec::<G>::with_capacity(self.group_of.len());
+        let mut groups = BTreeMap::<G, (Vec<Input>, Candidate)>::new();
+        for (group, candidate) in self.can_select.into_iter().zip(cached) {
+            let inputs = group.into_inputs();
+            let group_ids = inputs.iter().map(&mut policy).collect::<Vec<_>>();
+            // A group moved as a whole keeps its metadata.
+            let is_whole = group_ids.windows(2).all(|ids| ids[0] == ids[1]);
+            for (i, (input, group_id)) in inputs.into_iter().zip(group_ids).enumerate() {
+                use crate::collections::btree_map::Entry;
+                let (group_inputs, group_candidate) = match groups.entry(group_id.clone()) {
+                    Entry::Vacant(entry) => {
+                        order.push(group_id);
+                        entry.insert((vec![], EMPTY_CANDIDATE))
+                    }
+                    Entry::Occupied(entry) => entry.into_mut(),
+                };
+                if !is_whole {
+                    Self::merge_cs_candidate(group_candidate, Self::input_cs_candidate(&input));
+                } else if i == 0 {
+                    Self::merge_cs_candidate(group_candidate, candidate);
+                }
+                group_inputs.push(input);
+            }
+        }
+
         for g_id in must_select_order {
-            if let Some(inputs) = groups.remove(&g_id) {
+            if let Some((inputs, candidate)) = groups.remove(&g_id) {
                 must_select.extend(inputs);
+                Self::merge_cs_candidate(&mut must_select_candidate, candidate);
             }
         }
         let must_select = InputGroup::from_inputs(must_select);
 
-        let mut can_select = Vec::<InputGroup>::new();
+        let mut group_of = self.group_of;
+        let mut can_select = Vec::<InputGroup>::with_capacity(order.len());
+        let mut cs_candidates = Vec::<Candidate>::with_capacity(order.len() + 1);
+        if let Some(group) = &must_select {
+            cs_candidates.push(must_select_candidate);
+            for input in group.inputs() {
+                group_of.insert(input.prev_outpoint(), None);
+            }
+        }
         for g_id in order {
-            if let Some(inputs) = groups.remove(&g_id) {
+            if let Some((inputs, candidate)) = groups.remove(&g_id) {
                 if let Some(group) = InputGroup::from_inputs(inputs) {
+                    for input in group.inputs() {
+                        group_of.insert(input.prev_outpoint(), Some(can_select.len()));
+                    }
                     can_select.push(group);
+                    cs_candidates.push(candidate);
                 }
             }
         }
 
-        let cs_candidates = Self::build_cs_candidates(&must_select, &can_select);
-        let no_dup = self.contains;
-
         Self {
             must_select,
             can_select,
             cs_candidates,
-            contains: no_dup,
+            group_of,
+            excluded: self.excluded,
+            timelock_units: self.timelock_units,
         }
     }
 
+    /// Filters out partial groups of reused script pubkeys.
+    ///
+    /// This is meant for groups chunked with [`group_by_spk_with_max_entries`] using the same
+    /// `max_entries`. A `can_select` group is partial if all of its inputs share a script pubkey,
+    /// it has fewer than `max_entries` inputs, and another group of that script pubkey is full
+    /// (has exactly `max_entries` inputs). That is the last chunk of a script pubkey with more
+    /// inputs than fit in one group. Groups of a script pubkey without a full group are kept, so
+    /// groups made by other policies are left alone. Like Bitcoin Core, we prefer spending full
+    /// groups over leaving coins of a reused address behind in a partial one.
+    ///
+    /// Filtered-out inputs are excluded with [`ExclusionReason::PartialGroup`].
+    ///
+    /// Bitcoin Core only leaves out partial groups while the remaining coins can fund the
+    /// transaction. To do the same, keep the unfiltered candidates and fall back to them if
+    /// selecting from the filtered ones fails with [`SelectorError::CannotMeetTarget`].
+    ///
+    /// [`SelectorError::CannotMeetTarget`]: crate::SelectorError::CannotMeetTarget
+    pub fn filter_partial_groups(self, max_entries: usize) -> Self {
+        let spk_of = |group: &InputGroup| {
+            let spk = &group.inputs().first()?.prev_txout().script_pubkey;
+            group
+                .all(|input| &input.prev_txout().script_pubkey == spk)
+                .then(|| spk.clone())
+        };
+        let full = self
+            .groups()
+            .filter(|group| group.inputs().len() == max_entries)
+            .filter_map(spk_of)
+            .collect::<BTreeSet<_>>();
+        let partial = self
+            .can_select
+            .iter()
+            .filter(|group| {
+                group.inputs().len() < max_entries
+                    && spk_of(group).is_some_and(|spk| full.contains(&spk))
+            })
+            .flat_map(|group| group.inputs().iter().map(Input::prev_outpoint))
+            .collect::<HashSet<_>>();
+        self.filter_with_reason(|input| {
+            if partial.contains(&input.prev_outpoint()) {
+                Err(ExclusionReason::PartialGroup)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
     /// Filters out inputs.
     ///
     /// If a filtered-out input is part of a group, the group will also be filtered out.
-    /// Does not filter `must_select` inputs.
-    pub fn filter<P>(mut self, mut policy: P) -> Self
+    /// Does not filter `must_select` inputs. Filtered-out inputs are excluded with
+    /// [`ExclusionReason::Filtered`].
+    pub fn filter<P>(self, mut policy: P) -> Self
     where
         P: FnMut(&Input) -> bool,
     {
-        let mut to_rm = Vec::<OutPoint>::new();
+        self.filter_with_reason(|input| {
+            if policy(input) {
+                Ok(())
+            } else {
+                Err(ExclusionReason::Filtered)
+            }
+        })
+    }
+
+    /// Filters out inputs for which `policy` returns an [`ExclusionReason`].
+    ///
+    /// If a filtered-out input is part of a group, the group will also be filtered out with the
+    /// same reason. Does not filter `must_select` inputs. The exclusions are recorded per outpoint,
+    /// see [`excluded`](Self::excluded). Use [`ExclusionReason::Other`] for reasons not covered by
+    /// the other variants.
+    pub fn filter_with_reason<P>(mut self, mut policy: P) -> Self
+    where
+        P: FnMut(&Input) -> Result<(), ExclusionReason>,
+    {
+        let mut to_rm = Vec::<(OutPoint, Exclusion)>::new();
+        let mut retained = Vec::<bool>::with_capacity(self.can_select.len());
+        let mut retained_count = 0;
         self.can_select.retain(|group| {
-            let retain = group.all(&mut policy);
-            if !retain {
-                for input in group.inputs() {
-                    to_rm.push(input.prev_outpoint());
+            let reason = match group.inputs().iter().try_for_each(&mut policy) {
+                Ok(()) => {
+                    // Point the index at the new position of the group, if it moved.
+                    if retained_count < retained.len() {
+                        for input in group.inputs() {
+                            self.group_of
+                                .insert(input.prev_outpoint(), Some(retained_count));
+                        }
+                    }
+                    retained_count += 1;
+                    retained.push(true);
+                    return true;
                 }
+                Err(reason) => reason,
+            };
+            for input in group.inputs() {
+                self.timelock_units.remove(input);
+                let exclusion = Exclusion {
+                    value: input.prev_txout().value,
+                    reason: reason.clone(),
+                };
+                to_rm.push((input.prev_outpoint(), exclusion));
             }
-            retain
+            retained.push(false);
+            false
         });
-        for op in to_rm {
-            self.contains.remove(&op);
+        for (op, exclusion) in to_rm {
+            self.group_of.remove(&op);
+            self.excluded.insert(op, exclusion);
         }
-        self.cs_candidates = Self::build_cs_candidates(&self.must_select, &self.can_select);
+        // Keep the metadata of the retained groups instead of recomputing it.
+        let offset = usize::from(self.must_select.is_some());
+        let mut index = 0;
+        self.cs_candidates.retain(|_| {
+            let keep = index < offset || retained[index - offset];
+            index += 1;
+            keep
+        });
         self
     }
 
     /// Attempt to convert the input candidates into a valid [`Selection`] with a given
     /// `algorithm` and selector `params`.
+    ///
+    /// Fails with [`SelectorError::FeeCap`] if the selection exceeds the fee caps of `params`.
     pub fn into_selection<A, E>(
         self,
         algorithm: A,
@@ -200,15 +553,71 @@ impl InputCandidates {
     ) -> Result<Selection, IntoSelectionError<E>>
     where
         A: FnMut(&mut Selector) -> Result<(), E>,
+    {
+        self.select_and_finalize(
+            params,
+            |selector| selector.select_with_algorithm(algorithm),
+            |selector| selector.try_finalize(),
+        )
+        .map(|(selection, ())| selection)
+    }
+
+    /// Same as [`into_selection`](Self::into_selection), but splits the change at random with
+    /// `rng` if [`SelectorParams::change_split`] is set.
+    pub fn into_selection_with_rng<A, E>(
+        self,
+        algorithm: A,
+        params: SelectorParams,
+        rng: &mut impl RngCore,
+    ) -> Result<Selection, IntoSelectionError<E>>
+    where
+        A: FnMut(&mut Selector) -> Result<(), E>,
+    {
+        self.select_and_finalize(
+            params,
+            |selector| selector.select_with_algorithm(algorithm),
+            |selector| selector.try_finalize_with_rng(rng),
+        )
+        .map(|(selection, ())| selection)
+    }
+
+    /// Same as [`into_selection`](Self::into_selection), but first attempts to use inputs of a
+    /// single script type, see [`Selector::select_by_script_type`].
+    ///
+    /// The returned [`InputScriptTypes`] tells which script types the selection ended up with.
+    pub fn into_selection_by_script_type<A, E>(
+        self,
+        algorithm: A,
+        params: SelectorParams,
+    ) -> Result<(Selection, InputScriptTypes), IntoSelectionError<E>>
+    where
+        A: FnMut(&mut Selector) -> Result<(), E>,
+    {
+        self.select_and_finalize(
+            params,
+            |selector| selector.select_by_script_type(algorithm),
+            |selector| selector.try_finalize(),
+        )
+    }
+
+    fn select_and_finalize<S, T, E, F>(
+        self,
+        params: SelectorParams,
+        select: S,
+        finalize: F,
+    ) -> Result<(Selection, T), IntoSelectionError<E>>
+    where
+        S: FnOnce(&mut Selector) -> Result<T, E>,
+        F: FnOnce(&Selector) -> Option<Selection>,
     {
         let mut selector = Selector::new(&self, params).map_err(IntoSelectionError::Selector)?;
+        let selected = select(&mut selector).map_err(IntoSelectionError::SelectionAlgorithm)?;
+        let selection = finalize(&selector)
+            .ok_or_else(|| IntoSelectionError::CannotMeetTarget(selector.cannot_meet_target()))?;
         selector
-            .select_with_algorithm(algorithm)
-            .map_err(IntoSelectionError::SelectionAlgorithm)?;
-        let selection = selector
-            .try_finalize()
-            .ok_or(IntoSelectionError::CannotMeetTarget(CannotMeetTarget))?;
-        Ok(selection)
+            .check_fee_caps()
+            .map_err(IntoSelectionError::Selector)?;
+        Ok((selection, selected))
     }
 }
 
@@ -220,7 +629,7 @@ pub enum IntoSelectionError<E> {
     /// Selection algorithm failed.
     SelectionAlgorithm(E),
     /// The target cannot be met
-    CannotMeetTarget(CannotMeetTarget),
+    CannotMeetTarget(crate::CannotMeetTarget),
 }
 
 impl<E: fmt::Display> fmt::Display for IntoSelectionError<E> {
@@ -268,6 +677,33 @@ pub fn group_by_spk() -> impl Fn(&Input) -> bitcoin::ScriptBuf {
     |input| input.prev_txout().script_pubkey.clone()
 }
 
+/// Bitcoin Core's maximum number of inputs in a group of a reused script pubkey.
+pub const OUTPUT_GROUP_MAX_ENTRIES: usize = 100;
+
+/// Group inputs by script pubkey, with at most `max_entries` inputs per group.
+///
+/// Inputs of a script pubkey are chunked into groups in the order of the candidates, so the
+/// same candidates always result in the same groups. Use
+/// [`InputCandidates::filter_partial_groups`] to leave out the last chunk of a script pubkey
+/// that did not fill a group.
+///
+/// # Panics
+///
+/// If `max_entries` is zero.
+pub fn group_by_spk_with_max_entries(
+    max_entries: usize,
+) -> impl FnMut(&Input) -> (bitcoin::ScriptBuf, usize) {
+    assert!(max_entries > 0, "max_entries must not be zero");
+    let mut counts = BTreeMap::<bitcoin::ScriptBuf, usize>::new();
+    move |input| {
+        let spk = input.prev_txout().script_pubkey.clone();
+        let count = counts.entry(spk.clone()).or_default();
+        let chunk = *count / max_entries;
+        *count += 1;
+        (spk, chunk)
+    }
+}
+
 /// Filter out inputs that cannot be spent now.
 ///
 /// If an input's spendability cannot be determined, it will also be filtered out.
@@ -278,7 +714,267 @@ pub fn filter_unspendable(
     move |input| input.is_spendable(tip_height, tip_mtp).unwrap_or(false)
 }
 
+/// Filter out inputs that cannot be spent now, with the reason.
+///
+/// Use with [`InputCandidates::filter_with_reason`]. If an input's spendability cannot be
+/// determined, it is filtered out as [`ExclusionReason::TimeTimelocked`].
+pub fn filter_unspendable_with_reason(
+    tip_height: absolute::Height,
+    tip_mtp: Option<absolute::Time>,
+) -> impl Fn(&Input) -> Result<(), ExclusionReason> {
+    move |input| {
+        if input.is_immature(tip_height) {
+            return Err(ExclusionReason::Immature);
+        }
+        if input.is_block_timelocked(tip_height) {
+            return Err(ExclusionReason::BlockTimelocked);
+        }
+        match input.is_timelocked(tip_height, tip_mtp) {
+            Some(false) => Ok(()),
+            _ => Err(ExclusionReason::TimeTimelocked),
+        }
+    }
+}
+
+/// Filter out inputs with fewer than `min_confirmations` confirmations, with the reason.
+///
+/// Use with [`InputCandidates::filter_with_reason`]. Inputs are filtered out as
+/// [`ExclusionReason::Unconfirmed`], and an input without a confirmation status has no
+/// confirmations.
+pub fn filter_unconfirmed_with_reason(
+    tip_height: absolute::Height,
+    min_confirmations: u32,
+) -> impl Fn(&Input) -> Result<(), ExclusionReason> {
+    move |input| {
+        if input.confirmations(tip_height) < min_confirmations {
+            Err(ExclusionReason::Unconfirmed)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Filter out inputs spending one of the `frozen` outpoints, with the reason.
+///
+/// Use with [`InputCandidates::filter_with_reason`]. Inputs are filtered out as
+/// [`ExclusionReason::Frozen`].
+pub fn filter_frozen_with_reason(
+    frozen: impl IntoIterator<Item = OutPoint>,
+) -> impl Fn(&Input) -> Result<(), ExclusionReason> {
+    let frozen = frozen.into_iter().collect::<HashSet<_>>();
+    move |input| {
+        if frozen.contains(&input.prev_outpoint()) {
+            Err(ExclusionReason::Frozen)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 /// No filtering.
 pub fn no_filtering() -> impl Fn(&InputGroup) -> bool {
     |_| true
 }
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, input, parse_descriptor, WPKH_XPRV};
+    use crate::ConfirmationStatus;
+
+    /// Inputs paying to the given derivation indices, one coin per index.
+    fn inputs(indices: &[u32]) -> anyhow::Result<Vec<Input>> {
+        let (desc, keymap) = parse_descriptor(WPKH_XPRV)?;
+        let assets = assets(&keymap);
+        let status = Some(ConfirmationStatus::new(1, None)?);
+        let mut inputs = Vec::new();
+        for (n, &index) in indices.iter().enumerate() {
+            let desc = desc.at_derivation_index(index)?;
+            let value = Amount::from_sat(10_000);
+            inputs.push(input(&desc, &assets, value, n as u32, status)?);
+        }
+        Ok(inputs)
+    }
+
+    #[test]
+    fn test_group_by_spk_with_max_entries() -> anyhow::Result<()> {
+        // Five coins to address 0, two to address 1 and one to address 2.
+        let candidates = InputCandidates::new([], inputs(&[0, 0, 1, 0, 0, 2, 0, 1])?)
+            .regroup(group_by_spk_with_max_entries(2));
+        let sizes = candidates
+            .groups()
+            .map(|group| group.inputs().len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![2, 2, 2, 1, 1]);
+
+        let outpoints = |candidates: &InputCandidates| {
+            candidates
+                .inputs()
+                .map(Input::prev_outpoint)
+                .collect::<Vec<_>>()
+        };
+        let filtered = candidates.clone().filter_partial_groups(2);
+        // Only the last coin of address 0 is left behind.
+        let mut expected = outpoints(&candidates);
+        let partial = candidates.groups().nth(4).expect("partial group");
+        let partial_outpoint = partial.inputs()[0].prev_outpoint();
+        expected.retain(|&outpoint| outpoint != partial_outpoint);
+        assert_eq!(outpoints(&filtered), expected);
+        assert_eq!(
+            filtered.exclusion_reason(partial_outpoint),
+            Some(&ExclusionReason::PartialGroup)
+        );
+
+        // Groups of other policies, without a full chunk of their script pubkey, are kept.
+        let coins = inputs(&[0, 0, 0])?;
+        let first = coins[0].prev_outpoint();
+        let by_other = InputCandidates::new([], coins).regroup(|input| {
+            let spk = input.prev_txout().script_pubkey.clone();
+            (spk, input.prev_outpoint() == first)
+        });
+        assert_eq!(by_other.groups().count(), 2);
+        let kept = by_other
+            .clone()
+            .filter_partial_groups(OUTPUT_GROUP_MAX_ENTRIES);
+        assert_eq!(outpoints(&kept), outpoints(&by_other));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_with_reason_records_exclusions() -> anyhow::Result<()> {
+        let coins = inputs(&[0, 1, 2])?;
+        let frozen = coins[1].prev_outpoint();
+        let reused = coins[2].prev_outpoint();
+        let candidates = InputCandidates::new([], coins.clone())
+            .filter_with_reason(filter_frozen_with_reason([frozen]))
+            .filter_with_reason(|input| {
+                if input.prev_outpoint() == reused {
+                    Err(ExclusionReason::Other("address reuse".into()))
+                } else {
+                    Ok(())
+                }
+            });
+
+        assert_eq!(candidates.inputs().count(), 1);
+        assert_eq!(candidates.excluded().len(), 2);
+        assert_eq!(candidates.exclusion_reason(coins[0].prev_outpoint()), None);
+        assert_eq!(
+            candidates.excluded().get(&frozen),
+            Some(&Exclusion {
+                value: Amount::from_sat(10_000),
+                reason: ExclusionReason::Frozen,
+            })
+        );
+        assert_eq!(
+            candidates.exclusion_reason(reused),
+            Some(&ExclusionReason::Other("address reuse".into()))
+        );
+        assert_eq!(
+            candidates.excluded_value().into_iter().collect::<Vec<_>>(),
+            vec![
+                (ExclusionReason::Frozen, Amount::from_sat(10_000)),
+                (
+                    ExclusionReason::Other("address reuse".into()),
+                    Amount::from_sat(10_000)
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_unconfirmed_and_frozen_with_reason() -> anyhow::Result<()> {
+        // Every coin is confirmed at height 1.
+        let coins = inputs(&[0, 1, 2])?;
+        let tip_height = absolute::Height::from_consensus(3)?;
+        let candidates = InputCandidates::new([], coins.clone())
+            .filter_with_reason(filter_unconfirmed_with_reason(tip_height, 3));
+        assert_eq!(candidates.inputs().count(), 3);
+        let candidates = candidates
+            .filter_with_reason(filter_frozen_with_reason([coins[1].prev_outpoint()]))
+            .filter_with_reason(filter_unconfirmed_with_reason(tip_height, 4));
+        assert_eq!(candidates.inputs().count(), 0);
+        assert_eq!(
+            candidates.exclusion_reason(coins[1].prev_outpoint()),
+            Some(&ExclusionReason::Frozen)
+        );
+        for coin in [&coins[0], &coins[2]] {
+            assert_eq!(
+                candidates.exclusion_reason(coin.prev_outpoint()),
+                Some(&ExclusionReason::Unconfirmed)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_updates() -> anyhow::Result<()> {
+        let coins = inputs(&[0, 1, 1, 2, 3, 4, 4])?;
+        // `Candidate` is not `PartialEq`, so compare the debug output.
+        let is_consistent = |candidates: &InputCandidates| {
+            let rebuilt = InputCandidates::build_cs_candidates(
+                &candidates.must_select,
+                &candidates.can_select,
+            );
+            let indexed = candidates
+                .must_select
+                .iter()
+                .flat_map(InputGroup::inputs)
+                .all(|input| candidates.group_of.get(&input.prev_outpoint()) == Some(&None))
+                && candidates.can_select.iter().enumerate().all(|(i, group)| {
+                    group.inputs().iter().all(|input| {
+                        candidates.group_of.get(&input.prev_outpoint()) == Some(&Some(i))
+                    })
+                });
+            format!("{:?}", candidates.coin_select_candidates()) == format!("{rebuilt:?}")
+                && indexed
+                && candidates.group_of.len() == candidates.inputs().count()
+        };
+        let mut candidates =
+            InputCandidates::new([coins[0].clone()], coins[1..4].to_vec()).regroup(group_by_spk());
+        assert!(is_consistent(&candidates));
+        assert!(!candidates.insert(coins[1].clone()));
+        assert!(candidates.insert(coins[4].clone()));
+        assert!(is_consistent(&candidates));
+
+        // Remove from a group of two, then empty the must-select group.
+        let removed = candidates.remove(coins[2].prev_outpoint());
+        assert_eq!(
+            removed.map(|input| input.prev_outpoint()),
+            Some(coins[2].prev_outpoint())
+        );
+        assert!(is_consistent(&candidates));
+        assert!(candidates.remove(coins[0].prev_outpoint()).is_some());
+        assert!(candidates.must_select().is_none());
+        assert!(candidates.remove(coins[0].prev_outpoint()).is_none());
+        assert!(is_consistent(&candidates));
+
+        // Emptying a group moves the last group into its place.
+        assert!(candidates.insert(coins[5].clone()));
+        assert!(candidates.remove(coins[1].prev_outpoint()).is_some());
+        let first = candidates.can_select()[0].inputs()[0].prev_outpoint();
+        assert_eq!(first, coins[5].prev_outpoint());
+        assert!(is_consistent(&candidates));
+
+        let excluded = coins[3].prev_outpoint();
+        let mut candidates = candidates.filter(|input| input.prev_outpoint() != excluded);
+        assert_eq!(candidates.inputs().count(), 2);
+        assert!(is_consistent(&candidates));
+        assert!(candidates.remove(coins[4].prev_outpoint()).is_some());
+        assert!(is_consistent(&candidates));
+
+        // Regrouping merges the metadata of whole groups and splits the others.
+        let candidates = InputCandidates::new([coins[0].clone()], coins[1..].to_vec())
+            .regroup(group_by_spk())
+            .regroup(|input| input.prev_outpoint().txid);
+        assert_eq!(candidates.can_select().len(), 6);
+        assert!(is_consistent(&candidates));
+        let candidates = InputCandidates::new([coins[1].clone()], coins[2..].to_vec())
+            .regroup(group_by_spk_with_max_entries(1))
+            .regroup(group_by_spk());
+        assert_eq!(candidates.must_select().map(|g| g.inputs().len()), Some(2));
+        assert!(is_consistent(&candidates));
+        Ok(())
+    }
+}
@@ -0,0 +1,996 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::{
+    absolute, psbt, transaction, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Sequence,
+    SignedAmount, Transaction, TxOut, Weight,
+};
+use miniscript::bitcoin;
+
+use crate::collections::{BTreeMap, VecDeque};
+use crate::input::{COMMON_FIELDS_WEIGHT, TXIN_BASE_WEIGHT};
+use crate::{CreatePsbtError, Finalizer, Input, Output, PsbtParams, Selection};
+
+/// Maximum number of inputs or outputs of a negotiated transaction.
+pub const INTERACTIVE_TX_MAX_INPUTS_OUTPUTS: usize = 252;
+
+/// Maximum number of messages accepted from the counterparty during a negotiation.
+pub const INTERACTIVE_TX_MAX_RECEIVED_MESSAGES: usize = 4096;
+
+/// Maximum standard transaction weight.
+const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+
+/// Parameters shared by both parties of an interactive transaction construction.
+///
+/// Both parties must agree on `feerate`, `lock_time` and the shared output (usually out-of-band,
+/// e.g. in `open_channel2`/`accept_channel2`), each from their own perspective.
+#[derive(Debug, Clone)]
+pub struct InteractiveTxParams {
+    /// Whether we initiated the negotiation. The initiator sends the first message, adds the
+    /// shared output and pays for the common transaction fields.
+    pub is_initiator: bool,
+
+    /// Feerate each party pays for its own contributions.
+    pub feerate: FeeRate,
+
+    /// Locktime of the negotiated transaction.
+    pub lock_time: absolute::LockTime,
+
+    /// Script pubkey of the shared output (e.g. the channel funding output), if any.
+    ///
+    /// The shared output is added by the initiator and its value is the sum of
+    /// `our_shared_contribution` and `their_shared_contribution`.
+    pub shared_script_pubkey: Option<ScriptBuf>,
+
+    /// Amount we contribute to the shared output.
+    pub our_shared_contribution: Amount,
+
+    /// Amount the counterparty contributes to the shared output.
+    pub their_shared_contribution: Amount,
+}
+
+impl InteractiveTxParams {
+    /// Value of the shared output.
+    pub fn shared_output_value(&self) -> Amount {
+        self.our_shared_contribution + self.their_shared_contribution
+    }
+
+    /// Fee we pay when contributing `inputs` and `outputs`.
+    ///
+    /// Use this to size change outputs before creating the [`InteractiveTxConstructor`].
+    pub fn our_fee(&self, inputs: &[Input], outputs: &[Output]) -> Amount {
+        let input_weight = inputs
+            .iter()
+            .map(|input| TXIN_BASE_WEIGHT + input.satisfaction_weight())
+            .sum::<u64>();
+        let output_weight = outputs
+            .iter()
+            .map(|output| output.txout().weight().to_wu())
+            .sum::<u64>();
+        let initiator_weight = match (&self.shared_script_pubkey, self.is_initiator) {
+            (_, false) => 0,
+            (None, true) => COMMON_FIELDS_WEIGHT,
+            (Some(spk), true) => {
+                COMMON_FIELDS_WEIGHT
+                    + TxOut {
+                        value: self.shared_output_value(),
+                        script_pubkey: spk.clone(),
+                    }
+                    .weight()
+                    .to_wu()
+            }
+        };
+        self.feerate
+            .fee_wu(Weight::from_wu(
+                input_weight + output_weight + initiator_weight,
+            ))
+            .expect("fee must not overflow")
+    }
+}
+
+/// A message of the interactive transaction construction protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteractiveTxMessage {
+    /// Add an input spending output `prev_vout` of `prev_tx` (`tx_add_input`).
+    AddInput {
+        /// Serial ID of the input.
+        serial_id: u64,
+        /// Previous transaction.
+        prev_tx: Transaction,
+        /// Index of the spent output in `prev_tx`.
+        prev_vout: u32,
+        /// The `nSequence` of the input.
+        sequence: Sequence,
+    },
+    /// Add an output (`tx_add_output`).
+    AddOutput {
+        /// Serial ID of the output.
+        serial_id: u64,
+        /// Value of the output.
+        value: Amount,
+        /// Script pubkey of the output.
+        script_pubkey: ScriptBuf,
+    },
+    /// Remove a previously added input (`tx_remove_input`).
+    RemoveInput {
+        /// Serial ID of the input.
+        serial_id: u64,
+    },
+    /// Remove a previously added output (`tx_remove_output`).
+    RemoveOutput {
+        /// Serial ID of the output.
+        serial_id: u64,
+    },
+    /// The sender has nothing more to add (`tx_complete`).
+    Complete,
+}
+
+/// Error of the interactive transaction construction.
+#[derive(Debug)]
+pub enum InteractiveTxError {
+    /// The message was received out of turn or after the negotiation completed.
+    UnexpectedMessage,
+    /// The serial ID does not have the counterparty's parity.
+    InvalidSerialIdParity(u64),
+    /// The serial ID is already in use.
+    DuplicateSerialId(u64),
+    /// No input or output with this serial ID was added by the counterparty.
+    UnknownSerialId(u64),
+    /// The previous output of the input with this serial ID does not exist.
+    InvalidPrevOutput(u64),
+    /// The previous output is not a native segwit output.
+    NonSegwitInput(OutPoint),
+    /// The counterparty's input spends an output whose satisfaction weight cannot be estimated,
+    /// so the counterparty could not be made to pay for it. Only P2WPKH and P2TR are supported.
+    UnsupportedInputType(OutPoint),
+    /// The previous output is already spent by another input.
+    DuplicateInput(OutPoint),
+    /// The sequence of the input with this serial ID does not signal replaceability.
+    InvalidSequence(u64),
+    /// The output with this serial ID is dust or exceeds the money supply.
+    InvalidOutputValue(u64),
+    /// Our input is missing its previous transaction.
+    MissingPrevTx(OutPoint),
+    /// Our input requires an absolute locktime not implied by the agreed locktime.
+    IncompatibleLockTime(OutPoint),
+    /// Our output at this index pays to a silent payment address that is not resolved, see
+    /// [`Selection::resolve_silent_payments`].
+    UnresolvedSilentPayment(usize),
+    /// The counterparty sent too many messages.
+    TooManyMessages,
+    /// The transaction has too many inputs or outputs.
+    TooManyInputsOrOutputs,
+    /// The transaction exceeds the maximum standard weight.
+    TransactionTooHeavy,
+    /// The shared output is missing, duplicated or has an unexpected value.
+    InvalidSharedOutput,
+    /// Our contribution does not pay for its own weight at the agreed feerate.
+    OurContributionTooLow {
+        /// Fee required.
+        required: Amount,
+        /// Amount available for fees.
+        available: SignedAmount,
+    },
+    /// The counterparty's contribution does not pay for its own weight at the agreed feerate.
+    TheirContributionTooLow {
+        /// Fee required.
+        required: Amount,
+        /// Amount available for fees.
+        available: SignedAmount,
+    },
+    /// The negotiation is not complete, or was aborted.
+    Incomplete,
+    /// Failed to create the PSBT.
+    CreatePsbt(CreatePsbtError),
+}
+
+impl fmt::Display for InteractiveTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedMessage => write!(f, "unexpected message"),
+            Self::InvalidSerialIdParity(id) => write!(f, "serial id {id} has invalid parity"),
+            Self::DuplicateSerialId(id) => write!(f, "duplicate serial id {id}"),
+            Self::UnknownSerialId(id) => write!(f, "unknown serial id {id}"),
+            Self::InvalidPrevOutput(id) => {
+                write!(f, "previous output of input {id} does not exist")
+            }
+            Self::NonSegwitInput(op) => write!(f, "input {op} is not native segwit"),
+            Self::UnsupportedInputType(op) => {
+                write!(f, "weight of input {op} cannot be estimated")
+            }
+            Self::DuplicateInput(op) => write!(f, "input {op} is added twice"),
+            Self::InvalidSequence(id) => write!(f, "input {id} does not signal replaceability"),
+            Self::InvalidOutputValue(id) => write!(f, "output {id} has invalid value"),
+            Self::MissingPrevTx(op) => write!(f, "input {op} is missing previous transaction"),
+            Self::IncompatibleLockTime(op) => {
+                write!(f, "input {op} requires an incompatible locktime")
+            }
+            Self::UnresolvedSilentPayment(index) => {
+                write!(
+                    f,
+                    "output {index} pays to an unresolved silent payment address"
+                )
+            }
+            Self::TooManyMessages => write!(f, "too many messages received"),
+            Self::TooManyInputsOrOutputs => write!(f, "too many inputs or outputs"),
+            Self::TransactionTooHeavy => write!(f, "transaction exceeds maximum weight"),
+            Self::InvalidSharedOutput => write!(f, "invalid shared output"),
+            Self::OurContributionTooLow {
+                required,
+                available,
+            } => write!(
+                f,
+                "our contribution pays {available} for fees but {required} is required"
+            ),
+            Self::TheirContributionTooLow {
+                required,
+                available,
+            } => write!(
+                f,
+                "counterparty contribution pays {available} for fees but {required} is required"
+            ),
+            Self::Incomplete => write!(f, "negotiation is not complete"),
+            Self::CreatePsbt(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InteractiveTxError {}
+
+/// Transport-agnostic state machine for BOLT-2 style interactive transaction construction.
+///
+/// Both parties take turns sending a single message until both send
+/// [`InteractiveTxMessage::Complete`] consecutively. The initiator uses even serial IDs and the
+/// counterparty odd ones; the negotiated transaction orders inputs and outputs by serial ID.
+///
+/// Each party pays for the weight of its own inputs and outputs at the agreed feerate. The
+/// initiator also pays for the common transaction fields and the shared output. As the
+/// counterparty's witnesses are not known, their inputs are weighed assuming P2WPKH or P2TR
+/// key-path spends, and their inputs of other types are rejected with
+/// [`InteractiveTxError::UnsupportedInputType`].
+///
+/// ```rust,ignore
+/// let mut msg = initiator.start()?;
+/// while let Some(reply) = acceptor.handle_message(msg)? {
+///     match initiator.handle_message(reply)? {
+///         Some(next) => msg = next,
+///         None => break,
+///     }
+/// }
+/// let psbt = initiator.create_psbt()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct InteractiveTxConstructor {
+    params: InteractiveTxParams,
+    inputs: BTreeMap<u64, Input>,
+    outputs: BTreeMap<u64, Output>,
+    to_send: VecDeque<InteractiveTxMessage>,
+    started: bool,
+    sent_complete: bool,
+    is_complete: bool,
+    is_aborted: bool,
+    received_messages: usize,
+}
+
+impl InteractiveTxConstructor {
+    /// Create with our contributed `inputs` and `outputs`.
+    ///
+    /// The shared output is added automatically by the initiator and must not be part of
+    /// `outputs`. Our inputs must be native segwit and include their previous transaction, and
+    /// silent payment outputs must be resolved.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an input or output is not eligible, or if our contribution does not pay for
+    /// [`InteractiveTxParams::our_fee`].
+    pub fn new(
+        params: InteractiveTxParams,
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+    ) -> Result<Self, InteractiveTxError> {
+        for input in &inputs {
+            let outpoint = input.prev_outpoint();
+            let prev_tx = input
+                .prev_tx()
+                .ok_or(InteractiveTxError::MissingPrevTx(outpoint))?;
+            if !prev_tx.output[outpoint.vout as usize]
+                .script_pubkey
+                .is_witness_program()
+            {
+                return Err(InteractiveTxError::NonSegwitInput(outpoint));
+            }
+            if let Some(lock_time) = input.absolute_timelock() {
+                if !lock_time.is_implied_by(params.lock_time) {
+                    return Err(InteractiveTxError::IncompatibleLockTime(outpoint));
+                }
+            }
+        }
+        if let Some(index) = outputs
+            .iter()
+            .position(|output| output.silent_payment().is_some())
+        {
+            return Err(InteractiveTxError::UnresolvedSilentPayment(index));
+        }
+        if inputs.len() > INTERACTIVE_TX_MAX_INPUTS_OUTPUTS
+            || outputs.len() > INTERACTIVE_TX_MAX_INPUTS_OUTPUTS
+        {
+            return Err(InteractiveTxError::TooManyInputsOrOutputs);
+        }
+
+        let required = params.our_fee(&inputs, &outputs);
+        let available = contribution(
+            inputs.iter().map(|input| input.prev_txout().value),
+            outputs.iter().map(|output| output.value),
+            params.our_shared_contribution,
+        );
+        if available < required.to_signed().expect("fee must fit") {
+            return Err(InteractiveTxError::OurContributionTooLow {
+                required,
+                available,
+            });
+        }
+
+        let parity = u64::from(!params.is_initiator);
+        let mut serial_ids = (0..).map(move |i: u64| 2 * i + parity);
+        let mut constructor = Self {
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            to_send: VecDeque::new(),
+            started: false,
+            sent_complete: false,
+            is_complete: false,
+            is_aborted: false,
+            received_messages: 0,
+            params,
+        };
+        for input in inputs {
+            let serial_id = serial_ids.next().expect("infinite iterator");
+            let outpoint = input.prev_outpoint();
+            constructor
+                .to_send
+                .push_back(InteractiveTxMessage::AddInput {
+                    serial_id,
+                    prev_tx: input.prev_tx().expect("checked above").clone(),
+                    prev_vout: outpoint.vout,
+                    sequence: input.sequence().unwrap_or(Sequence::ENABLE_RBF_NO_LOCKTIME),
+                });
+            constructor.inputs.insert(serial_id, input);
+        }
+        let shared_output = match (
+            &constructor.params.shared_script_pubkey,
+            constructor.params.is_initiator,
+        ) {
+            (Some(spk), true) => Some(Output::with_script(
+                spk.clone(),
+                constructor.params.shared_output_value(),
+            )),
+            _ => None,
+        };
+        for output in shared_output.into_iter().chain(outputs) {
+            let serial_id = serial_ids.next().expect("infinite iterator");
+            constructor
+                .to_send
+                .push_back(InteractiveTxMessage::AddOutput {
+                    serial_id,
+                    value: output.value,
+                    script_pubkey: output.script_pubkey(),
+                });
+            constructor.outputs.insert(serial_id, output);
+        }
+        Ok(constructor)
+    }
+
+    /// Parameters of the negotiation.
+    pub fn params(&self) -> &InteractiveTxParams {
+        &self.params
+    }
+
+    /// Whether both parties have sent [`InteractiveTxMessage::Complete`] and the negotiated
+    /// transaction is valid.
+    pub fn is_complete(&self) -> bool {
+        self.is_complete
+    }
+
+    /// Whether the negotiation was aborted because of an invalid message or transaction.
+    pub fn is_aborted(&self) -> bool {
+        self.is_aborted
+    }
+
+    /// Send the first message of the negotiation. Only the initiator starts the negotiation.
+    pub fn start(&mut self) -> Result<InteractiveTxMessage, InteractiveTxError> {
+        if !self.params.is_initiator || self.started {
+            return Err(InteractiveTxError::UnexpectedMessage);
+        }
+        self.started = true;
+        Ok(self.next_message())
+    }
+
+    /// Handle a message from the counterparty and return our reply.
+    ///
+    /// Returns `None` once the negotiation is complete and no reply is due. The negotiated
+    /// transaction is validated when the negotiation completes.
+    ///
+    /// # Errors
+    ///
+    /// Any error aborts the negotiation, after which every message is rejected with
+    /// [`InteractiveTxError::UnexpectedMessage`].
+    pub fn handle_message(
+        &mut self,
+        message: InteractiveTxMessage,
+    ) -> Result<Option<InteractiveTxMessage>, InteractiveTxError> {
+        if self.is_complete || self.is_aborted || (self.params.is_initiator && !self.started) {
+            return Err(InteractiveTxError::UnexpectedMessage);
+        }
+        let result = self.apply_message(message);
+        self.is_aborted = result.is_err();
+        result
+    }
+
+    fn apply_message(
+        &mut self,
+        message: InteractiveTxMessage,
+    ) -> Result<Option<InteractiveTxMessage>, InteractiveTxError> {
+        self.received_messages += 1;
+        if self.received_messages > INTERACTIVE_TX_MAX_RECEIVED_MESSAGES {
+            return Err(InteractiveTxError::TooManyMessages);
+        }
+        self.started = true;
+
+        let received_complete = match message {
+            InteractiveTxMessage::AddInput {
+                serial_id,
+                prev_tx,
+                prev_vout,
+                sequence,
+            } => {
+                self.check_new_serial_id(serial_id, self.inputs.contains_key(&serial_id))?;
+                let outpoint = OutPoint::new(prev_tx.compute_txid(), prev_vout);
+                let prev_txout = prev_tx
+                    .output
+                    .get(prev_vout as usize)
+                    .cloned()
+                    .ok_or(InteractiveTxError::InvalidPrevOutput(serial_id))?;
+                if !prev_txout.script_pubkey.is_witness_program() {
+                    return Err(InteractiveTxError::NonSegwitInput(outpoint));
+                }
+                if self
+                    .inputs
+                    .values()
+                    .any(|input| input.prev_outpoint() == outpoint)
+                {
+                    return Err(InteractiveTxError::DuplicateInput(outpoint));
+                }
+                if !sequence.is_rbf() {
+                    return Err(InteractiveTxError::InvalidSequence(serial_id));
+                }
+                let satisfaction_weight = estimate_satisfaction_weight(&prev_txout)
+                    .ok_or(InteractiveTxError::UnsupportedInputType(outpoint))?;
+                let is_coinbase = prev_tx.is_coinbase();
+                let psbt_input = psbt::Input {
+                    non_witness_utxo: Some(prev_tx),
+                    witness_utxo: Some(prev_txout),
+                    ..Default::default()
+                };
+                let input = Input::from_psbt_input(
+                    outpoint,
+                    sequence,
+                    psbt_input,
+                    satisfaction_weight,
+                    None,
+                    is_coinbase,
+                    None,
+                )
+                .map_err(|_| InteractiveTxError::InvalidPrevOutput(serial_id))?;
+                self.inputs.insert(serial_id, input);
+                false
+            }
+            InteractiveTxMessage::AddOutput {
+                serial_id,
+                value,
+                script_pubkey,
+            } => {
+                self.check_new_serial_id(serial_id, self.outputs.contains_key(&serial_id))?;
+                if value > Amount::MAX_MONEY || value < script_pubkey.minimal_non_dust() {
+                    return Err(InteractiveTxError::InvalidOutputValue(serial_id));
+                }
+                self.outputs
+                    .insert(serial_id, Output::with_script(script_pubkey, value));
+                false
+            }
+            InteractiveTxMessage::RemoveInput { serial_id } => {
+                self.check_their_serial_id(serial_id)?;
+                self.inputs
+                    .remove(&serial_id)
+                    .ok_or(InteractiveTxError::UnknownSerialId(serial_id))?;
+                false
+            }
+            InteractiveTxMessage::RemoveOutput { serial_id } => {
+                self.check_their_serial_id(serial_id)?;
+                self.outputs
+                    .remove(&serial_id)
+                    .ok_or(InteractiveTxError::UnknownSerialId(serial_id))?;
+                false
+            }
+            InteractiveTxMessage::Complete => true,
+        };
+
+        if received_complete && self.sent_complete {
+            self.complete()?;
+            return Ok(None);
+        }
+        let reply = self.next_message();
+        if received_complete && self.sent_complete {
+            self.complete()?;
+        }
+        Ok(Some(reply))
+    }
+
+    /// Fee we pay for our contribution.
+    pub fn our_fee(&self) -> Amount {
+        let (inputs, outputs) = self.contributions(true);
+        self.params.our_fee(&inputs, &outputs)
+    }
+
+    /// Create the PSBT of the negotiated transaction for signing.
+    ///
+    /// Our inputs are updated from their plans; the counterparty's inputs only contain the
+    /// previous transaction and output.
+    pub fn create_psbt(&self) -> Result<Psbt, InteractiveTxError> {
+        if !self.is_complete {
+            return Err(InteractiveTxError::Incomplete);
+        }
+        let selection = self.selection();
+        let params = PsbtParams {
+            version: transaction::Version::TWO,
+            min_locktime: self.params.lock_time,
+            mandate_full_tx_for_segwit_v0: false,
+            anti_fee_sniping: None,
+            ..Default::default()
+        };
+        selection
+            .unsigned_tx(&params)
+            .and_then(|tx| selection.psbt_from_unsigned_tx(tx, &params))
+            .map_err(InteractiveTxError::CreatePsbt)
+    }
+
+    /// Into a [`Finalizer`] for our inputs.
+    ///
+    /// The counterparty's inputs are left for the counterparty to finalize.
+    pub fn into_finalizer(self) -> Finalizer {
+        Finalizer::new(
+            self.inputs
+                .values()
+                .filter_map(|input| Some((input.prev_outpoint(), input.plan().cloned()?))),
+        )
+    }
+
+    fn selection(&self) -> Selection {
+        Selection::new(
+            self.inputs.values().cloned().collect(),
+            self.outputs.values().cloned().collect(),
+        )
+    }
+
+    fn next_message(&mut self) -> InteractiveTxMessage {
+        let message = self
+            .to_send
+            .pop_front()
+            .unwrap_or(InteractiveTxMessage::Complete);
+        self.sent_complete = message == InteractiveTxMessage::Complete;
+        message
+    }
+
+    fn is_ours(&self, serial_id: u64) -> bool {
+        (serial_id % 2 == 0) == self.params.is_initiator
+    }
+
+    fn check_their_serial_id(&self, serial_id: u64) -> Result<(), InteractiveTxError> {
+        if self.is_ours(serial_id) {
+            return Err(InteractiveTxError::InvalidSerialIdParity(serial_id));
+        }
+        Ok(())
+    }
+
+    fn check_new_serial_id(&self, serial_id: u64, exists: bool) -> Result<(), InteractiveTxError> {
+        self.check_their_serial_id(serial_id)?;
+        if exists {
+            return Err(InteractiveTxError::DuplicateSerialId(serial_id));
+        }
+        Ok(())
+    }
+
+    /// Inputs and outputs contributed by us (`ours`) or the counterparty, excluding the shared
+    /// output.
+    fn contributions(&self, ours: bool) -> (Vec<Input>, Vec<Output>) {
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|(&serial_id, _)| self.is_ours(serial_id) == ours)
+            .map(|(_, input)| input.clone())
+            .collect();
+        let shared_serial_id = self.shared_output_serial_id();
+        let outputs = self
+            .outputs
+            .iter()
+            .filter(|(&serial_id, _)| {
+                self.is_ours(serial_id) == ours && Some(serial_id) != shared_serial_id
+            })
+            .map(|(_, output)| output.clone())
+            .collect();
+        (inputs, outputs)
+    }
+
+    fn shared_output_serial_id(&self) -> Option<u64> {
+        let spk = self.params.shared_script_pubkey.as_ref()?;
+        self.outputs
+            .iter()
+            .find(|(_, output)| output.script_pubkey() == *spk)
+            .map(|(&serial_id, _)| serial_id)
+    }
+
+    /// Validate the negotiated transaction.
+    fn complete(&mut self) -> Result<(), InteractiveTxError> {
+        if self.inputs.len() > INTERACTIVE_TX_MAX_INPUTS_OUTPUTS
+            || self.outputs.len() > INTERACTIVE_TX_MAX_INPUTS_OUTPUTS
+        {
+            return Err(InteractiveTxError::TooManyInputsOrOutputs);
+        }
+
+        if let Some(spk) = &self.params.shared_script_pubkey {
+            let mut shared_outputs = self
+                .outputs
+                .iter()
+                .filter(|(_, output)| output.script_pubkey() == *spk);
+            match (shared_outputs.next(), shared_outputs.next()) {
+                (Some((&serial_id, output)), None)
+                    if (serial_id % 2 == 0)
+                        && output.value == self.params.shared_output_value() => {}
+                _ => return Err(InteractiveTxError::InvalidSharedOutput),
+            }
+        }
+
+        // Our contribution was checked on creation, so only theirs is checked here.
+        let (inputs, outputs) = self.contributions(false);
+        let their_params = InteractiveTxParams {
+            is_initiator: !self.params.is_initiator,
+            ..self.params.clone()
+        };
+        let required = their_params.our_fee(&inputs, &outputs);
+        let available = contribution(
+            inputs.iter().map(|input| input.prev_txout().value),
+            outputs.iter().map(|output| output.value),
+            self.params.their_shared_contribution,
+        );
+        if available < required.to_signed().expect("fee must fit") {
+            return Err(InteractiveTxError::TheirContributionTooLow {
+                required,
+                available,
+            });
+        }
+
+        let weight = COMMON_FIELDS_WEIGHT
+            + self
+                .inputs
+                .values()
+                .map(|input| TXIN_BASE_WEIGHT + input.satisfaction_weight())
+                .sum::<u64>()
+            + self
+                .outputs
+                .values()
+                .map(|output| output.txout().weight().to_wu())
+                .sum::<u64>();
+        if weight > MAX_STANDARD_TX_WEIGHT {
+            return Err(InteractiveTxError::TransactionTooHeavy);
+        }
+        self.is_complete = true;
+        Ok(())
+    }
+}
+
+/// Amount a party leaves for fees: its inputs minus its outputs and shared contribution.
+fn contribution(
+    input_values: impl Iterator<Item = Amount>,
+    output_values: impl Iterator<Item = Amount>,
+    shared_contribution: Amount,
+) -> SignedAmount {
+    let to_signed = |amount: Amount| amount.to_signed().expect("amount must fit");
+    input_values.map(to_signed).sum::<SignedAmount>()
+        - output_values.map(to_signed).sum::<SignedAmount>()
+        - to_signed(shared_contribution)
+}
+
+/// Estimate the satisfaction weight of a counterparty input whose witness is unknown.
+///
+/// Returns `None` if the witness depends on a script that is not known.
+fn estimate_satisfaction_weight(prev_txout: &TxOut) -> Option<usize> {
+    let spk = &prev_txout.script_pubkey;
+    if spk.is_p2wpkh() {
+        // Witness item count, signature with sighash flag and compressed public key.
+        Some(1 + 1 + 72 + 1 + 33)
+    } else if spk.is_p2tr() {
+        // Witness item count and key-path schnorr signature.
+        Some(1 + 1 + 64)
+    } else {
+        None
+    }
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, input, parse_descriptor, prev_tx, TR_XPRV, WPKH_XPRV_2};
+    use crate::{Signer, SilentPaymentAddress};
+    use bitcoin::{
+        hashes::Hash,
+        secp256k1::{PublicKey, Secp256k1, SecretKey},
+        WScriptHash,
+    };
+    use miniscript::{plan::Assets, Descriptor};
+
+    struct Party {
+        descriptor: Descriptor<miniscript::DescriptorPublicKey>,
+        signer: Signer,
+        assets: Assets,
+    }
+
+    impl Party {
+        fn new(descriptor: &str) -> anyhow::Result<Self> {
+            let (descriptor, keymap) = parse_descriptor(descriptor)?;
+            Ok(Self {
+                descriptor,
+                assets: assets(&keymap),
+                signer: Signer(keymap),
+            })
+        }
+
+        fn input(&self, index: u32, value: Amount) -> anyhow::Result<Input> {
+            let desc = self.descriptor.at_derivation_index(index)?;
+            input(&desc, &self.assets, value, index, None)
+        }
+
+        fn change(&self, index: u32, value: Amount) -> anyhow::Result<Output> {
+            Ok(Output::with_descriptor(
+                self.descriptor.at_derivation_index(index)?,
+                value,
+            ))
+        }
+    }
+
+    fn params(is_initiator: bool, ours: u64, theirs: u64) -> InteractiveTxParams {
+        InteractiveTxParams {
+            is_initiator,
+            feerate: FeeRate::from_sat_per_vb_u32(5),
+            lock_time: absolute::LockTime::from_height(100).expect("valid height"),
+            shared_script_pubkey: Some(ScriptBuf::new_p2wsh(&WScriptHash::all_zeros())),
+            our_shared_contribution: Amount::from_sat(ours),
+            their_shared_contribution: Amount::from_sat(theirs),
+        }
+    }
+
+    /// Create a constructor contributing one input and a change output paying exactly our fee.
+    fn constructor(
+        party: &Party,
+        params: InteractiveTxParams,
+    ) -> anyhow::Result<InteractiveTxConstructor> {
+        let input = party.input(0, Amount::from_sat(100_000))?;
+        let mut change = party.change(1, Amount::ZERO)?;
+        let fee = params.our_fee(
+            core::slice::from_ref(&input),
+            core::slice::from_ref(&change),
+        );
+        change.value = input.prev_txout().value - params.our_shared_contribution - fee;
+        Ok(InteractiveTxConstructor::new(
+            params,
+            vec![input],
+            vec![change],
+        )?)
+    }
+
+    fn negotiate(
+        initiator: &mut InteractiveTxConstructor,
+        acceptor: &mut InteractiveTxConstructor,
+    ) -> Result<(), InteractiveTxError> {
+        let mut message = initiator.start()?;
+        while let Some(reply) = acceptor.handle_message(message)? {
+            match initiator.handle_message(reply)? {
+                Some(next) => message = next,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dual_funded_negotiation() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let alice = Party::new(TR_XPRV)?;
+        let bob = Party::new(WPKH_XPRV_2)?;
+        let mut initiator = constructor(&alice, params(true, 60_000, 40_000))?;
+        let mut acceptor = constructor(&bob, params(false, 40_000, 60_000))?;
+
+        negotiate(&mut initiator, &mut acceptor)?;
+        assert!(initiator.is_complete());
+        assert!(acceptor.is_complete());
+
+        let mut psbt = initiator.create_psbt()?;
+        let bob_psbt = acceptor.create_psbt()?;
+        assert_eq!(psbt.unsigned_tx, bob_psbt.unsigned_tx);
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+        assert_eq!(psbt.unsigned_tx.output.len(), 3);
+        assert_eq!(psbt.unsigned_tx.lock_time, initiator.params().lock_time);
+        // Ordered by serial ID: alice's input (0), then bob's (1).
+        assert!(psbt.inputs[0]
+            .witness_utxo
+            .as_ref()
+            .unwrap()
+            .script_pubkey
+            .is_p2tr());
+        assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(100_000));
+
+        let fee = initiator.our_fee() + acceptor.our_fee();
+        assert_eq!(psbt.fee()?, fee);
+
+        let _ = psbt.sign(&alice.signer, &secp);
+        let mut bob_psbt = bob_psbt;
+        let _ = bob_psbt.sign(&bob.signer, &secp);
+        psbt.combine(bob_psbt)?;
+        // Each party only finalizes its own inputs.
+        let results = initiator.into_finalizer().finalize(&mut psbt).results();
+        assert!(matches!(results[&0], Ok(true)));
+        assert!(matches!(results[&1], Ok(false)));
+        assert!(acceptor.into_finalizer().finalize(&mut psbt).is_finalized());
+        let tx = psbt.extract_tx()?;
+        assert!(fee / tx.weight() >= FeeRate::from_sat_per_vb_u32(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_invalid_messages() -> anyhow::Result<()> {
+        let alice = Party::new(TR_XPRV)?;
+        let bob = Party::new(WPKH_XPRV_2)?;
+        let input = alice.input(0, Amount::from_sat(100_000))?;
+        let add_input = |serial_id| InteractiveTxMessage::AddInput {
+            serial_id,
+            prev_tx: input.prev_tx().unwrap().clone(),
+            prev_vout: 0,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        };
+        let handle = |messages: Vec<InteractiveTxMessage>| -> anyhow::Result<_> {
+            let mut acceptor = constructor(&bob, params(false, 40_000, 60_000))?;
+            let mut result = Ok(None);
+            for message in messages {
+                result = acceptor.handle_message(message);
+            }
+            assert_eq!(acceptor.is_aborted(), result.is_err());
+            Ok(result)
+        };
+
+        assert!(matches!(
+            handle(vec![add_input(0)])?,
+            Ok(Some(InteractiveTxMessage::AddInput { serial_id: 1, .. }))
+        ));
+        assert!(matches!(
+            handle(vec![add_input(1)])?,
+            Err(InteractiveTxError::InvalidSerialIdParity(1))
+        ));
+        assert!(matches!(
+            handle(vec![add_input(0), add_input(0)])?,
+            Err(InteractiveTxError::DuplicateSerialId(0))
+        ));
+        assert!(matches!(
+            handle(vec![add_input(0), add_input(2)])?,
+            Err(InteractiveTxError::DuplicateInput(op)) if op == input.prev_outpoint()
+        ));
+        assert!(matches!(
+            handle(vec![InteractiveTxMessage::AddOutput {
+                serial_id: 4,
+                value: Amount::from_sat(1),
+                script_pubkey: input.prev_txout().script_pubkey.clone(),
+            }])?,
+            Err(InteractiveTxError::InvalidOutputValue(4))
+        ));
+        assert!(matches!(
+            handle(vec![InteractiveTxMessage::RemoveOutput { serial_id: 6 }])?,
+            Err(InteractiveTxError::UnknownSerialId(6))
+        ));
+        assert!(matches!(
+            handle(vec![add_input(1), add_input(0)])?,
+            Err(InteractiveTxError::UnexpectedMessage)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_input_of_unknown_weight() -> anyhow::Result<()> {
+        let bob = Party::new(WPKH_XPRV_2)?;
+        let mut acceptor = constructor(&bob, params(false, 40_000, 60_000))?;
+        // A P2WSH multisig input would be weighed without its witness and paid for by us.
+        let prev_tx = prev_tx(
+            7,
+            vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()),
+            }],
+        );
+        let outpoint = OutPoint::new(prev_tx.compute_txid(), 0);
+        assert!(matches!(
+            acceptor.handle_message(InteractiveTxMessage::AddInput {
+                serial_id: 0,
+                prev_tx,
+                prev_vout: 0,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            }),
+            Err(InteractiveTxError::UnsupportedInputType(op)) if op == outpoint
+        ));
+        assert!(acceptor.is_aborted());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_unresolved_silent_payment() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let alice = Party::new(TR_XPRV)?;
+        let key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3_u8; 32])?);
+        let outputs = vec![
+            alice.change(1, Amount::from_sat(10_000))?,
+            Output::with_silent_payment(
+                SilentPaymentAddress::new(key, key),
+                Amount::from_sat(10_000),
+            ),
+        ];
+        // The placeholder script of the silent payment must not be sent to the counterparty.
+        assert!(matches!(
+            InteractiveTxConstructor::new(
+                params(true, 60_000, 40_000),
+                vec![alice.input(0, Amount::from_sat(100_000))?],
+                outputs,
+            ),
+            Err(InteractiveTxError::UnresolvedSilentPayment(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_underpaying_counterparty() -> anyhow::Result<()> {
+        let alice = Party::new(TR_XPRV)?;
+        let bob = Party::new(WPKH_XPRV_2)?;
+        let mut acceptor = constructor(&bob, params(false, 40_000, 60_000))?;
+        let input = alice.input(0, Amount::from_sat(100_000))?;
+        let messages = [
+            InteractiveTxMessage::AddInput {
+                serial_id: 0,
+                prev_tx: input.prev_tx().unwrap().clone(),
+                prev_vout: 0,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            },
+            InteractiveTxMessage::AddOutput {
+                serial_id: 2,
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()),
+            },
+            // Alice keeps all remaining funds as change and pays no fee.
+            InteractiveTxMessage::AddOutput {
+                serial_id: 4,
+                value: Amount::from_sat(40_000),
+                script_pubkey: input.prev_txout().script_pubkey.clone(),
+            },
+        ];
+        for message in messages {
+            assert!(acceptor.handle_message(message)?.is_some());
+        }
+        // Bob sent `Complete` in reply to the last output, so this ends the negotiation.
+        assert!(matches!(
+            acceptor.handle_message(InteractiveTxMessage::Complete),
+            Err(InteractiveTxError::TheirContributionTooLow { available, .. })
+                if available == SignedAmount::ZERO
+        ));
+        assert!(!acceptor.is_complete());
+        assert!(matches!(
+            acceptor.create_psbt(),
+            Err(InteractiveTxError::Incomplete)
+        ));
+        Ok(())
+    }
+}
@@ -13,30 +13,56 @@ pub extern crate bdk_coin_select;
 
 mod afs;
 mod canonical_unspents;
+mod coinjoin;
+mod fee_caps;
 mod finalizer;
 mod input;
 mod input_candidates;
+mod interactive_tx;
 mod no_std_rand;
+mod offer;
 mod output;
+mod payjoin;
+mod psbt_verifier;
 mod rbf;
+mod script_type;
 mod selection;
+mod selection_algorithms;
 mod selector;
 mod signer;
+mod signing_status;
+mod silent_payments;
+#[cfg(test)]
+mod test_utils;
+mod verify;
+mod weight_check;
 
 pub use afs::*;
 pub use canonical_unspents::*;
+pub use coinjoin::*;
+pub use fee_caps::*;
 pub use finalizer::*;
 pub use input::*;
 pub use input_candidates::*;
+pub use interactive_tx::*;
 pub use miniscript;
 pub use miniscript::bitcoin;
 use miniscript::{DefiniteDescriptorKey, Descriptor};
 use no_std_rand::*;
+pub use offer::*;
 pub use output::*;
+pub use payjoin::*;
+pub use psbt_verifier::*;
 pub use rbf::*;
+pub use script_type::*;
 pub use selection::*;
+pub use selection_algorithms::*;
 pub use selector::*;
 pub use signer::*;
+pub use signing_status::*;
+pub use silent_payments::*;
+pub use verify::*;
+pub use weight_check::*;
 
 #[cfg(feature = "std")]
 pub(crate) mod collections {
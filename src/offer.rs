@@ -0,0 +1,374 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::{
+    absolute,
+    psbt::{self, PsbtSighashType},
+    secp256k1::Secp256k1,
+    sighash::{EcdsaSighashType, TapSighashType},
+    transaction, Psbt,
+};
+use miniscript::{bitcoin, psbt::PsbtExt};
+
+use crate::{final_satisfaction_weight, CreatePsbtError, Input, Output, PsbtParams, Selection};
+
+/// Create an offer PSBT that pairs `input` with `output` for signing with
+/// `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY`.
+///
+/// The seller signs the returned PSBT, committing only to its own input, the paired output, and
+/// the transaction version and locktime. The offer must not be finalized, as [`Offer::from_psbt`]
+/// reads the sighash types from the signatures of the PSBT input. Any buyer can then complete the transaction
+/// with their own inputs and outputs, see [`Offer`].
+///
+/// Anti-fee-sniping should not be enabled in `params`, as the buyer cannot change the committed
+/// locktime later. For taproot inputs, plan with [`TaprootCanSign::sighash_default`] disabled, as
+/// a non-default sighash type adds a byte to the signature.
+///
+/// [`TaprootCanSign::sighash_default`]: miniscript::plan::TaprootCanSign::sighash_default
+pub fn create_offer_psbt(
+    input: &Input,
+    output: &Output,
+    params: PsbtParams,
+) -> Result<Psbt, CreatePsbtError> {
+    let selection = Selection::new(alloc::vec![input.clone()], alloc::vec![output.clone()]);
+    let mut psbt = selection.psbt_from_unsigned_tx(selection.unsigned_tx(&params)?, &params)?;
+    let sighash_type = if input.prev_txout().script_pubkey.is_p2tr() {
+        PsbtSighashType::from(TapSighashType::SinglePlusAnyoneCanPay)
+    } else {
+        PsbtSighashType::from(EcdsaSighashType::SinglePlusAnyoneCanPay)
+    };
+    psbt.inputs[0].sighash_type = Some(sighash_type);
+    Ok(psbt)
+}
+
+/// A signed `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY` offer, as seen by the buyer.
+///
+/// To complete the transaction, the buyer selects their own inputs and outputs and places the
+/// offer's input and output at the same index with [`Selection::merge_offer`]. The simplest way
+/// to account for the offer during coin selection is to add [`Offer::input`] as a must-select
+/// candidate and [`Offer::output`] as a target output. The PSBT must then be created with
+/// [`Offer::psbt_params`], as the seller's signature commits to the version and locktime.
+#[derive(Debug, Clone)]
+pub struct Offer {
+    input: Input,
+    output: Output,
+    version: transaction::Version,
+    lock_time: absolute::LockTime,
+}
+
+/// Error when parsing an [`Offer`] from a PSBT.
+#[derive(Debug)]
+pub enum OfferError {
+    /// The offer must have exactly one input and one output.
+    InvalidShape,
+    /// The offer input has no signatures, e.g. because it was already finalized.
+    MissingSignatures,
+    /// The offer input is not signed with `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY` only.
+    InvalidSighash,
+    /// Finalizing the offer input failed.
+    Finalize(miniscript::psbt::Error),
+    /// The offer input is invalid, e.g. its previous output is missing.
+    Input(crate::FromPsbtInputError),
+}
+
+impl fmt::Display for OfferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidShape => write!(f, "offer must have exactly one input and one output"),
+            Self::MissingSignatures => write!(f, "offer input has no signatures"),
+            Self::InvalidSighash => write!(
+                f,
+                "offer input must be signed with SIGHASH_SINGLE|SIGHASH_ANYONECANPAY"
+            ),
+            Self::Finalize(e) => write!(f, "failed to finalize offer input: {e}"),
+            Self::Input(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OfferError {}
+
+impl Offer {
+    /// Parse a signed offer PSBT and finalize its input.
+    ///
+    /// The sighash types are read from the signatures of the PSBT input, and the input is then
+    /// finalized with miniscript's generic PSBT finalizer.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the PSBT does not have exactly one signed input and one output, if any signature
+    /// of the input does not use `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY`, or if the input cannot be
+    /// finalized.
+    pub fn from_psbt(mut psbt: Psbt) -> Result<Self, OfferError> {
+        if psbt.inputs.len() != 1 || psbt.outputs.len() != 1 {
+            return Err(OfferError::InvalidShape);
+        }
+        let sighash_types = sighash_types(&psbt.inputs[0]);
+        if sighash_types.is_empty() {
+            return Err(OfferError::MissingSignatures);
+        }
+        if sighash_types
+            .iter()
+            .any(|&sighash_type| sighash_type != SINGLE_ANYONECANPAY)
+        {
+            return Err(OfferError::InvalidSighash);
+        }
+
+        psbt.finalize_inp_mut(&Secp256k1::verification_only(), 0)
+            .map_err(OfferError::Finalize)?;
+        let txin = &psbt.unsigned_tx.input[0];
+        let psbt_input = &psbt.inputs[0];
+        let satisfaction_weight = final_satisfaction_weight(psbt_input) as usize;
+        let is_coinbase = psbt_input
+            .non_witness_utxo
+            .as_ref()
+            .is_some_and(|tx| tx.is_coinbase());
+        let input = Input::from_psbt_input(
+            txin.previous_output,
+            txin.sequence,
+            psbt_input.clone(),
+            satisfaction_weight,
+            None,
+            is_coinbase,
+            None,
+        )
+        .map_err(OfferError::Input)?;
+        let txout = psbt.unsigned_tx.output[0].clone();
+        Ok(Self {
+            input,
+            output: Output::with_script(txout.script_pubkey, txout.value),
+            version: psbt.unsigned_tx.version,
+            lock_time: psbt.unsigned_tx.lock_time,
+        })
+    }
+
+    /// The seller's input.
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// The output paired with the seller's input, usually paying the seller.
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    /// Params for creating the buyer's PSBT with the version and locktime the seller signed.
+    pub fn psbt_params(&self) -> PsbtParams {
+        PsbtParams {
+            version: self.version,
+            min_locktime: self.lock_time,
+            ..Default::default()
+        }
+    }
+}
+
+/// `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY`, the same for ECDSA and schnorr signatures.
+const SINGLE_ANYONECANPAY: u32 = 0x83;
+
+/// Sighash types of the signatures of `psbt_input`.
+fn sighash_types(psbt_input: &psbt::Input) -> Vec<u32> {
+    let ecdsa = psbt_input
+        .partial_sigs
+        .values()
+        .map(|sig| sig.sighash_type.to_u32());
+    let schnorr = psbt_input
+        .tap_key_sig
+        .iter()
+        .chain(psbt_input.tap_script_sigs.values())
+        .map(|sig| sig.sighash_type as u32);
+    ecdsa.chain(schnorr).collect()
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, input, parse_descriptor, TR_XPRV, TR_XPRV_2, WPKH_XPRV};
+    use crate::{
+        selection_algorithm_lowest_fee_bnb, ChangeScript, Finalizer, InputCandidates,
+        SelectorParams, Signer,
+    };
+    use bitcoin::{secp256k1::Secp256k1, Amount, FeeRate};
+    use miniscript::{plan::Assets, Descriptor, DescriptorPublicKey};
+
+    struct Wallet {
+        descriptor: Descriptor<DescriptorPublicKey>,
+        signer: Signer,
+        assets: Assets,
+    }
+
+    impl Wallet {
+        fn new(descriptor: &str, sighash_default: bool) -> anyhow::Result<Self> {
+            let (descriptor, keymap) = parse_descriptor(descriptor)?;
+            let mut assets = assets(&keymap);
+            // Sellers sign offers with a 65 byte schnorr signature.
+            assets.keys = core::mem::take(&mut assets.keys)
+                .into_iter()
+                .map(|(key_source, mut can_sign)| {
+                    can_sign.taproot.sighash_default = sighash_default;
+                    (key_source, can_sign)
+                })
+                .collect();
+            Ok(Self {
+                descriptor,
+                signer: Signer(keymap),
+                assets,
+            })
+        }
+
+        fn input(&self, index: u32, value: Amount) -> anyhow::Result<Input> {
+            let desc = self.descriptor.at_derivation_index(index)?;
+            input(&desc, &self.assets, value, index, None)
+        }
+
+        fn output(&self, index: u32, value: Amount) -> anyhow::Result<Output> {
+            Ok(Output::with_descriptor(
+                self.descriptor.at_derivation_index(index)?,
+                value,
+            ))
+        }
+
+        /// Create and sign an offer selling `input` for `price`.
+        fn offer(&self, input: &Input, price: Amount) -> anyhow::Result<Psbt> {
+            let mut psbt = create_offer_psbt(
+                input,
+                &self.output(100, price)?,
+                PsbtParams {
+                    mandate_full_tx_for_segwit_v0: false,
+                    ..Default::default()
+                },
+            )?;
+            let _ = psbt.sign(&self.signer, &Secp256k1::new());
+            Ok(psbt)
+        }
+    }
+
+    #[test]
+    fn test_offer_roundtrip() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        for seller_desc in [TR_XPRV, WPKH_XPRV] {
+            let seller = Wallet::new(seller_desc, false)?;
+            let buyer = Wallet::new(TR_XPRV_2, true)?;
+            let asset = seller.input(0, Amount::from_sat(10_000))?;
+            let unsigned = create_offer_psbt(
+                &asset,
+                &seller.output(100, Amount::from_sat(50_000))?,
+                PsbtParams {
+                    mandate_full_tx_for_segwit_v0: false,
+                    ..Default::default()
+                },
+            )?;
+            assert_eq!(
+                unsigned.inputs[0].sighash_type.map(|t| t.to_u32()),
+                Some(0x83)
+            );
+            let offer = Offer::from_psbt(seller.offer(&asset, Amount::from_sat(50_000))?)?;
+
+            // Buyer receives the asset and pays the seller.
+            let candidates = InputCandidates::new(
+                [offer.input().clone()],
+                [
+                    buyer.input(0, Amount::from_sat(30_000))?,
+                    buyer.input(1, Amount::from_sat(40_000))?,
+                ],
+            );
+            let mut selection = candidates.into_selection(
+                selection_algorithm_lowest_fee_bnb(FeeRate::from_sat_per_vb_u32(1), 100_000),
+                SelectorParams::new(
+                    FeeRate::from_sat_per_vb_u32(2),
+                    vec![
+                        buyer.output(0, Amount::from_sat(10_000))?,
+                        offer.output().clone(),
+                    ],
+                    ChangeScript::from_descriptor(buyer.descriptor.at_derivation_index(1)?),
+                ),
+            )?;
+            selection.merge_offer(&offer, 1)?;
+            assert_eq!(
+                selection.inputs()[1].prev_outpoint(),
+                offer.input().prev_outpoint()
+            );
+            assert_eq!(
+                selection.outputs()[1].script_pubkey(),
+                offer.output().script_pubkey()
+            );
+            assert_eq!(selection.inputs().len(), 3);
+            assert_eq!(
+                selection
+                    .inputs()
+                    .iter()
+                    .filter(|input| input.prev_outpoint() == asset.prev_outpoint())
+                    .count(),
+                1
+            );
+
+            let mut psbt = selection.create_psbt(PsbtParams {
+                mandate_full_tx_for_segwit_v0: false,
+                ..offer.psbt_params()
+            })?;
+            let _ = psbt.sign(&buyer.signer, &secp);
+            assert!(selection
+                .into_finalizer()
+                .finalize(&mut psbt)
+                .is_finalized());
+            let prevouts = psbt
+                .inputs
+                .iter()
+                .map(|psbt_input| psbt_input.witness_utxo.clone().expect("must have utxo"))
+                .collect::<Vec<_>>();
+            let tx = psbt.extract_tx()?;
+
+            // The seller's signature is still valid in the completed transaction.
+            for (index, txin) in tx.input.iter().enumerate() {
+                let interpreter = miniscript::Interpreter::from_txdata(
+                    &prevouts[index].script_pubkey,
+                    &txin.script_sig,
+                    &txin.witness,
+                    txin.sequence,
+                    tx.lock_time,
+                )?;
+                for constraint in interpreter.iter(
+                    &secp,
+                    &tx,
+                    index,
+                    &bitcoin::sighash::Prevouts::All(&prevouts),
+                ) {
+                    constraint?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_offer_rejects_other_sighash() -> anyhow::Result<()> {
+        let seller = Wallet::new(TR_XPRV, false)?;
+        let asset = seller.input(0, Amount::from_sat(10_000))?;
+        let mut psbt = create_offer_psbt(
+            &asset,
+            &seller.output(100, Amount::from_sat(50_000))?,
+            PsbtParams::default(),
+        )?;
+        assert!(matches!(
+            Offer::from_psbt(psbt.clone()),
+            Err(OfferError::MissingSignatures)
+        ));
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(TapSighashType::All));
+        let _ = psbt.sign(&seller.signer, &Secp256k1::new());
+        assert!(matches!(
+            Offer::from_psbt(psbt.clone()),
+            Err(OfferError::InvalidSighash)
+        ));
+
+        // Finalizing removes the signatures the sighash types are read from.
+        let finalizer = Finalizer::new([(asset.prev_outpoint(), asset.plan().unwrap().clone())])
+            .with_sighash_types([(asset.prev_outpoint(), TapSighashType::All.into())]);
+        assert!(finalizer.finalize(&mut psbt).is_finalized());
+        assert!(matches!(
+            Offer::from_psbt(psbt),
+            Err(OfferError::MissingSignatures)
+        ));
+        Ok(())
+    }
+}
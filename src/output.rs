@@ -1,8 +1,8 @@
 use alloc::boxed::Box;
-use bitcoin::{Amount, ScriptBuf, TxOut};
+use bitcoin::{opcodes::all::OP_PUSHNUM_1, script::Builder, Amount, ScriptBuf, TxOut};
 use miniscript::bitcoin;
 
-use crate::DefiniteDescriptor;
+use crate::{DefiniteDescriptor, SilentPaymentAddress};
 
 /// Source of the output script pubkey
 #[derive(Debug, Clone)]
@@ -11,6 +11,11 @@ pub enum ScriptSource {
     Script(ScriptBuf),
     /// definite descriptor
     Descriptor(Box<DefiniteDescriptor>),
+    /// silent payment address
+    ///
+    /// The script pubkey depends on the inputs of the transaction, so it is only known after
+    /// selection. See [`Selection::resolve_silent_payments`](crate::Selection::resolve_silent_payments).
+    SilentPayment(Box<SilentPaymentAddress>),
 }
 
 impl From<ScriptBuf> for ScriptSource {
@@ -25,6 +30,12 @@ impl From<DefiniteDescriptor> for ScriptSource {
     }
 }
 
+impl From<SilentPaymentAddress> for ScriptSource {
+    fn from(address: SilentPaymentAddress) -> Self {
+        Self::from_silent_payment(address)
+    }
+}
+
 impl ScriptSource {
     /// From script
     pub fn from_script(script: ScriptBuf) -> Self {
@@ -36,19 +47,39 @@ impl ScriptSource {
         Self::Descriptor(Box::new(descriptor))
     }
 
+    /// From silent payment address
+    pub fn from_silent_payment(address: SilentPaymentAddress) -> Self {
+        Self::SilentPayment(Box::new(address))
+    }
+
     /// To ScriptBuf
+    ///
+    /// For an unresolved silent payment, this is a placeholder P2TR script with an all-zero
+    /// output key. It has the same weight as the resolved script, but must not be paid to.
     pub fn script(&self) -> ScriptBuf {
         match self {
             ScriptSource::Script(spk) => spk.clone(),
             ScriptSource::Descriptor(descriptor) => descriptor.script_pubkey(),
+            ScriptSource::SilentPayment(_) => Builder::new()
+                .push_opcode(OP_PUSHNUM_1)
+                .push_slice([0_u8; 32])
+                .into_script(),
         }
     }
 
     /// Get descriptor (if any).
     pub fn descriptor(&self) -> Option<&DefiniteDescriptor> {
         match self {
-            ScriptSource::Script(_) => None,
             ScriptSource::Descriptor(descriptor) => Some(descriptor),
+            _ => None,
+        }
+    }
+
+    /// Get silent payment address (if any).
+    pub fn silent_payment(&self) -> Option<&SilentPaymentAddress> {
+        match self {
+            ScriptSource::SilentPayment(address) => Some(address),
+            _ => None,
         }
     }
 }
@@ -74,11 +105,18 @@ impl From<(DefiniteDescriptor, Amount)> for Output {
     }
 }
 
+impl From<(SilentPaymentAddress, Amount)> for Output {
+    fn from((address, value): (SilentPaymentAddress, Amount)) -> Self {
+        Self::with_silent_payment(address, value)
+    }
+}
+
 impl From<(ScriptSource, Amount)> for Output {
     fn from((src, value): (ScriptSource, Amount)) -> Self {
         match src {
             ScriptSource::Descriptor(desc) => Self::with_descriptor(*desc, value),
             ScriptSource::Script(s) => Self::with_script(s, value),
+            ScriptSource::SilentPayment(address) => Self::with_silent_payment(*address, value),
         }
     }
 }
@@ -100,7 +138,21 @@ impl Output {
         }
     }
 
+    /// To a silent payment address
+    ///
+    /// The output is weight-estimated as P2TR during selection and must be resolved with
+    /// [`Selection::resolve_silent_payments`](crate::Selection::resolve_silent_payments) before
+    /// creating the PSBT.
+    pub fn with_silent_payment(address: SilentPaymentAddress, value: Amount) -> Self {
+        Self {
+            value,
+            script_pubkey_source: address.into(),
+        }
+    }
+
     /// Script pubkey
+    ///
+    /// This is a placeholder for an unresolved silent payment, see [`ScriptSource::script`].
     pub fn script_pubkey(&self) -> ScriptBuf {
         self.script_pubkey_source.script()
     }
@@ -110,7 +162,15 @@ impl Output {
         self.script_pubkey_source.descriptor()
     }
 
+    /// Silent payment address (if unresolved).
+    pub fn silent_payment(&self) -> Option<&SilentPaymentAddress> {
+        self.script_pubkey_source.silent_payment()
+    }
+
     /// Create txout.
+    ///
+    /// For an unresolved silent payment, the script pubkey is a placeholder that is only good for
+    /// weighing the output. Check [`Output::silent_payment`] before paying to it.
     pub fn txout(&self) -> TxOut {
         TxOut {
             value: self.value,
@@ -0,0 +1,1051 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::{
+    psbt, Amount, FeeRate, OutPoint, Psbt, Script, ScriptBuf, Transaction, TxIn, Weight,
+};
+use miniscript::bitcoin;
+use rand_core::RngCore;
+
+use crate::{
+    final_satisfaction_weight, no_std_rand::random_range, CreatePsbtError, FinalizeError,
+    Finalizer, FromPsbtInputError, Input, InputCandidates, Output, PsbtParams, ScriptType,
+    Selection, SetSequenceError, TXIN_BASE_WEIGHT,
+};
+
+/// Optional parameters communicated by the payjoin sender, as per [BIP78].
+///
+/// Both the [`PayjoinReceiver`] and the [`PayjoinSender`] must use the same parameters.
+///
+/// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+#[derive(Debug, Clone)]
+pub struct PayjoinParams {
+    /// Index of the sender's output that the receiver may decrease to pay for the additional fee
+    /// (`additionalfeeoutputindex`).
+    ///
+    /// `None` means the sender does not contribute to the fee of the receiver's input.
+    pub additional_fee_output_index: Option<usize>,
+
+    /// Maximum amount the receiver may subtract from the output at
+    /// [`additional_fee_output_index`](Self::additional_fee_output_index)
+    /// (`maxadditionalfeecontribution`).
+    pub max_additional_fee_contribution: Amount,
+
+    /// Minimum feerate of the payjoin proposal (`minfeerate`).
+    pub min_feerate: FeeRate,
+
+    /// Whether the receiver is forbidden from decreasing the payment output
+    /// (`disableoutputsubstitution`).
+    pub disable_output_substitution: bool,
+}
+
+impl Default for PayjoinParams {
+    fn default() -> Self {
+        Self {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            min_feerate: FeeRate::ZERO,
+            disable_output_substitution: false,
+        }
+    }
+}
+
+/// The receiving side of a [BIP78] payjoin.
+///
+/// Takes the sender's finalized original PSBT, contributes one of our [`Input`]s to it, and
+/// increases our receiving output by the value of that input (minus the part of the additional
+/// fee that the sender does not pay for).
+///
+/// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+#[derive(Debug, Clone)]
+pub struct PayjoinReceiver {
+    original: Psbt,
+    original_tx: Transaction,
+    original_fee: Amount,
+    original_inputs: Vec<Input>,
+    receiver_output_index: usize,
+    params: PayjoinParams,
+}
+
+/// Payjoin proposal created by [`PayjoinReceiver::contribute`].
+///
+/// The receiver's input must be signed and finalized before the PSBT is returned to the sender.
+#[derive(Debug)]
+#[must_use]
+pub struct PayjoinProposal {
+    psbt: Psbt,
+    finalizer: Finalizer,
+    receiver_input_index: usize,
+}
+
+/// Occurs when the receiver cannot create a payjoin proposal.
+#[derive(Debug)]
+pub enum PayjoinReceiverError {
+    /// An input of the original PSBT is not finalized.
+    OriginalInputNotFinalized(usize),
+    /// An input of the original PSBT cannot be converted into an [`Input`].
+    OriginalInput(FromPsbtInputError),
+    /// The fee of the original PSBT cannot be determined.
+    OriginalFee(psbt::Error),
+    /// The original PSBT spends inputs of different script types.
+    MixedInputScripts,
+    /// The original PSBT has no output paying to the receiver script.
+    ReceiverOutputNotFound,
+    /// The original PSBT has more than one output paying to the receiver script.
+    DuplicateReceiverOutput,
+    /// The `additional_fee_output_index` does not point to one of the sender's outputs.
+    InvalidAdditionalFeeOutputIndex(usize),
+    /// The selection strategy did not pick an eligible input.
+    NoEligibleInput,
+    /// The value of the contributed input does not cover the fee the receiver has to pay for it.
+    ContributionTooSmall {
+        /// Value of the contributed input.
+        value: Amount,
+        /// Fee the receiver has to pay for the contributed input.
+        fee: Amount,
+    },
+    /// Setting the sequence of the contributed input failed.
+    SetSequence(SetSequenceError),
+    /// The contributed input changes the locktime of the original transaction.
+    LockTimeChanged,
+    /// Creating the proposal PSBT failed.
+    CreatePsbt(CreatePsbtError),
+    /// Finalizing the receiver's input failed.
+    Finalize(FinalizeError),
+}
+
+impl fmt::Display for PayjoinReceiverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OriginalInputNotFinalized(index) => {
+                write!(f, "input {index} of the original psbt is not finalized")
+            }
+            Self::OriginalInput(err) => write!(f, "{err}"),
+            Self::OriginalFee(err) => write!(f, "cannot determine original fee: {err}"),
+            Self::MixedInputScripts => {
+                write!(f, "original psbt spends inputs of different script types")
+            }
+            Self::ReceiverOutputNotFound => {
+                write!(f, "original psbt does not pay to the receiver script")
+            }
+            Self::DuplicateReceiverOutput => {
+                write!(
+                    f,
+                    "original psbt pays to the receiver script more than once"
+                )
+            }
+            Self::InvalidAdditionalFeeOutputIndex(index) => {
+                write!(f, "invalid additional fee output index {index}")
+            }
+            Self::NoEligibleInput => write!(f, "no eligible input to contribute"),
+            Self::ContributionTooSmall { value, fee } => write!(
+                f,
+                "contributed input value {value} does not cover its fee {fee}"
+            ),
+            Self::SetSequence(err) => write!(f, "{err}"),
+            Self::LockTimeChanged => {
+                write!(f, "contributed input changes the original locktime")
+            }
+            Self::CreatePsbt(err) => write!(f, "{err}"),
+            Self::Finalize(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinReceiverError {}
+
+impl PayjoinReceiver {
+    /// Create a payjoin receiver from the sender's `original` PSBT.
+    ///
+    /// `receiver_script` is the script pubkey of the output paying to us.
+    ///
+    /// # Errors
+    ///
+    /// - If any input of `original` is not finalized or is missing its UTXO.
+    /// - If the inputs of `original` are not all of the same script type.
+    /// - If `original` does not pay to `receiver_script` exactly once.
+    /// - If `params.additional_fee_output_index` is out of range or points to our output.
+    pub fn new(
+        original: Psbt,
+        receiver_script: &Script,
+        params: PayjoinParams,
+    ) -> Result<Self, PayjoinReceiverError> {
+        let mut original_inputs = Vec::with_capacity(original.inputs.len());
+        for (index, (txin, psbt_input)) in original
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(&original.inputs)
+            .enumerate()
+        {
+            if !is_finalized(psbt_input) {
+                return Err(PayjoinReceiverError::OriginalInputNotFinalized(index));
+            }
+            let satisfaction_weight = final_satisfaction_weight(psbt_input);
+            let is_coinbase = psbt_input
+                .non_witness_utxo
+                .as_ref()
+                .is_some_and(Transaction::is_coinbase);
+            let input = Input::from_psbt_input(
+                txin.previous_output,
+                txin.sequence,
+                psbt_input.clone(),
+                satisfaction_weight as usize,
+                None,
+                is_coinbase,
+                None,
+            )
+            .map_err(PayjoinReceiverError::OriginalInput)?;
+            original_inputs.push(input);
+        }
+        if !original_inputs.windows(2).all(|pair| {
+            is_same_script_type(
+                &pair[0].prev_txout().script_pubkey,
+                &pair[1].prev_txout().script_pubkey,
+            )
+        }) {
+            return Err(PayjoinReceiverError::MixedInputScripts);
+        }
+
+        let mut receiver_outputs = original
+            .unsigned_tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txout)| txout.script_pubkey.as_script() == receiver_script)
+            .map(|(index, _)| index);
+        let receiver_output_index = receiver_outputs
+            .next()
+            .ok_or(PayjoinReceiverError::ReceiverOutputNotFound)?;
+        if receiver_outputs.next().is_some() {
+            return Err(PayjoinReceiverError::DuplicateReceiverOutput);
+        }
+        if let Some(index) = params.additional_fee_output_index {
+            if index >= original.unsigned_tx.output.len() || index == receiver_output_index {
+                return Err(PayjoinReceiverError::InvalidAdditionalFeeOutputIndex(index));
+            }
+        }
+
+        let original_fee = original.fee().map_err(PayjoinReceiverError::OriginalFee)?;
+        let original_tx = original.clone().extract_tx_unchecked_fee_rate();
+        Ok(Self {
+            original,
+            original_tx,
+            original_fee,
+            original_inputs,
+            receiver_output_index,
+            params,
+        })
+    }
+
+    /// The sender's original transaction.
+    ///
+    /// The receiver should make sure this is broadcastable before contributing, so that it can
+    /// fall back to broadcasting it if the payjoin fails.
+    pub fn original_tx(&self) -> &Transaction {
+        &self.original_tx
+    }
+
+    /// Inputs of the sender's original transaction.
+    pub fn original_inputs(&self) -> &[Input] {
+        &self.original_inputs
+    }
+
+    /// Index of the output paying to the receiver.
+    pub fn receiver_output_index(&self) -> usize {
+        self.receiver_output_index
+    }
+
+    /// Whether `input` can be contributed to the payjoin.
+    ///
+    /// An eligible input has a plan (so we can sign it), is not already spent by the original
+    /// transaction, has no absolute timelock and matches the script type of the sender's inputs.
+    pub fn is_eligible(&self, input: &Input) -> bool {
+        input.plan().is_some()
+            && input.absolute_timelock().is_none()
+            && self
+                .original_inputs
+                .iter()
+                .all(|original| original.prev_outpoint() != input.prev_outpoint())
+            && self.original_inputs.first().is_none_or(|original| {
+                is_same_script_type(
+                    &original.prev_txout().script_pubkey,
+                    &input.prev_txout().script_pubkey,
+                )
+            })
+    }
+
+    /// Returns `(sender_contribution, receiver_fee)` for contributing `input`.
+    fn contribution_fees(&self, input: &Input) -> (Amount, Amount) {
+        let original_weight = self.original_tx.weight();
+        let original_feerate = self.original_fee / original_weight;
+        let target_feerate = original_feerate.max(self.params.min_feerate);
+        let input_weight = Weight::from_wu(TXIN_BASE_WEIGHT + input.satisfaction_weight());
+        let required_fee = target_feerate
+            .fee_wu(original_weight + input_weight)
+            .expect("must not overflow");
+        let additional_fee = required_fee
+            .checked_sub(self.original_fee)
+            .unwrap_or(Amount::ZERO);
+        let sender_contribution = match self.params.additional_fee_output_index {
+            Some(index) => additional_fee
+                .min(self.params.max_additional_fee_contribution)
+                .min(max_sender_contribution(&self.original, original_feerate, 1))
+                .min(self.original_tx.output[index].value),
+            None => Amount::ZERO,
+        };
+        (sender_contribution, additional_fee - sender_contribution)
+    }
+
+    /// Contribute one of our `candidates` to the payjoin.
+    ///
+    /// `select` picks the outpoint of the input to contribute, see [`payjoin_select_avoid_uih`]
+    /// and [`payjoin_select_first`].
+    #[cfg(feature = "std")]
+    pub fn contribute<S>(
+        &self,
+        candidates: &InputCandidates,
+        select: S,
+    ) -> Result<PayjoinProposal, PayjoinReceiverError>
+    where
+        S: FnMut(&InputCandidates, &PayjoinReceiver) -> Option<OutPoint>,
+    {
+        self.contribute_with_rng(candidates, select, &mut rand::thread_rng())
+    }
+
+    /// Contribute one of our `candidates` to the payjoin with `rng`.
+    ///
+    /// The contributed input is inserted at a random position and uses the same sequence as the
+    /// sender's inputs. Signatures of the sender's inputs are cleared, as they are invalidated by
+    /// the new input.
+    ///
+    /// # Errors
+    ///
+    /// - [`PayjoinReceiverError::NoEligibleInput`] if `select` does not return an eligible input.
+    /// - [`PayjoinReceiverError::ContributionTooSmall`] if the input does not pay for itself.
+    /// - [`PayjoinReceiverError::LockTimeChanged`] if the proposal would change the locktime.
+    pub fn contribute_with_rng<S>(
+        &self,
+        candidates: &InputCandidates,
+        mut select: S,
+        rng: &mut impl RngCore,
+    ) -> Result<PayjoinProposal, PayjoinReceiverError>
+    where
+        S: FnMut(&InputCandidates, &PayjoinReceiver) -> Option<OutPoint>,
+    {
+        let outpoint = select(candidates, self).ok_or(PayjoinReceiverError::NoEligibleInput)?;
+        let mut contributed = candidates
+            .inputs()
+            .find(|input| input.prev_outpoint() == outpoint)
+            .filter(|input| self.is_eligible(input))
+            .cloned()
+            .ok_or(PayjoinReceiverError::NoEligibleInput)?;
+        if let Some(txin) = self.original_tx.input.first() {
+            contributed
+                .set_sequence(txin.sequence)
+                .map_err(PayjoinReceiverError::SetSequence)?;
+        }
+
+        let (sender_contribution, receiver_fee) = self.contribution_fees(&contributed);
+        let value = contributed.prev_txout().value;
+        if value <= receiver_fee {
+            return Err(PayjoinReceiverError::ContributionTooSmall {
+                value,
+                fee: receiver_fee,
+            });
+        }
+
+        let mut outputs = self
+            .original_tx
+            .output
+            .iter()
+            .map(|txout| Output::with_script(txout.script_pubkey.clone(), txout.value))
+            .collect::<Vec<_>>();
+        outputs[self.receiver_output_index].value += value - receiver_fee;
+        if let Some(index) = self.params.additional_fee_output_index {
+            outputs[index].value -= sender_contribution;
+        }
+
+        let mut inputs = self.original_inputs.clone();
+        let receiver_input_index = random_range(rng, inputs.len() as u32 + 1) as usize;
+        inputs.insert(receiver_input_index, contributed);
+
+        let selection = Selection::new(inputs, outputs);
+        let mut psbt = selection
+            .create_psbt_with_rng(
+                PsbtParams {
+                    version: self.original_tx.version,
+                    min_locktime: self.original_tx.lock_time,
+                    mandate_full_tx_for_segwit_v0: false,
+                    anti_fee_sniping: None,
+                    ..Default::default()
+                },
+                rng,
+            )
+            .map_err(PayjoinReceiverError::CreatePsbt)?;
+        if psbt.unsigned_tx.lock_time != self.original_tx.lock_time {
+            return Err(PayjoinReceiverError::LockTimeChanged);
+        }
+
+        for (index, psbt_input) in psbt.inputs.iter_mut().enumerate() {
+            if index != receiver_input_index {
+                clear_signatures(psbt_input);
+            }
+        }
+        for (psbt_output, original) in psbt.outputs.iter_mut().zip(&self.original.outputs) {
+            *psbt_output = original.clone();
+        }
+
+        Ok(PayjoinProposal {
+            psbt,
+            finalizer: selection.into_finalizer(),
+            receiver_input_index,
+        })
+    }
+}
+
+impl PayjoinProposal {
+    /// The proposal PSBT.
+    ///
+    /// Sign the receiver's input of this PSBT before calling [`finalize`](Self::finalize).
+    pub fn psbt(&self) -> &Psbt {
+        &self.psbt
+    }
+
+    /// Mutable reference to the proposal PSBT, used for signing.
+    pub fn psbt_mut(&mut self) -> &mut Psbt {
+        &mut self.psbt
+    }
+
+    /// Index of the receiver's input.
+    pub fn receiver_input_index(&self) -> usize {
+        self.receiver_input_index
+    }
+
+    /// Finalize the receiver's input.
+    pub fn finalize(&mut self) -> Result<(), PayjoinReceiverError> {
+        let finalized = self
+            .finalizer
+            .finalize_input(&mut self.psbt, self.receiver_input_index)
+            .map_err(PayjoinReceiverError::Finalize)?;
+        debug_assert!(finalized, "receiver input must have a plan");
+        Ok(())
+    }
+
+    /// Consume the proposal and return the PSBT to be sent back to the payjoin sender.
+    pub fn into_psbt(self) -> Psbt {
+        self.psbt
+    }
+}
+
+/// The sending side of a [BIP78] payjoin.
+///
+/// Validates the receiver's proposal against the original PSBT.
+///
+/// [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+#[derive(Debug, Clone)]
+pub struct PayjoinSender {
+    original: Psbt,
+    payee: ScriptBuf,
+    params: PayjoinParams,
+}
+
+/// Occurs when the receiver's payjoin proposal is rejected by the sender.
+#[derive(Debug)]
+pub enum PayjoinProposalError {
+    /// The transaction version was changed.
+    VersionChanged,
+    /// The transaction locktime was changed.
+    LockTimeChanged,
+    /// A sender input is missing from the proposal.
+    MissingSenderInput(OutPoint),
+    /// The sequence of a sender input was changed.
+    SenderInputSequenceChanged(OutPoint),
+    /// A sender input of the proposal is still finalized.
+    SenderInputFinalized(OutPoint),
+    /// The proposal does not contain any receiver input.
+    NoReceiverInputs,
+    /// A receiver input is not finalized.
+    ReceiverInputNotFinalized(OutPoint),
+    /// A receiver input is missing its UTXO.
+    ReceiverInputMissingUtxo(OutPoint),
+    /// A receiver input does not match the script type of the sender's inputs.
+    MixedInputScripts(OutPoint),
+    /// A receiver input does not use the same sequence as the sender's inputs.
+    ReceiverInputSequenceMismatch(OutPoint),
+    /// An output of the original transaction is missing.
+    MissingOriginalOutput(usize),
+    /// The value of an output of the original transaction was changed.
+    ///
+    /// Only the payment output and the additional fee output may change, and the additional fee
+    /// output may only decrease, see [`FeeContributionExceedsMaximum`](Self::FeeContributionExceedsMaximum).
+    ModifiedOriginalOutput(usize),
+    /// The payment output was decreased although output substitution is disabled.
+    PaymentDecreased,
+    /// The sender's fee contribution exceeds what the sender allowed.
+    FeeContributionExceedsMaximum {
+        /// The sender's fee contribution.
+        contribution: Amount,
+        /// The maximum allowed fee contribution.
+        max: Amount,
+    },
+    /// The proposal feerate is below the minimum feerate.
+    FeeRateBelowMinimum {
+        /// The proposal feerate.
+        feerate: FeeRate,
+        /// The minimum feerate.
+        min_feerate: FeeRate,
+    },
+    /// The fee of the original or the proposal transaction cannot be determined.
+    Fee(psbt::Error),
+}
+
+impl fmt::Display for PayjoinProposalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VersionChanged => write!(f, "proposal changed the transaction version"),
+            Self::LockTimeChanged => write!(f, "proposal changed the transaction locktime"),
+            Self::MissingSenderInput(op) => write!(f, "proposal is missing sender input {op}"),
+            Self::SenderInputSequenceChanged(op) => {
+                write!(f, "proposal changed the sequence of sender input {op}")
+            }
+            Self::SenderInputFinalized(op) => {
+                write!(f, "proposal sender input {op} must not be finalized")
+            }
+            Self::NoReceiverInputs => write!(f, "proposal does not contain receiver inputs"),
+            Self::ReceiverInputNotFinalized(op) => {
+                write!(f, "receiver input {op} is not finalized")
+            }
+            Self::ReceiverInputMissingUtxo(op) => {
+                write!(f, "receiver input {op} is missing its utxo")
+            }
+            Self::MixedInputScripts(op) => write!(
+                f,
+                "receiver input {op} does not match the script type of the sender inputs"
+            ),
+            Self::ReceiverInputSequenceMismatch(op) => write!(
+                f,
+                "receiver input {op} does not use the sequence of the sender inputs"
+            ),
+            Self::MissingOriginalOutput(index) => write!(f, "original output {index} is missing"),
+            Self::ModifiedOriginalOutput(index) => {
+                write!(f, "value of original output {index} was modified")
+            }
+            Self::PaymentDecreased => write!(
+                f,
+                "payment output decreased although output substitution is disabled"
+            ),
+            Self::FeeContributionExceedsMaximum { contribution, max } => write!(
+                f,
+                "fee contribution {contribution} exceeds the maximum of {max}"
+            ),
+            Self::FeeRateBelowMinimum {
+                feerate,
+                min_feerate,
+            } => write!(
+                f,
+                "proposal feerate {feerate:#} is below the minimum of {min_feerate:#}"
+            ),
+            Self::Fee(err) => write!(f, "cannot determine fee: {err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinProposalError {}
+
+impl PayjoinSender {
+    /// Create a payjoin sender.
+    ///
+    /// `original` is the finalized PSBT sent to the receiver and `payee` is the script pubkey of
+    /// the payment output.
+    pub fn new(original: Psbt, payee: ScriptBuf, params: PayjoinParams) -> Self {
+        Self {
+            original,
+            payee,
+            params,
+        }
+    }
+
+    /// The finalized original PSBT.
+    pub fn original(&self) -> &Psbt {
+        &self.original
+    }
+
+    /// Validate the receiver's `proposal` and prepare it for signing.
+    ///
+    /// On success, the sender's inputs are restored with the UTXOs of the original PSBT and
+    /// updated with the plans of `finalizer`, so that they can be signed again. The same
+    /// `finalizer` can then be used to finalize the proposal.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`PayjoinProposalError`] if the proposal violates the BIP78 sender checks.
+    pub fn process_proposal(
+        &self,
+        mut proposal: Psbt,
+        finalizer: &Finalizer,
+    ) -> Result<Psbt, PayjoinProposalError> {
+        let original_tx = &self.original.unsigned_tx;
+        let proposal_tx = &proposal.unsigned_tx;
+        if proposal_tx.version != original_tx.version {
+            return Err(PayjoinProposalError::VersionChanged);
+        }
+        if proposal_tx.lock_time != original_tx.lock_time {
+            return Err(PayjoinProposalError::LockTimeChanged);
+        }
+
+        // Estimate the weight of the finalized proposal, using the original's satisfactions for
+        // the sender's inputs.
+        let mut finalized_tx = proposal_tx.clone();
+        let mut receiver_inputs = 0_u64;
+        let mut receiver_input_value = Amount::ZERO;
+        for (index, txin) in finalized_tx.input.iter_mut().enumerate() {
+            let op = txin.previous_output;
+            let psbt_input = &proposal.inputs[index];
+            match original_tx
+                .input
+                .iter()
+                .position(|original| original.previous_output == op)
+            {
+                Some(original_index) => {
+                    if txin.sequence != original_tx.input[original_index].sequence {
+                        return Err(PayjoinProposalError::SenderInputSequenceChanged(op));
+                    }
+                    if is_finalized(psbt_input) {
+                        return Err(PayjoinProposalError::SenderInputFinalized(op));
+                    }
+                    set_final_fields(txin, &self.original.inputs[original_index]);
+                }
+                None => {
+                    if !is_finalized(psbt_input) {
+                        return Err(PayjoinProposalError::ReceiverInputNotFinalized(op));
+                    }
+                    let utxo = receiver_utxo(psbt_input, op)
+                        .ok_or(PayjoinProposalError::ReceiverInputMissingUtxo(op))?;
+                    if let Some(original_txin) = original_tx.input.first() {
+                        let original_script = &self
+                            .original
+                            .spend_utxo(0)
+                            .map_err(|_| PayjoinProposalError::MissingSenderInput(op))?
+                            .script_pubkey;
+                        if !is_same_script_type(original_script, &utxo.script_pubkey) {
+                            return Err(PayjoinProposalError::MixedInputScripts(op));
+                        }
+                        if txin.sequence != original_txin.sequence {
+                            return Err(PayjoinProposalError::ReceiverInputSequenceMismatch(op));
+                        }
+                    }
+                    receiver_inputs += 1;
+                    receiver_input_value += utxo.value;
+                    set_final_fields(txin, psbt_input);
+                }
+            }
+        }
+        if let Some(missing) = original_tx.input.iter().find(|original| {
+            !proposal_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == original.previous_output)
+        }) {
+            return Err(PayjoinProposalError::MissingSenderInput(
+                missing.previous_output,
+            ));
+        }
+        if receiver_inputs == 0 {
+            return Err(PayjoinProposalError::NoReceiverInputs);
+        }
+
+        // Original outputs must be present in order. The receiver may add outputs in between.
+        let mut contribution = Amount::ZERO;
+        let mut proposal_outputs = proposal_tx.output.iter();
+        for (index, original) in original_tx.output.iter().enumerate() {
+            let proposed = proposal_outputs
+                .find(|txout| txout.script_pubkey == original.script_pubkey)
+                .ok_or(PayjoinProposalError::MissingOriginalOutput(index))?;
+            if original.script_pubkey == self.payee {
+                if self.params.disable_output_substitution && proposed.value < original.value {
+                    return Err(PayjoinProposalError::PaymentDecreased);
+                }
+            } else if Some(index) == self.params.additional_fee_output_index {
+                contribution = original
+                    .value
+                    .checked_sub(proposed.value)
+                    .ok_or(PayjoinProposalError::ModifiedOriginalOutput(index))?;
+            } else if proposed.value != original.value {
+                return Err(PayjoinProposalError::ModifiedOriginalOutput(index));
+            }
+        }
+
+        let original_fee = self.original.fee().map_err(PayjoinProposalError::Fee)?;
+        let original_feerate = original_fee
+            / self
+                .original
+                .clone()
+                .extract_tx_unchecked_fee_rate()
+                .weight();
+        let max_contribution =
+            self.params
+                .max_additional_fee_contribution
+                .min(max_sender_contribution(
+                    &self.original,
+                    original_feerate,
+                    receiver_inputs,
+                ));
+        if contribution > max_contribution {
+            return Err(PayjoinProposalError::FeeContributionExceedsMaximum {
+                contribution,
+                max: max_contribution,
+            });
+        }
+
+        let input_value = self
+            .original
+            .iter_funding_utxos()
+            .map(|utxo| utxo.map(|txout| txout.value))
+            .sum::<Result<Amount, _>>()
+            .map_err(PayjoinProposalError::Fee)?
+            + receiver_input_value;
+        let output_value = proposal_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<Amount>();
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or(PayjoinProposalError::Fee(psbt::Error::NegativeFee))?;
+        let feerate = fee / finalized_tx.weight();
+        if feerate < self.params.min_feerate {
+            return Err(PayjoinProposalError::FeeRateBelowMinimum {
+                feerate,
+                min_feerate: self.params.min_feerate,
+            });
+        }
+
+        // Restore the sender's inputs so that they can be signed again.
+        for (index, txin) in proposal.unsigned_tx.input.iter().enumerate() {
+            let Some(original_index) = original_tx
+                .input
+                .iter()
+                .position(|original| original.previous_output == txin.previous_output)
+            else {
+                continue;
+            };
+            let original = &self.original.inputs[original_index];
+            let psbt_input = &mut proposal.inputs[index];
+            *psbt_input = psbt::Input {
+                non_witness_utxo: original.non_witness_utxo.clone(),
+                witness_utxo: original.witness_utxo.clone(),
+                ..Default::default()
+            };
+            if let Some(plan) = finalizer.plans.get(&txin.previous_output) {
+                plan.update_psbt_input(psbt_input);
+            }
+        }
+        Ok(proposal)
+    }
+}
+
+/// Select the first eligible input that avoids the "unnecessary input" heuristics.
+///
+/// A payjoin transaction looks like a regular payment with change if its smallest input is larger
+/// than its smallest output. Returns `None` if no eligible input satisfies this.
+pub fn payjoin_select_avoid_uih(
+) -> impl FnMut(&InputCandidates, &PayjoinReceiver) -> Option<OutPoint> {
+    |candidates, receiver| {
+        let original_tx = receiver.original_tx();
+        let min_original_out = original_tx.output.iter().map(|txout| txout.value).min()?;
+        let min_original_in = receiver
+            .original_inputs()
+            .iter()
+            .map(|input| input.prev_txout().value)
+            .min()?;
+        let prior_payment = original_tx.output[receiver.receiver_output_index()].value;
+        candidates
+            .inputs()
+            .filter(|input| receiver.is_eligible(input))
+            .find(|input| {
+                let value = input.prev_txout().value;
+                let min_out = min_original_out.min(prior_payment + value);
+                let min_in = min_original_in.min(value);
+                min_in > min_out
+            })
+            .map(Input::prev_outpoint)
+    }
+}
+
+/// Select the first eligible input.
+pub fn payjoin_select_first() -> impl FnMut(&InputCandidates, &PayjoinReceiver) -> Option<OutPoint>
+{
+    |candidates, receiver| {
+        candidates
+            .inputs()
+            .find(|input| receiver.is_eligible(input))
+            .map(Input::prev_outpoint)
+    }
+}
+
+/// Maximum fee the sender pays for `receiver_inputs` inputs.
+///
+/// As the receiver's inputs must match the script type of the sender's inputs, both sides
+/// estimate their weight from the largest finalized input of the `original` PSBT.
+fn max_sender_contribution(
+    original: &Psbt,
+    original_feerate: FeeRate,
+    receiver_inputs: u64,
+) -> Amount {
+    let input_weight = original
+        .inputs
+        .iter()
+        .map(finalized_weight)
+        .max()
+        .unwrap_or(0);
+    original_feerate
+        .fee_wu(Weight::from_wu(input_weight * receiver_inputs))
+        .expect("must not overflow")
+}
+
+/// Weight of an input once satisfied with the final fields of `psbt_input`.
+///
+/// The scriptSig length counted by [`TXIN_BASE_WEIGHT`] is part of the satisfaction weight.
+fn finalized_weight(psbt_input: &psbt::Input) -> u64 {
+    TXIN_BASE_WEIGHT - 4 + final_satisfaction_weight(psbt_input)
+}
+
+fn set_final_fields(txin: &mut TxIn, psbt_input: &psbt::Input) {
+    txin.script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+    txin.witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+}
+
+fn is_finalized(psbt_input: &psbt::Input) -> bool {
+    psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some()
+}
+
+fn clear_signatures(psbt_input: &mut psbt::Input) {
+    psbt_input.final_script_sig = None;
+    psbt_input.final_script_witness = None;
+    psbt_input.partial_sigs.clear();
+    psbt_input.tap_key_sig = None;
+    psbt_input.tap_script_sigs.clear();
+}
+
+fn receiver_utxo(psbt_input: &psbt::Input, outpoint: OutPoint) -> Option<&bitcoin::TxOut> {
+    match (&psbt_input.witness_utxo, &psbt_input.non_witness_utxo) {
+        (Some(txout), _) => Some(txout),
+        (None, Some(tx)) if tx.compute_txid() == outpoint.txid => {
+            tx.output.get(outpoint.vout as usize)
+        }
+        _ => None,
+    }
+}
+
+fn is_same_script_type(a: &Script, b: &Script) -> bool {
+    ScriptType::from_script(a)
+        .is_some_and(|script_type| ScriptType::from_script(b) == Some(script_type))
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, descriptor_at, input, parse_descriptor, TR_XPRV, TR_XPRV_3};
+    use crate::{ConfirmationStatus, Signer};
+    use bitcoin::secp256k1::Secp256k1;
+    use miniscript::descriptor::KeyMap;
+    use rand_core::OsRng;
+
+    fn input_at(descriptor: &str, index: u32, value: u64) -> anyhow::Result<(Input, KeyMap)> {
+        let (desc, keymap) = parse_descriptor(descriptor)?;
+        let desc = desc.at_derivation_index(index)?;
+        let status = ConfirmationStatus::new(1_000, None)?;
+        let input = input(
+            &desc,
+            &assets(&keymap),
+            Amount::from_sat(value),
+            index,
+            Some(status),
+        )?;
+        Ok((input, keymap))
+    }
+
+    fn spk_at(descriptor: &str, index: u32) -> anyhow::Result<ScriptBuf> {
+        Ok(descriptor_at(descriptor, index)?.script_pubkey())
+    }
+
+    struct Setup {
+        original: Psbt,
+        sender_finalizer: Finalizer,
+        sender_signer: Signer,
+        receiver_signer: Signer,
+        candidates: InputCandidates,
+        payee: ScriptBuf,
+    }
+
+    fn setup() -> anyhow::Result<Setup> {
+        let secp = Secp256k1::new();
+        let (sender_input, sender_keymap) = input_at(TR_XPRV, 0, 100_000)?;
+        let (receiver_input, receiver_keymap) = input_at(TR_XPRV_3, 0, 80_000)?;
+        let payee = spk_at(TR_XPRV_3, 1)?;
+        let sender_change = spk_at(TR_XPRV, 1)?;
+
+        let selection = Selection::new(
+            vec![sender_input],
+            vec![
+                Output::with_script(payee.clone(), Amount::from_sat(50_000)),
+                Output::with_script(sender_change, Amount::from_sat(49_000)),
+            ],
+        );
+        let mut original = selection.create_psbt(PsbtParams::default())?;
+        let sender_finalizer = selection.into_finalizer();
+        let sender_signer = Signer(sender_keymap);
+        original
+            .sign(&sender_signer, &secp)
+            .expect("signing failed");
+        assert!(sender_finalizer.finalize(&mut original).is_finalized());
+
+        Ok(Setup {
+            original,
+            sender_finalizer,
+            sender_signer,
+            receiver_signer: Signer(receiver_keymap),
+            candidates: InputCandidates::new([], [receiver_input]),
+            payee,
+        })
+    }
+
+    #[test]
+    fn test_payjoin_roundtrip() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let setup = setup()?;
+        let params = PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: Amount::from_sat(1_000),
+            ..Default::default()
+        };
+
+        // Receiver
+        let receiver = PayjoinReceiver::new(setup.original.clone(), &setup.payee, params.clone())?;
+        let mut proposal = receiver.contribute_with_rng(
+            &setup.candidates,
+            payjoin_select_avoid_uih(),
+            &mut OsRng,
+        )?;
+        proposal
+            .psbt_mut()
+            .sign(&setup.receiver_signer, &secp)
+            .expect("signing failed");
+        proposal.finalize()?;
+        let proposal = proposal.into_psbt();
+        assert_eq!(proposal.inputs.len(), 2);
+        assert!(proposal
+            .inputs
+            .iter()
+            .any(|psbt_input| psbt_input.final_script_witness.is_none()));
+
+        // Sender
+        let sender = PayjoinSender::new(setup.original.clone(), setup.payee.clone(), params);
+        let mut psbt = sender.process_proposal(proposal, &setup.sender_finalizer)?;
+        psbt.sign(&setup.sender_signer, &secp)
+            .expect("signing failed");
+        assert!(setup.sender_finalizer.finalize(&mut psbt).is_finalized());
+        let tx = psbt.extract_tx()?;
+
+        let original_tx = setup.original.extract_tx()?;
+        assert_eq!(tx.input.len(), 2);
+        assert!(tx.output[0].value > original_tx.output[0].value);
+        let contribution = original_tx.output[1].value - tx.output[1].value;
+        assert!(contribution > Amount::ZERO);
+        assert!(contribution <= Amount::from_sat(1_000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_payjoin_receiver_pays_fee_without_fee_output() -> anyhow::Result<()> {
+        let setup = setup()?;
+        let receiver = PayjoinReceiver::new(
+            setup.original.clone(),
+            &setup.payee,
+            PayjoinParams::default(),
+        )?;
+        let proposal =
+            receiver.contribute_with_rng(&setup.candidates, payjoin_select_first(), &mut OsRng)?;
+        let original_tx = setup.original.extract_tx()?;
+        let outputs = &proposal.psbt().unsigned_tx.output;
+        assert_eq!(outputs[1].value, original_tx.output[1].value);
+        let received = outputs[0].value - original_tx.output[0].value;
+        assert!(received < Amount::from_sat(80_000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_payjoin_sender_rejects_excessive_fee_contribution() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let setup = setup()?;
+        let params = PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: Amount::from_sat(1_000),
+            ..Default::default()
+        };
+        let receiver = PayjoinReceiver::new(setup.original.clone(), &setup.payee, params.clone())?;
+        let mut proposal =
+            receiver.contribute_with_rng(&setup.candidates, payjoin_select_first(), &mut OsRng)?;
+        // Take more from the sender's change than allowed.
+        proposal.psbt_mut().unsigned_tx.output[1].value -= Amount::from_sat(5_000);
+        proposal.psbt_mut().unsigned_tx.output[0].value += Amount::from_sat(5_000);
+        proposal
+            .psbt_mut()
+            .sign(&setup.receiver_signer, &secp)
+            .expect("signing failed");
+        proposal.finalize()?;
+
+        let sender = PayjoinSender::new(setup.original, setup.payee, params);
+        let result = sender.process_proposal(proposal.into_psbt(), &setup.sender_finalizer);
+        assert!(matches!(
+            result,
+            Err(PayjoinProposalError::FeeContributionExceedsMaximum { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_payjoin_sender_rejects_modified_output() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let setup = setup()?;
+        let params = PayjoinParams::default();
+        let receiver = PayjoinReceiver::new(setup.original.clone(), &setup.payee, params.clone())?;
+        let mut proposal =
+            receiver.contribute_with_rng(&setup.candidates, payjoin_select_first(), &mut OsRng)?;
+        // Without an additional fee output, the sender's change must not change.
+        proposal.psbt_mut().unsigned_tx.output[1].value -= Amount::from_sat(500);
+        proposal
+            .psbt_mut()
+            .sign(&setup.receiver_signer, &secp)
+            .expect("signing failed");
+        proposal.finalize()?;
+
+        let sender = PayjoinSender::new(setup.original, setup.payee, params);
+        let result = sender.process_proposal(proposal.into_psbt(), &setup.sender_finalizer);
+        assert!(matches!(
+            result,
+            Err(PayjoinProposalError::ModifiedOriginalOutput(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_payjoin_receiver_rejects_unfinalized_original() -> anyhow::Result<()> {
+        let (sender_input, _) = input_at(TR_XPRV, 0, 100_000)?;
+        let payee = spk_at(TR_XPRV_3, 1)?;
+        let selection = Selection::new(
+            vec![sender_input],
+            vec![Output::with_script(payee.clone(), Amount::from_sat(50_000))],
+        );
+        let original = selection.create_psbt(PsbtParams::default())?;
+        assert!(matches!(
+            PayjoinReceiver::new(original, &payee, PayjoinParams::default()),
+            Err(PayjoinReceiverError::OriginalInputNotFinalized(0))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,624 @@
+use crate::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use bitcoin::{absolute, Amount, OutPoint, Psbt, ScriptBuf, Sequence};
+use miniscript::{bitcoin, psbt::PsbtExt};
+
+use crate::input::{estimate_tx_weight, final_satisfaction_weight};
+use crate::verify::spent_txout;
+use crate::{DefiniteDescriptor, FeeCapError, FeeCaps, InputScriptError, Output};
+
+/// Verifies a PSBT received from an untrusted party against what we expect it to contain, before
+/// signing it.
+///
+/// The expectations are given as public fields, all of which are optional:
+///
+/// - Every output of [`recipients`] must be paid with its exact script pubkey and value. Silent
+///   payment recipients must be resolved, as their script pubkey depends on the inputs.
+/// - Any other output must be change to one of the [`change_descriptors`], with the
+///   `bip32_derivation` / `tap_key_origins` of the PSBT output matching the descriptor. This makes
+///   sure that a signer displaying such an output as change is not fooled.
+/// - Inputs must be part of [`allowed_inputs`], if set.
+/// - The fee must not exceed the [`fee_caps`]. The fee is computed from the `non_witness_utxo` of
+///   each input when present, which must be the spent transaction and agree with the
+///   `witness_utxo`. Only taproot inputs may have just a `witness_utxo`, as their signatures
+///   commit to the value of every spent output. When fee caps are set, other inputs without a
+///   `non_witness_utxo` are reported, since a lying `witness_utxo` value could hide the fee.
+/// - The locktime and the sequences of inputs must match [`lock_time`] and [`sequences`].
+///
+/// [`verify`] returns every [`Discrepancy`] found.
+///
+/// [`recipients`]: PsbtVerifier::recipients
+/// [`change_descriptors`]: PsbtVerifier::change_descriptors
+/// [`allowed_inputs`]: PsbtVerifier::allowed_inputs
+/// [`fee_caps`]: PsbtVerifier::fee_caps
+/// [`lock_time`]: PsbtVerifier::lock_time
+/// [`sequences`]: PsbtVerifier::sequences
+/// [`verify`]: PsbtVerifier::verify
+#[derive(Debug, Clone, Default)]
+pub struct PsbtVerifier {
+    /// Outputs that must be paid.
+    pub recipients: Vec<Output>,
+    /// Descriptors that the remaining outputs must be change to.
+    pub change_descriptors: Vec<DefiniteDescriptor>,
+    /// Outpoints the PSBT is allowed to spend, `None` to allow any.
+    pub allowed_inputs: Option<BTreeSet<OutPoint>>,
+    /// Caps on the fee.
+    ///
+    /// The feerate is computed with the estimated weight of the finalized transaction, and the
+    /// amount sent is the value of every output that is not change.
+    pub fee_caps: FeeCaps,
+    /// Satisfaction weight of the input spending each outpoint, as counted by
+    /// [`Plan::satisfaction_weight`], for estimating the finalized weight.
+    ///
+    /// Finalized inputs are counted with their final scriptSig and witness. Other inputs without
+    /// a satisfaction weight are counted with an empty scriptSig, which overestimates the feerate.
+    ///
+    /// [`Plan::satisfaction_weight`]: miniscript::plan::Plan::satisfaction_weight
+    pub satisfaction_weights: BTreeMap<OutPoint, u64>,
+    /// Expected locktime.
+    pub lock_time: Option<absolute::LockTime>,
+    /// Expected sequence of the input spending each outpoint.
+    pub sequences: BTreeMap<OutPoint, Sequence>,
+}
+
+impl PsbtVerifier {
+    /// Verify the `psbt`, returning every [`Discrepancy`] from the expectations.
+    pub fn verify(&self, psbt: &Psbt) -> Result<(), Discrepancies> {
+        let mut discrepancies = Vec::new();
+        let tx = &psbt.unsigned_tx;
+
+        let mut input_value = Some(Amount::ZERO);
+        let mut input_weights = Vec::with_capacity(tx.input.len());
+        for (input_index, (txin, psbt_input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+            let outpoint = txin.previous_output;
+            match spent_txout(psbt_input, outpoint) {
+                Ok(txout) => {
+                    if self.fee_caps != FeeCaps::default()
+                        && psbt_input.non_witness_utxo.is_none()
+                        && !txout.script_pubkey.is_p2tr()
+                    {
+                        discrepancies.push(Discrepancy::UnverifiedInputValue { input_index });
+                    }
+                    input_value = input_value.and_then(|v| v.checked_add(txout.value));
+                }
+                Err(InputScriptError::UtxoMismatch) => {
+                    discrepancies.push(Discrepancy::UtxoMismatch { input_index });
+                    input_value = None;
+                }
+                Err(_) => input_value = None,
+            }
+            let is_finalized =
+                psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some();
+            let satisfaction_weight = if is_finalized {
+                final_satisfaction_weight(psbt_input)
+            } else {
+                // an empty scriptSig
+                self.satisfaction_weights
+                    .get(&outpoint)
+                    .copied()
+                    .unwrap_or(4)
+            };
+            let is_segwit =
+                psbt_input.final_script_witness.is_some() || psbt_input.witness_utxo.is_some();
+            input_weights.push((satisfaction_weight, is_segwit));
+
+            if let Some(allowed_inputs) = &self.allowed_inputs {
+                if !allowed_inputs.contains(&outpoint) {
+                    discrepancies.push(Discrepancy::UnexpectedInput {
+                        input_index,
+                        outpoint,
+                    });
+                }
+            }
+            if let Some(&expected) = self.sequences.get(&outpoint) {
+                if txin.sequence != expected {
+                    discrepancies.push(Discrepancy::SequenceMismatch {
+                        input_index,
+                        expected,
+                        actual: txin.sequence,
+                    });
+                }
+            }
+        }
+
+        let mut change_value = Amount::ZERO;
+        let mut matched = alloc::vec![false; tx.output.len()];
+        for (recipient_index, recipient) in self.recipients.iter().enumerate() {
+            if recipient.silent_payment().is_some() {
+                discrepancies.push(Discrepancy::UnresolvedSilentPayment { recipient_index });
+                continue;
+            }
+            let script_pubkey = recipient.script_pubkey();
+            let output_index = (0..tx.output.len())
+                .filter(|&i| !matched[i] && tx.output[i].script_pubkey == script_pubkey)
+                .min_by_key(|&i| tx.output[i].value != recipient.value);
+            match output_index {
+                Some(output_index) => {
+                    matched[output_index] = true;
+                    let actual = tx.output[output_index].value;
+                    if actual != recipient.value {
+                        discrepancies.push(Discrepancy::RecipientAmountMismatch {
+                            output_index,
+                            expected: recipient.value,
+                            actual,
+                        });
+                    }
+                }
+                None => discrepancies.push(Discrepancy::MissingRecipient {
+                    recipient_index,
+                    script_pubkey,
+                    value: recipient.value,
+                }),
+            }
+        }
+        for output_index in (0..tx.output.len()).filter(|&i| !matched[i]) {
+            let script_pubkey = &tx.output[output_index].script_pubkey;
+            let descriptors = self
+                .change_descriptors
+                .iter()
+                .filter(|desc| desc.script_pubkey() == *script_pubkey)
+                .collect::<Vec<_>>();
+            if descriptors.is_empty() {
+                discrepancies.push(Discrepancy::UnexpectedOutput { output_index });
+            } else if descriptors
+                .iter()
+                .any(|desc| has_change_derivation(psbt, output_index, desc))
+            {
+                change_value += tx.output[output_index].value;
+            } else {
+                discrepancies.push(Discrepancy::ChangeDerivationMismatch { output_index });
+            }
+        }
+
+        let output_value = tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+        match input_value.and_then(|v| v.checked_sub(output_value)) {
+            Some(fee) => {
+                let weight = estimate_tx_weight(tx, input_weights);
+                let send_amount = output_value - change_value;
+                if let Err(e) = self.fee_caps.check(fee, weight, send_amount) {
+                    discrepancies.push(Discrepancy::FeeCap(e));
+                }
+            }
+            None if self.fee_caps != FeeCaps::default() => {
+                discrepancies.push(Discrepancy::UnknownFee);
+            }
+            None => {}
+        }
+
+        if let Some(expected) = self.lock_time {
+            if tx.lock_time != expected {
+                discrepancies.push(Discrepancy::LockTimeMismatch {
+                    expected,
+                    actual: tx.lock_time,
+                });
+            }
+        }
+
+        if discrepancies.is_empty() {
+            Ok(())
+        } else {
+            Err(Discrepancies(discrepancies))
+        }
+    }
+}
+
+/// Whether the key origins of the PSBT output are the ones derived from the change `descriptor`.
+fn has_change_derivation(
+    psbt: &Psbt,
+    output_index: usize,
+    descriptor: &DefiniteDescriptor,
+) -> bool {
+    if output_index >= psbt.outputs.len() {
+        return false;
+    }
+    let mut expected = psbt.clone();
+    expected.outputs[output_index] = Default::default();
+    if expected
+        .update_output_with_descriptor(output_index, descriptor)
+        .is_err()
+    {
+        return false;
+    }
+    let expected = &expected.outputs[output_index];
+    let actual = &psbt.outputs[output_index];
+    expected.bip32_derivation == actual.bip32_derivation
+        && expected.tap_internal_key == actual.tap_internal_key
+        && expected.tap_key_origins == actual.tap_key_origins
+}
+
+/// A difference between a PSBT and the expectations of a [`PsbtVerifier`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discrepancy {
+    /// The input spends an outpoint that is not allowed.
+    UnexpectedInput {
+        /// Index of the input.
+        input_index: usize,
+        /// The spent outpoint.
+        outpoint: OutPoint,
+    },
+    /// The input has an unexpected sequence.
+    SequenceMismatch {
+        /// Index of the input.
+        input_index: usize,
+        /// The expected sequence.
+        expected: Sequence,
+        /// The actual sequence.
+        actual: Sequence,
+    },
+    /// No output pays the recipient.
+    MissingRecipient {
+        /// Index of the recipient in [`PsbtVerifier::recipients`].
+        recipient_index: usize,
+        /// The script pubkey of the recipient.
+        script_pubkey: ScriptBuf,
+        /// The value the recipient should be paid.
+        value: Amount,
+    },
+    /// The recipient pays to a silent payment address that is not resolved, so the output paying
+    /// it cannot be recognized.
+    UnresolvedSilentPayment {
+        /// Index of the recipient in [`PsbtVerifier::recipients`].
+        recipient_index: usize,
+    },
+    /// The output pays a recipient a different amount.
+    RecipientAmountMismatch {
+        /// Index of the output.
+        output_index: usize,
+        /// The expected value.
+        expected: Amount,
+        /// The actual value.
+        actual: Amount,
+    },
+    /// The output is neither a recipient nor change.
+    UnexpectedOutput {
+        /// Index of the output.
+        output_index: usize,
+    },
+    /// The output pays to a change descriptor, but its key origins don't match the descriptor.
+    ChangeDerivationMismatch {
+        /// Index of the output.
+        output_index: usize,
+    },
+    /// The `non_witness_utxo` of the input is not the spent transaction, or disagrees with the
+    /// `witness_utxo`.
+    UtxoMismatch {
+        /// Index of the input.
+        input_index: usize,
+    },
+    /// The input is not taproot and has no `non_witness_utxo`, so its value cannot be trusted
+    /// for checking the [`PsbtVerifier::fee_caps`].
+    UnverifiedInputValue {
+        /// Index of the input.
+        input_index: usize,
+    },
+    /// The fee exceeds one of the [`PsbtVerifier::fee_caps`].
+    FeeCap(FeeCapError),
+    /// The fee cannot be computed because of missing or invalid utxos.
+    UnknownFee,
+    /// The transaction has an unexpected locktime.
+    LockTimeMismatch {
+        /// The expected locktime.
+        expected: absolute::LockTime,
+        /// The actual locktime.
+        actual: absolute::LockTime,
+    },
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedInput {
+                input_index,
+                outpoint,
+            } => write!(
+                f,
+                "input {input_index} spends unexpected outpoint {outpoint}"
+            ),
+            Self::SequenceMismatch {
+                input_index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "input {input_index} has sequence {actual}, expected {expected}"
+            ),
+            Self::MissingRecipient {
+                recipient_index,
+                value,
+                ..
+            } => write!(f, "recipient {recipient_index} of {value} is not paid"),
+            Self::UnresolvedSilentPayment { recipient_index } => write!(
+                f,
+                "recipient {recipient_index} is an unresolved silent payment address"
+            ),
+            Self::RecipientAmountMismatch {
+                output_index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "output {output_index} pays {actual}, expected {expected}"
+            ),
+            Self::UnexpectedOutput { output_index } => {
+                write!(f, "output {output_index} is neither a recipient nor change")
+            }
+            Self::ChangeDerivationMismatch { output_index } => write!(
+                f,
+                "output {output_index} has key origins that don't match the change descriptor"
+            ),
+            Self::UtxoMismatch { input_index } => write!(
+                f,
+                "non-witness utxo of input {input_index} does not match the spent output"
+            ),
+            Self::UnverifiedInputValue { input_index } => write!(
+                f,
+                "value of input {input_index} cannot be verified without a non-witness utxo"
+            ),
+            Self::FeeCap(e) => write!(f, "{e}"),
+            Self::UnknownFee => write!(f, "fee cannot be computed"),
+            Self::LockTimeMismatch { expected, actual } => {
+                write!(f, "locktime is {actual}, expected {expected}")
+            }
+        }
+    }
+}
+
+/// The discrepancies found by [`PsbtVerifier::verify`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancies(pub Vec<Discrepancy>);
+
+impl Display for Discrepancies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, discrepancy) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{discrepancy}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Discrepancies {}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        assets, descriptor_at, input, parse_descriptor, TR_CHANGE_XPRV, TR_XPRV, WPKH_XPRV,
+    };
+    use crate::{PsbtParams, Selection, SilentPaymentAddress};
+    use bitcoin::{
+        secp256k1::{PublicKey, Secp256k1, SecretKey},
+        FeeRate, TxOut, Weight,
+    };
+
+    #[test]
+    fn test_verify_psbt() -> anyhow::Result<()> {
+        let (desc, keymap) = parse_descriptor(TR_XPRV)?;
+        let desc = desc.at_derivation_index(0)?;
+        let input = input(&desc, &assets(&keymap), Amount::from_sat(100_000), 0, None)?;
+        let outpoint = input.prev_outpoint();
+        let satisfaction_weight = input.satisfaction_weight();
+
+        let recipient = Output::with_script(
+            descriptor_at(TR_XPRV, 42)?.script_pubkey(),
+            Amount::from_sat(60_000),
+        );
+        let change_desc = descriptor_at(TR_CHANGE_XPRV, 3)?;
+        let change = Output::with_descriptor(change_desc.clone(), Amount::from_sat(39_000));
+        let selection = Selection::new(vec![input], vec![recipient.clone(), change]);
+        let psbt = selection.create_psbt(PsbtParams::default())?;
+
+        let verifier = PsbtVerifier {
+            recipients: vec![recipient],
+            change_descriptors: vec![change_desc],
+            allowed_inputs: Some([outpoint].into()),
+            fee_caps: FeeCaps {
+                max_fee: Some(Amount::from_sat(1_000)),
+                max_feerate: Some(FeeRate::from_sat_per_vb_u32(20)),
+                max_fee_fraction_of_send: Some(0.02),
+            },
+            satisfaction_weights: [(outpoint, satisfaction_weight)].into(),
+            lock_time: Some(psbt.unsigned_tx.lock_time),
+            sequences: [(outpoint, psbt.unsigned_tx.input[0].sequence)].into(),
+        };
+        verifier.verify(&psbt)?;
+
+        // The service redirects part of the payment and lies about the change derivation.
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(50_000);
+        tampered.unsigned_tx.output[1].value = Amount::from_sat(40_000);
+        tampered.outputs[1].tap_key_origins.clear();
+        tampered.unsigned_tx.output.push(TxOut {
+            script_pubkey: ScriptBuf::new_op_return([]),
+            value: Amount::from_sat(9_000),
+        });
+        tampered.outputs.push(Default::default());
+        tampered.unsigned_tx.lock_time = absolute::LockTime::from_consensus(1);
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&tampered) else {
+            panic!("verification must fail");
+        };
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::RecipientAmountMismatch {
+                    output_index: 0,
+                    expected: Amount::from_sat(60_000),
+                    actual: Amount::from_sat(50_000),
+                },
+                Discrepancy::ChangeDerivationMismatch { output_index: 1 },
+                Discrepancy::UnexpectedOutput { output_index: 2 },
+                Discrepancy::LockTimeMismatch {
+                    expected: psbt.unsigned_tx.lock_time,
+                    actual: absolute::LockTime::from_consensus(1),
+                },
+            ]
+        );
+
+        // Unexpected inputs and high fees are reported.
+        let verifier = PsbtVerifier {
+            allowed_inputs: Some(BTreeSet::new()),
+            fee_caps: FeeCaps {
+                max_fee: Some(Amount::from_sat(999)),
+                ..verifier.fee_caps
+            },
+            ..verifier.clone()
+        };
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&psbt) else {
+            panic!("verification must fail");
+        };
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::UnexpectedInput {
+                    input_index: 0,
+                    outpoint,
+                },
+                Discrepancy::FeeCap(FeeCapError::Fee {
+                    fee: Amount::from_sat(1_000),
+                    max_fee: Amount::from_sat(999),
+                }),
+            ]
+        );
+
+        // The feerate is checked against the estimated finalized weight of 616 wu, the unsigned
+        // weight of 548 wu with the satisfaction and the segwit marker and flag.
+        let feerate_verifier = |max_feerate| PsbtVerifier {
+            fee_caps: FeeCaps {
+                max_feerate: Some(max_feerate),
+                ..Default::default()
+            },
+            allowed_inputs: None,
+            ..verifier.clone()
+        };
+        let finalized_feerate = Amount::from_sat(1_000) / Weight::from_wu(616);
+        feerate_verifier(finalized_feerate).verify(&psbt)?;
+        let Err(Discrepancies(discrepancies)) = feerate_verifier(FeeRate::from_sat_per_kwu(
+            finalized_feerate.to_sat_per_kwu() - 1,
+        ))
+        .verify(&psbt) else {
+            panic!("verification must fail");
+        };
+        assert!(matches!(
+            discrepancies.last(),
+            Some(Discrepancy::FeeCap(FeeCapError::FeeRate { .. }))
+        ));
+
+        // The fee is computed from the non-witness utxo, which must match the witness utxo.
+        let mut lying = psbt.clone();
+        lying.inputs[0]
+            .witness_utxo
+            .as_mut()
+            .expect("must have witness utxo")
+            .value = Amount::from_sat(99_500);
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&lying) else {
+            panic!("verification must fail");
+        };
+        assert!(discrepancies.contains(&Discrepancy::UtxoMismatch { input_index: 0 }));
+        assert!(discrepancies.contains(&Discrepancy::UnknownFee));
+
+        Ok(())
+    }
+    #[test]
+    fn test_verify_psbt_witness_utxo_value() -> anyhow::Result<()> {
+        let recipient = Output::with_script(
+            descriptor_at(TR_XPRV, 42)?.script_pubkey(),
+            Amount::from_sat(60_000),
+        );
+        let verifier = PsbtVerifier {
+            recipients: vec![recipient.clone()],
+            fee_caps: FeeCaps {
+                max_fee: Some(Amount::from_sat(1_000)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let psbt_of = |descriptor| -> anyhow::Result<Psbt> {
+            let (desc, keymap) = parse_descriptor(descriptor)?;
+            let desc = desc.at_derivation_index(0)?;
+            let input = input(&desc, &assets(&keymap), Amount::from_sat(100_000), 0, None)?;
+            let selection = Selection::new(vec![input], vec![recipient.clone()]);
+            let mut psbt = selection.create_psbt(PsbtParams::default())?;
+            psbt.inputs[0].non_witness_utxo = None;
+            Ok(psbt)
+        };
+
+        // The fee of 40k sats is hidden by a witness utxo value lowered to 61k sats. A segwit v0
+        // signature only commits to the value of its own input, so the signer would pay it.
+        let mut psbt = psbt_of(WPKH_XPRV)?;
+        psbt.inputs[0]
+            .witness_utxo
+            .as_mut()
+            .expect("must have witness utxo")
+            .value = Amount::from_sat(61_000);
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&psbt) else {
+            panic!("verification must fail");
+        };
+        assert_eq!(
+            discrepancies,
+            vec![Discrepancy::UnverifiedInputValue { input_index: 0 }]
+        );
+
+        // Without fee caps the value is not needed.
+        PsbtVerifier {
+            fee_caps: FeeCaps::default(),
+            ..verifier.clone()
+        }
+        .verify(&psbt)?;
+
+        // A taproot signature commits to the value of every spent output.
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&psbt_of(TR_XPRV)?) else {
+            panic!("verification must fail");
+        };
+        assert!(matches!(
+            discrepancies[..],
+            [Discrepancy::FeeCap(FeeCapError::Fee { .. })]
+        ));
+
+        Ok(())
+    }
+    #[test]
+    fn test_verify_psbt_unresolved_silent_payment() -> anyhow::Result<()> {
+        let (desc, keymap) = parse_descriptor(TR_XPRV)?;
+        let desc = desc.at_derivation_index(0)?;
+        let input = input(&desc, &assets(&keymap), Amount::from_sat(100_000), 0, None)?;
+        let key =
+            PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[3_u8; 32])?);
+        let recipient = Output::with_silent_payment(
+            SilentPaymentAddress::new(key, key),
+            Amount::from_sat(60_000),
+        );
+
+        // A PSBT paying the placeholder script of the silent payment must not pass.
+        let mut psbt = Selection::new(
+            vec![input],
+            vec![Output::with_script(
+                descriptor_at(TR_XPRV, 42)?.script_pubkey(),
+                Amount::from_sat(60_000),
+            )],
+        )
+        .create_psbt(PsbtParams::default())?;
+        psbt.unsigned_tx.output[0].script_pubkey = recipient.script_pubkey();
+        let verifier = PsbtVerifier {
+            recipients: vec![recipient],
+            ..Default::default()
+        };
+        let Err(Discrepancies(discrepancies)) = verifier.verify(&psbt) else {
+            panic!("verification must fail");
+        };
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::UnresolvedSilentPayment { recipient_index: 0 },
+                Discrepancy::UnexpectedOutput { output_index: 0 },
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -1,11 +1,12 @@
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::Display;
 
 use bitcoin::{absolute, Amount, OutPoint, Transaction, TxOut, Txid};
 use miniscript::bitcoin;
 
 use crate::collections::{HashMap, HashSet};
-use crate::{CanonicalUnspents, Input, RbfParams};
+use crate::{CanonicalUnspents, Input, RbfParams, TxWithStatus};
 
 /// Set of txs to replace.
 pub struct RbfSet {
@@ -15,6 +16,8 @@ pub struct RbfSet {
     descendant_fee: Amount,
     /// Previous outputs spent by the originals.
     prev_txouts: HashMap<OutPoint, TxOut>,
+    /// Txs extracted from [`CanonicalUnspents`] for this set, restored if it is abandoned.
+    extracted: Vec<TxWithStatus<Arc<Transaction>>>,
 }
 
 /// Occurs when the given original tx has no input spend that is still available for spending.
@@ -62,6 +65,7 @@ impl RbfSet {
             txs,
             descendant_fee,
             prev_txouts: prev_txouts.into_iter().collect(),
+            extracted: Vec::new(),
         };
         let no_missing_prevouts = rbf_set.txs.values().all(|tx| {
             tx.input
@@ -75,6 +79,20 @@ impl RbfSet {
         Some(rbf_set)
     }
 
+    /// Record the txs extracted from [`CanonicalUnspents`] for this set.
+    pub(crate) fn with_extracted(mut self, extracted: Vec<TxWithStatus<Arc<Transaction>>>) -> Self {
+        self.extracted = extracted;
+        self
+    }
+
+    /// Txs extracted from [`CanonicalUnspents`] by
+    /// [`extract_replacements`](CanonicalUnspents::extract_replacements), with their statuses.
+    ///
+    /// Empty for a set created with [`new`](Self::new).
+    pub fn extracted(&self) -> &[TxWithStatus<Arc<Transaction>>] {
+        &self.extracted
+    }
+
     /// Txids of the original txs that are to be replaced.
     pub fn txids(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
         self.txs.keys().copied()
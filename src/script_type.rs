@@ -0,0 +1,78 @@
+use core::fmt;
+
+use bitcoin::Script;
+use miniscript::bitcoin;
+
+use crate::{Input, InputGroup, ScriptSource};
+
+/// Type of a standard output script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScriptType {
+    /// Pay to public key hash.
+    P2pkh,
+    /// Pay to script hash.
+    P2sh,
+    /// Pay to witness public key hash.
+    P2wpkh,
+    /// Pay to witness script hash.
+    P2wsh,
+    /// Pay to taproot.
+    P2tr,
+}
+
+impl ScriptType {
+    /// Get the type of the `script`, or `None` if it is not one of the standard types.
+    pub fn from_script(script: &Script) -> Option<Self> {
+        if script.is_p2pkh() {
+            Some(Self::P2pkh)
+        } else if script.is_p2sh() {
+            Some(Self::P2sh)
+        } else if script.is_p2wpkh() {
+            Some(Self::P2wpkh)
+        } else if script.is_p2wsh() {
+            Some(Self::P2wsh)
+        } else if script.is_p2tr() {
+            Some(Self::P2tr)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::P2pkh => write!(f, "p2pkh"),
+            Self::P2sh => write!(f, "p2sh"),
+            Self::P2wpkh => write!(f, "p2wpkh"),
+            Self::P2wsh => write!(f, "p2wsh"),
+            Self::P2tr => write!(f, "p2tr"),
+        }
+    }
+}
+
+impl ScriptSource {
+    /// Get the [`ScriptType`] of the output script.
+    ///
+    /// A silent payment is always [`ScriptType::P2tr`].
+    pub fn script_type(&self) -> Option<ScriptType> {
+        ScriptType::from_script(&self.script())
+    }
+}
+
+impl Input {
+    /// Get the [`ScriptType`] of the output spent by this input.
+    pub fn script_type(&self) -> Option<ScriptType> {
+        ScriptType::from_script(&self.prev_txout().script_pubkey)
+    }
+}
+
+impl InputGroup {
+    /// Get the [`ScriptType`] shared by all inputs of the group, or `None` if the inputs have
+    /// different or non-standard script types.
+    pub fn script_type(&self) -> Option<ScriptType> {
+        let script_type = self.inputs().first()?.script_type()?;
+        self.all(|input| input.script_type() == Some(script_type))
+            .then_some(script_type)
+    }
+}
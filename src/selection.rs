@@ -4,13 +4,19 @@ use core::cmp::Ordering;
 use core::fmt::{Debug, Display};
 
 use miniscript::bitcoin;
-use miniscript::bitcoin::{absolute, transaction, OutPoint, Psbt, Sequence};
+use miniscript::bitcoin::{
+    absolute,
+    psbt::{GetKey, PsbtSighashType},
+    secp256k1, transaction, Amount, OutPoint, Psbt, Sequence, TxOut,
+};
 use miniscript::psbt::PsbtExt;
 use rand_core::RngCore;
 
+use crate::collections::BTreeMap;
 use crate::{
-    apply_anti_fee_sniping, fisher_yates_shuffle, AntiFeeSnipingError, Finalizer, Input, InputMut,
-    Output,
+    apply_anti_fee_sniping, estimate_tx_weight, fisher_yates_shuffle, silent_payment_scripts,
+    AntiFeeSnipingError, FeeCapError, FeeCaps, Finalizer, Input, InputMut, Offer, Output,
+    ScriptSource, SilentPaymentError,
 };
 
 /// Final selection of inputs and outputs.
@@ -19,6 +25,9 @@ use crate::{
 pub struct Selection {
     inputs: Vec<Input>,
     outputs: Vec<Output>,
+    /// Change outputs added by the [`Selector`](crate::Selector), which are not part of the
+    /// amount sent.
+    change: Vec<TxOut>,
 }
 
 /// Parameters for creating a psbt.
@@ -71,6 +80,28 @@ pub struct PsbtParams {
     ///
     /// [`min_locktime`]: Self::min_locktime
     pub anti_fee_sniping: Option<absolute::Height>,
+
+    /// Sighash type to request for the plan-based input spending each outpoint.
+    ///
+    /// Sets [`sighash_type`] of the PSBT input, which signers use when signing and the
+    /// [`Finalizer`] requires of the signatures it satisfies with. Inputs without an entry use the
+    /// default sighash type. Inputs created from a [`psbt::Input`] keep their own sighash type, and
+    /// entries for outpoints that are not spent are ignored.
+    ///
+    /// Note that a non-default sighash type adds a byte to taproot signatures, so such inputs
+    /// should be planned with [`TaprootCanSign::sighash_default`] disabled.
+    ///
+    /// [`sighash_type`]: bitcoin::psbt::Input::sighash_type
+    /// [`psbt::Input`]: bitcoin::psbt::Input
+    /// [`TaprootCanSign::sighash_default`]: miniscript::plan::TaprootCanSign::sighash_default
+    pub sighash_types: BTreeMap<OutPoint, PsbtSighashType>,
+
+    /// Caps on the fee of the PSBT, re-checked after it is created.
+    ///
+    /// The fee is computed from the values of the spent outputs, and the weight is estimated
+    /// from the satisfaction weights of the inputs. The amount sent is the total value of the
+    /// outputs, except the change outputs added by the [`Selector`](crate::Selector).
+    pub fee_caps: FeeCaps,
 }
 
 impl Default for PsbtParams {
@@ -80,6 +111,8 @@ impl Default for PsbtParams {
             min_locktime: absolute::LockTime::ZERO,
             mandate_full_tx_for_segwit_v0: true,
             anti_fee_sniping: None,
+            sighash_types: BTreeMap::new(),
+            fee_caps: FeeCaps::default(),
         }
     }
 }
@@ -97,6 +130,10 @@ pub enum CreatePsbtError {
     OutputUpdate(miniscript::psbt::OutputUpdateError),
     /// Occurs when applying anti-fee-sniping fails.
     AntiFeeSniping(AntiFeeSnipingError),
+    /// The output at this index pays to a silent payment address that is not resolved.
+    UnresolvedSilentPayment(usize),
+    /// The PSBT exceeds [`PsbtParams::fee_caps`].
+    FeeCap(FeeCapError),
 }
 
 impl From<AntiFeeSnipingError> for CreatePsbtError {
@@ -123,6 +160,12 @@ impl core::fmt::Display for CreatePsbtError {
                 Display::fmt(&output_update_error, f)
             }
             CreatePsbtError::AntiFeeSniping(e) => Display::fmt(e, f),
+            CreatePsbtError::UnresolvedSilentPayment(output_index) => write!(
+                f,
+                "silent payment output {} must be resolved before creating the PSBT",
+                output_index
+            ),
+            CreatePsbtError::FeeCap(e) => Display::fmt(e, f),
         }
     }
 }
@@ -130,9 +173,50 @@ impl core::fmt::Display for CreatePsbtError {
 #[cfg(feature = "std")]
 impl std::error::Error for CreatePsbtError {}
 
+/// Error when merging an [`Offer`] into a [`Selection`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOfferError {
+    /// The index is beyond the inputs or outputs of the selection.
+    IndexOutOfBounds(usize),
+    /// The selection spends the offer input but does not contain the offer output.
+    MissingOfferOutput,
+}
+
+impl Display for MergeOfferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::IndexOutOfBounds(index) => {
+                write!(f, "index {index} is out of bounds of the selection")
+            }
+            Self::MissingOfferOutput => {
+                write!(
+                    f,
+                    "selection spends the offer input but lacks the offer output"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MergeOfferError {}
+
 impl Selection {
     pub(crate) fn new(inputs: Vec<Input>, outputs: Vec<Output>) -> Self {
-        Self { inputs, outputs }
+        Self::with_change(inputs, outputs, Vec::new())
+    }
+
+    /// Selection whose `outputs` include the `change` outputs added by the selector.
+    pub(crate) fn with_change(
+        inputs: Vec<Input>,
+        outputs: Vec<Output>,
+        change: Vec<TxOut>,
+    ) -> Self {
+        Self {
+            inputs,
+            outputs,
+            change,
+        }
     }
 
     /// Inputs in this selection.
@@ -202,6 +286,98 @@ impl Selection {
         fisher_yates_shuffle(&mut self.outputs, rng);
     }
 
+    /// Place the input and output of `offer` at `index` of the inputs and outputs.
+    ///
+    /// `SIGHASH_SINGLE` requires the signed input and its paired output to share the same index.
+    /// If the selection already spends the offer input (e.g. because it was a must-select
+    /// candidate), the input and the first output matching the offer output are moved to
+    /// `index`. Otherwise both are inserted. Inserting at the same index keeps other
+    /// `SIGHASH_SINGLE` pairs at higher indices paired.
+    ///
+    /// # Errors
+    ///
+    /// - [`MergeOfferError::IndexOutOfBounds`] if `index` is greater than the number of inputs
+    ///   or outputs without the offer.
+    /// - [`MergeOfferError::MissingOfferOutput`] if the selection spends the offer input but does
+    ///   not contain the offer output.
+    pub fn merge_offer(&mut self, offer: &Offer, index: usize) -> Result<(), MergeOfferError> {
+        let outpoint = offer.input().prev_outpoint();
+        let offer_txout = offer.output().txout();
+        let input_pos = self
+            .inputs
+            .iter()
+            .position(|input| input.prev_outpoint() == outpoint);
+        let output_pos = match input_pos {
+            Some(_) => Some(
+                self.outputs
+                    .iter()
+                    .position(|output| output.txout() == offer_txout)
+                    .ok_or(MergeOfferError::MissingOfferOutput)?,
+            ),
+            None => None,
+        };
+        let (input_count, output_count) = match input_pos {
+            Some(_) => (self.inputs.len() - 1, self.outputs.len() - 1),
+            None => (self.inputs.len(), self.outputs.len()),
+        };
+        if index > input_count || index > output_count {
+            return Err(MergeOfferError::IndexOutOfBounds(index));
+        }
+
+        let input = match input_pos {
+            Some(pos) => self.inputs.remove(pos),
+            None => offer.input().clone(),
+        };
+        let output = match output_pos {
+            Some(pos) => self.outputs.remove(pos),
+            None => offer.output().clone(),
+        };
+        self.inputs.insert(index, input);
+        self.outputs.insert(index, output);
+        Ok(())
+    }
+
+    /// Resolve the script pubkeys of outputs paying to silent payment addresses.
+    ///
+    /// The scripts are derived from the private keys of the selected inputs as specified in
+    /// [BIP352], so this must be called after the inputs are final. Reordering inputs afterwards
+    /// is fine, but adding or removing inputs requires resolving again. Multiple outputs to the
+    /// same scan key are given distinct scripts in output order.
+    ///
+    /// Fails if any input is not eligible (see [`filter_silent_payment_eligible`]) or if `signer`
+    /// is missing the private key of any input.
+    ///
+    /// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+    /// [`filter_silent_payment_eligible`]: crate::filter_silent_payment_eligible
+    pub fn resolve_silent_payments<K, C>(
+        &mut self,
+        signer: &K,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), SilentPaymentError>
+    where
+        K: GetKey,
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        let addresses = self
+            .outputs
+            .iter()
+            .filter_map(|output| output.silent_payment())
+            .collect::<Vec<_>>();
+        if addresses.is_empty() {
+            return Ok(());
+        }
+        let scripts = silent_payment_scripts(&self.inputs, addresses, signer, secp)?;
+        for (output, script) in self
+            .outputs
+            .iter_mut()
+            .filter(|output| output.silent_payment().is_some())
+            .zip(scripts)
+        {
+            output.script_pubkey_source = ScriptSource::Script(script);
+        }
+        Ok(())
+    }
+
     /// Accumulates the maximum locktime from an iterator of input-required locktimes.
     ///
     /// Returns `min_locktime` if the locktimes iterator is empty, otherwise the maximum locktime
@@ -250,7 +426,27 @@ impl Selection {
         params: PsbtParams,
         rng: &mut impl RngCore,
     ) -> Result<bitcoin::Psbt, CreatePsbtError> {
-        let mut tx = bitcoin::Transaction {
+        let mut tx = self.unsigned_tx(&params)?;
+        if let Some(tip_height) = params.anti_fee_sniping {
+            apply_anti_fee_sniping(&mut tx, &self.inputs, tip_height, rng)?;
+        };
+        self.psbt_from_unsigned_tx(tx, &params)
+    }
+
+    /// Build the unsigned transaction of this selection, without anti-fee-sniping applied.
+    pub(crate) fn unsigned_tx(
+        &self,
+        params: &PsbtParams,
+    ) -> Result<bitcoin::Transaction, CreatePsbtError> {
+        if let Some(output_index) = self
+            .outputs
+            .iter()
+            .position(|output| output.silent_payment().is_some())
+        {
+            return Err(CreatePsbtError::UnresolvedSilentPayment(output_index));
+        }
+
+        Ok(bitcoin::Transaction {
             version: params.version,
             lock_time: Self::accumulate_max_locktime(
                 self.inputs
@@ -268,12 +464,15 @@ impl Selection {
                 })
                 .collect(),
             output: self.outputs.iter().map(|output| output.txout()).collect(),
-        };
-
-        if let Some(tip_height) = params.anti_fee_sniping {
-            apply_anti_fee_sniping(&mut tx, &self.inputs, tip_height, rng)?;
-        };
+        })
+    }
 
+    /// Create a PSBT from `tx`, which must spend the inputs of this selection in order.
+    pub(crate) fn psbt_from_unsigned_tx(
+        &self,
+        tx: bitcoin::Transaction,
+        params: &PsbtParams,
+    ) -> Result<bitcoin::Psbt, CreatePsbtError> {
         let mut psbt = Psbt::from_unsigned_tx(tx).map_err(CreatePsbtError::Psbt)?;
 
         for (plan_input, psbt_input) in self.inputs.iter().zip(psbt.inputs.iter_mut()) {
@@ -283,6 +482,10 @@ impl Selection {
             }
             if let Some(plan) = plan_input.plan() {
                 plan.update_psbt_input(psbt_input);
+                psbt_input.sighash_type = params
+                    .sighash_types
+                    .get(&plan_input.prev_outpoint())
+                    .copied();
 
                 let witness_version = plan.witness_version();
                 if witness_version.is_some() {
@@ -318,16 +521,71 @@ impl Selection {
             }
         }
 
+        let input_value = self
+            .inputs
+            .iter()
+            .map(|input| input.prev_txout().value)
+            .sum::<Amount>();
+        let output_value = self
+            .outputs
+            .iter()
+            .map(|output| output.value)
+            .sum::<Amount>();
+        let mut change = self.change.clone();
+        let send_amount = self
+            .outputs
+            .iter()
+            .filter(|output| {
+                let txout = output.txout();
+                match change.iter().position(|change| *change == txout) {
+                    Some(pos) => {
+                        change.swap_remove(pos);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .map(|output| output.value)
+            .sum::<Amount>();
+        let weight = estimate_tx_weight(
+            &psbt.unsigned_tx,
+            self.inputs
+                .iter()
+                .map(|input| (input.satisfaction_weight(), input.is_segwit())),
+        );
+        params
+            .fee_caps
+            .check(
+                input_value
+                    .checked_sub(output_value)
+                    .unwrap_or(Amount::ZERO),
+                weight,
+                send_amount,
+            )
+            .map_err(CreatePsbtError::FeeCap)?;
+
         Ok(psbt)
     }
 
     /// Into psbt finalizer.
+    ///
+    /// Inputs created from a [`psbt::Input`](bitcoin::psbt::Input) are finalized with the generic
+    /// PSBT finalizer, see [`Finalizer::with_foreign_inputs`].
+    ///
+    /// If [`PsbtParams::sighash_types`] requested other sighash types, register them with
+    /// [`Finalizer::with_sighash_types`] so that their signatures are used.
     pub fn into_finalizer(self) -> Finalizer {
         Finalizer::new(
             self.inputs
                 .iter()
                 .filter_map(|input| Some((input.prev_outpoint(), input.plan().cloned()?))),
         )
+        .with_foreign_inputs(
+            self.inputs
+                .iter()
+                .filter(|input| input.psbt_input().is_some())
+                .map(Input::prev_outpoint),
+        )
     }
 }
 
@@ -0,0 +1,866 @@
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+
+use bdk_coin_select::{
+    float::Ordf32, metrics::Changeless, BnbMetric, ChangePolicy, CoinSelector, Drain,
+    InsufficientFunds, NoBnbSolution, Target,
+};
+use bitcoin::FeeRate;
+use miniscript::bitcoin;
+use rand_core::RngCore;
+
+use crate::{fisher_yates_shuffle, random_probability, CannotMeetTarget, FeeRateExt, Selector};
+
+/// Number of random subsets tried by [`selection_algorithm_knapsack`], as in Bitcoin Core.
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// Select in random order until the target is met (Single Random Draw).
+///
+/// Randomness makes the selection harder to fingerprint and avoids always spending the same
+/// coins.
+pub fn selection_algorithm_single_random_draw<R: RngCore>(
+    mut rng: R,
+) -> impl FnMut(&mut Selector) -> Result<(), InsufficientFunds> {
+    move |selector| {
+        let target = selector.target();
+        let inner = selector.inner_mut();
+        let mut indices = inner.unselected_indices().collect::<Vec<_>>();
+        fisher_yates_shuffle(&mut indices, &mut rng);
+        for index in indices {
+            if inner.is_target_met(target) {
+                break;
+            }
+            inner.select(index);
+        }
+        inner.select_until_target_met(target)
+    }
+}
+
+/// Select with the knapsack solver of Bitcoin Core.
+///
+/// Picks an exact match if there is one, otherwise approximates the subset of the smaller
+/// candidates that overshoots the target (or the target plus the cost of a change output) the
+/// least over many random passes, and falls back to the smallest candidate larger than the
+/// target if that is better. Values are effective values at the target feerate.
+pub fn selection_algorithm_knapsack<R: RngCore>(
+    mut rng: R,
+) -> impl FnMut(&mut Selector) -> Result<(), InsufficientFunds> {
+    move |selector| {
+        let target = selector.target();
+        let change_policy = selector.cs_change_policy();
+        let inner = selector.inner_mut();
+
+        let needed = -inner.excess(target, Drain::NONE);
+        if needed <= 0 {
+            return Ok(());
+        }
+        let change_target = change_policy.min_value as i64
+            + target
+                .fee
+                .rate
+                .implied_fee(change_policy.drain_weights.output_weight) as i64;
+
+        let mut candidates = inner
+            .unselected()
+            .map(|(index, candidate)| {
+                let value =
+                    candidate.value as i64 - target.fee.rate.implied_fee(candidate.weight) as i64;
+                (index, value)
+            })
+            .filter(|&(_, value)| value > 0)
+            .collect::<Vec<_>>();
+        fisher_yates_shuffle(&mut candidates, &mut rng);
+
+        let mut lower = Vec::<(usize, i64)>::new();
+        let mut lower_total = 0_i64;
+        let mut lowest_larger = Option::<(usize, i64)>::None;
+        for (index, value) in candidates {
+            if value == needed {
+                inner.select(index);
+                return inner.select_until_target_met(target);
+            }
+            if value < needed + change_target {
+                lower.push((index, value));
+                lower_total += value;
+            } else if lowest_larger.is_none_or(|(_, larger)| value < larger) {
+                lowest_larger = Some((index, value));
+            }
+        }
+
+        if lower_total == needed {
+            for &(index, _) in &lower {
+                inner.select(index);
+            }
+            return inner.select_until_target_met(target);
+        }
+        if lower_total < needed {
+            if let Some((index, _)) = lowest_larger {
+                inner.select(index);
+            }
+            return inner.select_until_target_met(target);
+        }
+
+        lower.sort_by_key(|&(_, value)| Reverse(value));
+        let values = lower.iter().map(|&(_, value)| value).collect::<Vec<_>>();
+        let (mut best, mut best_total) =
+            approximate_best_subset(&values, lower_total, needed, &mut rng);
+        if best_total != needed && lower_total >= needed + change_target {
+            (best, best_total) =
+                approximate_best_subset(&values, lower_total, needed + change_target, &mut rng);
+        }
+
+        match lowest_larger {
+            Some((index, larger))
+                if (best_total != needed && best_total < needed + change_target)
+                    || larger <= best_total =>
+            {
+                inner.select(index);
+            }
+            _ => {
+                for (&(index, _), included) in lower.iter().zip(best) {
+                    if included {
+                        inner.select(index);
+                    }
+                }
+            }
+        }
+        inner.select_until_target_met(target)
+    }
+}
+
+/// Randomly include `values` over many passes and return the subset whose sum is the smallest
+/// one reaching `target`, together with that sum.
+fn approximate_best_subset(
+    values: &[i64],
+    total: i64,
+    target: i64,
+    rng: &mut impl RngCore,
+) -> (Vec<bool>, i64) {
+    let mut best = alloc::vec![true; values.len()];
+    let mut best_total = total;
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_total == target {
+            break;
+        }
+        let mut included = alloc::vec![false; values.len()];
+        let mut included_total = 0_i64;
+        let mut reached_target = false;
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for (i, &value) in values.iter().enumerate() {
+                // The first pass includes at random, the second pass fills up the rest.
+                let include = if pass == 0 {
+                    random_probability(rng, 2)
+                } else {
+                    !included[i]
+                };
+                if !include {
+                    continue;
+                }
+                included_total += value;
+                included[i] = true;
+                if included_total >= target {
+                    reached_target = true;
+                    if included_total < best_total {
+                        best_total = included_total;
+                        best.clone_from(&included);
+                    }
+                    included_total -= value;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+    (best, best_total)
+}
+
+/// Select the set of candidates with the lowest input weight that funds the target and a change
+/// output (CoinGrinder).
+///
+/// At high feerates, spending less weight saves more than avoiding change does, so this is
+/// preferable over [`selection_algorithm_lowest_fee_bnb`] when fees are high.
+///
+/// [`selection_algorithm_lowest_fee_bnb`]: crate::selection_algorithm_lowest_fee_bnb
+pub fn selection_algorithm_coin_grinder(
+    max_rounds: usize,
+) -> impl FnMut(&mut Selector) -> Result<(), NoBnbSolution> {
+    move |selector| {
+        let target = selector.target();
+        let change_policy = selector.cs_change_policy();
+        selector
+            .inner_mut()
+            .run_bnb(
+                MinInputWeight {
+                    target,
+                    change_policy,
+                },
+                max_rounds,
+            )
+            .map(|_| ())
+    }
+}
+
+/// Metric minimizing the input weight of selections that fund the target and a change output.
+struct MinInputWeight {
+    target: Target,
+    change_policy: ChangePolicy,
+}
+
+impl MinInputWeight {
+    fn is_funded(&self, cs: &CoinSelector<'_>) -> bool {
+        let drain = Drain {
+            weights: self.change_policy.drain_weights,
+            value: self.change_policy.min_value,
+        };
+        cs.is_target_met_with_drain(self.target, drain)
+    }
+}
+
+impl BnbMetric for MinInputWeight {
+    fn score(&mut self, cs: &CoinSelector<'_>) -> Option<Ordf32> {
+        if !self.is_funded(cs) {
+            return None;
+        }
+        Some(Ordf32(cs.input_weight() as f32))
+    }
+
+    fn bound(&mut self, cs: &CoinSelector<'_>) -> Option<Ordf32> {
+        if self.is_funded(cs) {
+            return self.score(cs);
+        }
+        let mut all = cs.clone();
+        all.select_all_effective(self.target.fee.rate);
+        if !self.is_funded(&all) {
+            return None;
+        }
+        // At least one more candidate is needed.
+        let lightest = cs.unselected().map(|(_, c)| c.weight).min()?;
+        Some(Ordf32((cs.input_weight() + lightest) as f32))
+    }
+
+    fn requires_ordering_by_descending_value_pwu(&self) -> bool {
+        true
+    }
+}
+
+/// Select the candidates with the largest value first until the target is met.
+pub fn selection_algorithm_largest_first(
+) -> impl FnMut(&mut Selector) -> Result<(), InsufficientFunds> {
+    |selector| {
+        let target = selector.target();
+        let inner = selector.inner_mut();
+        inner.sort_candidates_by_key(|(_, candidate)| Reverse(candidate.value));
+        inner.select_until_target_met(target)
+    }
+}
+
+/// Select the candidates with the oldest confirmation first until the target is met.
+///
+/// A group is as old as its most recently confirmed input. Unconfirmed candidates are selected
+/// last.
+pub fn selection_algorithm_oldest_first(
+) -> impl FnMut(&mut Selector) -> Result<(), InsufficientFunds> {
+    |selector| {
+        let target = selector.target();
+        let heights = selector
+            .candidates()
+            .groups()
+            .map(|group| {
+                group
+                    .inputs()
+                    .iter()
+                    .map(|input| {
+                        input
+                            .status()
+                            .map_or(u32::MAX, |status| status.height.to_consensus_u32())
+                    })
+                    .max()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect::<Vec<_>>();
+        let inner = selector.inner_mut();
+        inner.sort_candidates_by_key(|(index, _)| heights[index]);
+        inner.select_until_target_met(target)
+    }
+}
+
+/// Select a changeless solution with branch and bound.
+///
+/// Fails if there is no selection where the excess is small enough to be given up as fee, see
+/// [`SelectorParams::change_min_value`](crate::SelectorParams::change_min_value).
+pub fn selection_algorithm_changeless_bnb(
+    max_rounds: usize,
+) -> impl FnMut(&mut Selector) -> Result<(), NoBnbSolution> {
+    move |selector| {
+        let change_policy = selector.cs_change_policy();
+        run_changeless_bnb(selector, change_policy, u64::MAX, max_rounds)
+    }
+}
+
+/// Run branch and bound for a selection that creates no change under `change_policy`, and whose
+/// excess over the target without change is at most `max_excess`.
+pub(crate) fn run_changeless_bnb(
+    selector: &mut Selector,
+    change_policy: ChangePolicy,
+    max_excess: u64,
+    max_rounds: usize,
+) -> Result<(), NoBnbSolution> {
+    let metric = ChangelessWithin {
+        changeless: Changeless {
+            target: selector.target(),
+            change_policy,
+        },
+        max_excess: i64::try_from(max_excess).unwrap_or(i64::MAX),
+    };
+    selector.inner_mut().run_bnb(metric, max_rounds).map(|_| ())
+}
+
+/// [`Changeless`] metric that also bounds the excess given up as fee.
+struct ChangelessWithin {
+    changeless: Changeless,
+    max_excess: i64,
+}
+
+impl BnbMetric for ChangelessWithin {
+    fn score(&mut self, cs: &CoinSelector<'_>) -> Option<Ordf32> {
+        if cs.excess(self.changeless.target, Drain::NONE) > self.max_excess {
+            return None;
+        }
+        self.changeless.score(cs)
+    }
+
+    fn bound(&mut self, cs: &CoinSelector<'_>) -> Option<Ordf32> {
+        self.changeless.bound(cs)
+    }
+
+    fn requires_ordering_by_descending_value_pwu(&self) -> bool {
+        self.changeless.requires_ordering_by_descending_value_pwu()
+    }
+}
+
+/// Run `first`, and `second` if `first` fails or does not meet the target.
+///
+/// `second` starts from the selector state before `first` ran. Nest to build longer chains,
+/// e.g. `selection_algorithm_fallback(a, selection_algorithm_fallback(b, c))`. Returns the error
+/// of `second`.
+pub fn selection_algorithm_fallback<A, B, EA, EB>(
+    mut first: A,
+    mut second: B,
+) -> impl FnMut(&mut Selector) -> Result<(), EB>
+where
+    A: FnMut(&mut Selector) -> Result<(), EA>,
+    B: FnMut(&mut Selector) -> Result<(), EB>,
+{
+    move |selector| {
+        let mut attempt = selector.clone();
+        if first(&mut attempt).is_ok() && is_target_met(&attempt) {
+            *selector = attempt;
+            return Ok(());
+        }
+        second(selector)
+    }
+}
+
+/// Run both `a` and `b` from the same selector state and keep the result with the lower score
+/// under `metric`, preferring `a` on a tie.
+///
+/// Results that do not meet the target are discarded. Nest to compare more algorithms. If
+/// neither meets the target, the result of `b` is kept and its error returned, or
+/// [`BestOfError::CannotMeetTarget`] if `b` did not fail.
+pub fn selection_algorithm_best_of<A, B, EA, EB>(
+    metric: SelectionMetric,
+    mut a: A,
+    mut b: B,
+) -> impl FnMut(&mut Selector) -> Result<(), BestOfError<EB>>
+where
+    A: FnMut(&mut Selector) -> Result<(), EA>,
+    B: FnMut(&mut Selector) -> Result<(), EB>,
+{
+    move |selector| {
+        let mut attempt_a = selector.clone();
+        let a_met = a(&mut attempt_a).is_ok() && is_target_met(&attempt_a);
+        let mut attempt_b = selector.clone();
+        let result_b = b(&mut attempt_b);
+        let b_met = result_b.is_ok() && is_target_met(&attempt_b);
+        *selector = match (a_met, b_met) {
+            (true, true) if metric.score(&attempt_b) < metric.score(&attempt_a) => attempt_b,
+            (true, _) => attempt_a,
+            (false, true) => attempt_b,
+            (false, false) => {
+                *selector = attempt_b;
+                return Err(match result_b {
+                    Err(e) => BestOfError::Algorithm(e),
+                    Ok(()) => BestOfError::CannotMeetTarget(selector.cannot_meet_target()),
+                });
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Run `above` if the target feerate is higher than `threshold`, otherwise run `below`.
+///
+/// The target feerate includes the minimum feerate required to replace transactions, see
+/// [`SelectorParams::replace`](crate::SelectorParams::replace).
+pub fn selection_algorithm_by_feerate<A, B, EA, EB>(
+    threshold: FeeRate,
+    mut above: A,
+    mut below: B,
+) -> impl FnMut(&mut Selector) -> Result<(), FeeRateBranchError<EA, EB>>
+where
+    A: FnMut(&mut Selector) -> Result<(), EA>,
+    B: FnMut(&mut Selector) -> Result<(), EB>,
+{
+    let threshold = threshold.into_cs_feerate();
+    move |selector| {
+        if selector.target().fee.rate > threshold {
+            above(selector).map_err(FeeRateBranchError::Above)
+        } else {
+            below(selector).map_err(FeeRateBranchError::Below)
+        }
+    }
+}
+
+/// Error of [`selection_algorithm_by_feerate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeeRateBranchError<A, B> {
+    /// The algorithm for feerates above the threshold failed.
+    Above(A),
+    /// The algorithm for feerates at or below the threshold failed.
+    Below(B),
+}
+
+impl<A: fmt::Display, B: fmt::Display> fmt::Display for FeeRateBranchError<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Above(e) => write!(f, "{e}"),
+            Self::Below(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: fmt::Debug + fmt::Display, B: fmt::Debug + fmt::Display> std::error::Error
+    for FeeRateBranchError<A, B>
+{
+}
+
+/// Error of [`selection_algorithm_best_of`] when neither algorithm meets the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BestOfError<E> {
+    /// The second algorithm failed.
+    Algorithm(E),
+    /// The second algorithm did not fail, but its selection does not meet the target.
+    CannotMeetTarget(CannotMeetTarget),
+}
+
+impl<E: fmt::Display> fmt::Display for BestOfError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Algorithm(e) => write!(f, "{e}"),
+            Self::CannotMeetTarget(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for BestOfError<E> {}
+
+/// Metric to compare selections with [`selection_algorithm_best_of`]. Lower is better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionMetric {
+    /// The waste of the selection, given the feerate at which inputs are expected to be spent in
+    /// the long term.
+    Waste {
+        /// Long-term feerate.
+        long_term_feerate: FeeRate,
+    },
+    /// The fee paid by the selection.
+    Fee,
+    /// The number of selected inputs.
+    InputCount,
+}
+
+impl SelectionMetric {
+    /// Score the current selection of the `selector`.
+    pub fn score(&self, selector: &Selector) -> f32 {
+        let target = selector.target();
+        let inner = selector.inner();
+        let drain = inner.drain(target, selector.cs_change_policy());
+        match self {
+            Self::Waste { long_term_feerate } => {
+                inner.waste(target, long_term_feerate.into_cs_feerate(), drain, 1.0)
+            }
+            Self::Fee => inner.fee(target.value(), drain.value) as f32,
+            Self::InputCount => inner
+                .selected()
+                .map(|(_, candidate)| candidate.input_count)
+                .sum::<usize>() as f32,
+        }
+    }
+}
+
+fn is_target_met(selector: &Selector) -> bool {
+    selector.inner().is_target_met(selector.target())
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        assets, descriptor_at, input, parse_descriptor, PKH_XPRV, TR_XPRV, WPKH_XPRV,
+    };
+    use crate::{
+        ChangeScript, ConfirmationStatus, Input, InputCandidates, InputScriptTypes, Output,
+        ScriptType, SelectorParams,
+    };
+    use bitcoin::{Amount, FeeRate, ScriptBuf, Weight};
+    use miniscript::bitcoin;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Input candidates of the given values and confirmation heights.
+    fn candidates(coins: &[(u64, Option<u32>)]) -> anyhow::Result<InputCandidates> {
+        Ok(InputCandidates::new([], inputs(WPKH_XPRV, coins)?))
+    }
+
+    /// Inputs of `descriptor` with the given values and confirmation heights.
+    fn inputs(descriptor: &str, coins: &[(u64, Option<u32>)]) -> anyhow::Result<Vec<Input>> {
+        let (desc, keymap) = parse_descriptor(descriptor)?;
+        let assets = assets(&keymap);
+        let mut inputs = Vec::new();
+        for (index, &(value, height)) in coins.iter().enumerate() {
+            let desc = desc.at_derivation_index(index as u32)?;
+            let status = height
+                .map(|height| ConfirmationStatus::new(height, None))
+                .transpose()?;
+            let value = Amount::from_sat(value);
+            inputs.push(input(&desc, &assets, value, index as u32, status)?);
+        }
+        Ok(inputs)
+    }
+
+    /// Select for a recipient of `value` and return the values of the selected inputs.
+    fn select<A, E>(
+        candidates: &InputCandidates,
+        feerate: FeeRate,
+        value: u64,
+        algorithm: A,
+    ) -> anyhow::Result<Vec<u64>>
+    where
+        A: FnMut(&mut Selector) -> Result<(), E>,
+        E: core::fmt::Debug,
+    {
+        let change_script = ChangeScript::from_descriptor(descriptor_at(WPKH_XPRV, 100)?);
+        let recipient = descriptor_at(WPKH_XPRV, 101)?.script_pubkey();
+        let params = SelectorParams::new(
+            feerate,
+            vec![Output::with_script(recipient, Amount::from_sat(value))],
+            change_script,
+        );
+        let selection = candidates
+            .clone()
+            .into_selection(algorithm, params)
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let mut values = selection
+            .inputs()
+            .iter()
+            .map(|input| input.prev_txout().value.to_sat())
+            .collect::<Vec<_>>();
+        values.sort();
+        Ok(values)
+    }
+
+    #[test]
+    fn test_single_random_draw() -> anyhow::Result<()> {
+        let candidates = candidates(&[
+            (10_000, Some(1)),
+            (20_000, Some(2)),
+            (40_000, Some(3)),
+            (80_000, Some(4)),
+            (160_000, Some(5)),
+        ])?;
+        let feerate = FeeRate::from_sat_per_vb_u32(2);
+        let draw = |seed| {
+            let rng = StdRng::seed_from_u64(seed);
+            select(
+                &candidates,
+                feerate,
+                50_000,
+                selection_algorithm_single_random_draw(rng),
+            )
+        };
+        let selected = draw(42)?;
+        assert!(selected.iter().sum::<u64>() > 50_000);
+        assert_eq!(draw(42)?, selected);
+        // Different seeds draw different coins.
+        assert!((0..10)
+            .map(draw)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .iter()
+            .any(|other| *other != selected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_knapsack() -> anyhow::Result<()> {
+        let candidates = candidates(&[
+            (10_000, Some(1)),
+            (20_000, Some(2)),
+            (40_000, Some(3)),
+            (80_000, Some(4)),
+            (500_000, Some(5)),
+        ])?;
+        let feerate = FeeRate::from_sat_per_vb_u32(2);
+        for seed in 0..5 {
+            let rng = StdRng::seed_from_u64(seed);
+            // The subset of smaller coins overshoots the least while still affording change.
+            let selected = select(
+                &candidates,
+                feerate,
+                55_000,
+                selection_algorithm_knapsack(rng),
+            )?;
+            assert_eq!(selected, vec![20_000, 40_000]);
+        }
+
+        // Without enough smaller coins, the smallest larger coin is used.
+        let rng = StdRng::seed_from_u64(0);
+        let selected = select(
+            &candidates,
+            feerate,
+            75_000,
+            selection_algorithm_knapsack(rng),
+        )?;
+        assert_eq!(selected, vec![80_000]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_coin_grinder() -> anyhow::Result<()> {
+        // A heavy legacy coin covers the target alone, but two light taproot coins weigh less
+        // than it or any pair with a wpkh coin.
+        let mut coins = inputs(PKH_XPRV, &[(150_000, Some(1))])?;
+        coins.extend(inputs(WPKH_XPRV, &[(61_000, Some(1)), (62_000, Some(1))])?);
+        coins.extend(inputs(TR_XPRV, &[(60_000, Some(1)), (65_000, Some(1))])?);
+        let candidates = InputCandidates::new([], coins);
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        assert_eq!(
+            select(
+                &candidates,
+                feerate,
+                100_000,
+                selection_algorithm_coin_grinder(100_000),
+            )?,
+            vec![60_000, 65_000]
+        );
+        assert_eq!(
+            select(
+                &candidates,
+                feerate,
+                100_000,
+                selection_algorithm_largest_first(),
+            )?,
+            vec![150_000]
+        );
+        assert!(select(
+            &candidates,
+            feerate,
+            1_000_000,
+            selection_algorithm_coin_grinder(100_000),
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_largest_and_oldest_first() -> anyhow::Result<()> {
+        let candidates = candidates(&[
+            (30_000, Some(5)),
+            (10_000, Some(1)),
+            (200_000, None),
+            (50_000, Some(3)),
+        ])?;
+        let feerate = FeeRate::from_sat_per_vb_u32(2);
+        assert_eq!(
+            select(
+                &candidates,
+                feerate,
+                60_000,
+                selection_algorithm_largest_first()
+            )?,
+            vec![200_000]
+        );
+        assert_eq!(
+            select(
+                &candidates,
+                feerate,
+                60_000,
+                selection_algorithm_oldest_first()
+            )?,
+            vec![10_000, 30_000, 50_000]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strategy_combinators() -> anyhow::Result<()> {
+        let candidates = candidates(&[
+            (60_000, Some(1)),
+            (60_000, Some(2)),
+            (130_000, Some(3)),
+            (500_000, Some(4)),
+        ])?;
+        let low = FeeRate::from_sat_per_vb_u32(2);
+        let high = FeeRate::from_sat_per_vb_u32(100);
+
+        // Branch on the feerate.
+        let by_feerate = || {
+            selection_algorithm_by_feerate(
+                FeeRate::from_sat_per_vb_u32(30),
+                selection_algorithm_coin_grinder(100_000),
+                selection_algorithm_oldest_first(),
+            )
+        };
+        assert_eq!(select(&candidates, high, 100_000, by_feerate())?.len(), 1);
+        assert_eq!(
+            select(&candidates, low, 100_000, by_feerate())?,
+            vec![60_000, 60_000]
+        );
+
+        // The fallback starts from the state before the failed attempt.
+        let failing = |selector: &mut Selector| {
+            selector.select_all();
+            Err(())
+        };
+        assert_eq!(
+            select(
+                &candidates,
+                low,
+                50_000,
+                selection_algorithm_fallback(failing, selection_algorithm_oldest_first()),
+            )?,
+            vec![60_000]
+        );
+        // There is no changeless solution.
+        assert_eq!(
+            select(
+                &candidates,
+                low,
+                100_000,
+                selection_algorithm_fallback(
+                    selection_algorithm_changeless_bnb(100_000),
+                    selection_algorithm_largest_first(),
+                ),
+            )?,
+            vec![500_000]
+        );
+
+        // Keep the best result.
+        let best_of = |metric| {
+            selection_algorithm_best_of(
+                metric,
+                selection_algorithm_oldest_first(),
+                selection_algorithm_largest_first(),
+            )
+        };
+        assert_eq!(
+            select(
+                &candidates,
+                low,
+                100_000,
+                best_of(SelectionMetric::InputCount)
+            )?,
+            vec![500_000]
+        );
+        // Consolidating is less wasteful when feerates are expected to rise.
+        let waste = SelectionMetric::Waste {
+            long_term_feerate: high,
+        };
+        assert_eq!(
+            select(&candidates, low, 100_000, best_of(waste))?,
+            vec![60_000, 60_000]
+        );
+        // Selecting nothing does not meet the target, even without an error.
+        let noop = |_: &mut Selector| Ok::<(), NoBnbSolution>(());
+        let params = SelectorParams::new(
+            low,
+            vec![Output::with_script(
+                ScriptBuf::new(),
+                Amount::from_sat(100_000),
+            )],
+            ChangeScript::from_script(ScriptBuf::new(), Weight::ZERO),
+        );
+        let mut selector = Selector::new(&candidates, params)?;
+        assert!(matches!(
+            selection_algorithm_best_of(SelectionMetric::InputCount, noop, noop)(&mut selector),
+            Err(BestOfError::CannotMeetTarget(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_by_script_type() -> anyhow::Result<()> {
+        let (tr_desc, _) = parse_descriptor(TR_XPRV)?;
+        let candidates = |wpkh: &[(u64, Option<u32>)], tr: &[(u64, Option<u32>)]| {
+            let mut coins = inputs(WPKH_XPRV, wpkh)?;
+            coins.extend(inputs(TR_XPRV, tr)?);
+            anyhow::Ok(InputCandidates::new([], coins))
+        };
+        let feerate = FeeRate::from_sat_per_vb_u32(2);
+
+        let select = |candidates: InputCandidates,
+                      value: u64|
+         -> anyhow::Result<(Vec<u64>, InputScriptTypes)> {
+            let recipient = tr_desc.at_derivation_index(101)?.script_pubkey();
+            let params = SelectorParams::new(
+                feerate,
+                vec![Output::with_script(recipient, Amount::from_sat(value))],
+                ChangeScript::from_descriptor(tr_desc.at_derivation_index(100)?),
+            );
+            let (selection, script_types) = candidates
+                .into_selection_by_script_type(selection_algorithm_largest_first(), params)
+                .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+            let mut values = selection
+                .inputs()
+                .iter()
+                .map(|input| input.prev_txout().value.to_sat())
+                .collect::<Vec<_>>();
+            values.sort();
+            Ok((values, script_types))
+        };
+
+        let wpkh = &[(30_000, Some(1)), (40_000, Some(1))];
+        let tr = &[(50_000, Some(1)), (60_000, Some(1))];
+
+        // Taproot inputs match the taproot recipient.
+        assert_eq!(
+            select(candidates(wpkh, tr)?, 80_000)?,
+            (
+                vec![50_000, 60_000],
+                InputScriptTypes::MatchingOutputs(ScriptType::P2tr)
+            )
+        );
+        // Taproot inputs alone fall short, but wpkh inputs alone do not.
+        let wpkh_only = candidates(&[(70_000, Some(1)), (80_000, Some(1))], tr)?;
+        assert_eq!(
+            select(wpkh_only, 140_000)?,
+            (
+                vec![70_000, 80_000],
+                InputScriptTypes::SingleType(ScriptType::P2wpkh)
+            )
+        );
+        // Neither script type alone meets the target.
+        assert_eq!(
+            select(candidates(wpkh, tr)?, 150_000)?,
+            (
+                vec![30_000, 40_000, 50_000, 60_000],
+                InputScriptTypes::Mixed
+            )
+        );
+        Ok(())
+    }
+}
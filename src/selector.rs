@@ -1,14 +1,17 @@
-use bdk_coin_select::{InsufficientFunds, Replace, Target, TargetFee, TargetOutputs};
-use bitcoin::{Amount, FeeRate, ScriptBuf, Transaction, Weight};
+use bdk_coin_select::{DrainWeights, InsufficientFunds, Replace, Target, TargetFee, TargetOutputs};
+use bitcoin::{Amount, FeeRate, ScriptBuf, Transaction, Txid, Weight};
 use miniscript::bitcoin;
 
+use crate::collections::BTreeMap;
 use crate::{
-    DefiniteDescriptor, FeeRateExt, Input, InputCandidates, InputGroup, Output, ScriptSource,
-    Selection,
+    random_range, run_changeless_bnb, DefiniteDescriptor, ExclusionReason, FeeCapError, FeeCaps,
+    FeeRateExt, InputCandidates, InputGroup, Output, ScriptSource, ScriptType, Selection,
+    MAX_REPLACEMENT_CANDIDATES,
 };
 use alloc::boxed::Box;
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::fmt::{self, Debug};
+use rand_core::RngCore;
 
 /// A coin selector
 #[derive(Debug, Clone)]
@@ -17,7 +20,11 @@ pub struct Selector<'c> {
     target_outputs: Vec<Output>,
     target: Target,
     change_policy: bdk_coin_select::ChangePolicy,
-    change_script: ScriptSource,
+    /// Script and minimum value of each change output.
+    change_outputs: Vec<(ScriptSource, Amount)>,
+    change_round_to: Option<Amount>,
+    fee_caps: FeeCaps,
+    prefer_changeless: Option<PreferChangeless>,
     inner: bdk_coin_select::CoinSelector<'c>,
 }
 
@@ -46,6 +53,18 @@ pub struct SelectorParams {
     /// this. For descriptors it is computed automatically; for raw scripts it must be provided.
     pub change_script: ChangeScript,
 
+    /// Alternative change scripts by script type.
+    ///
+    /// If not empty, the change output uses the script whose type matches the most target
+    /// outputs, choosing between [`change_script`](Self::change_script) and these, so the change
+    /// does not stand out from the payments. `change_script` is preferred on a tie. See
+    /// [`chosen_change_script`](Self::chosen_change_script) and
+    /// [`randomize_change_script`](Self::randomize_change_script).
+    ///
+    /// Each script must be of the script type it is keyed by, otherwise [`Selector::new`] fails
+    /// with [`SelectorError::ChangeScriptTypeMismatch`].
+    pub change_script_alternatives: BTreeMap<ScriptType, ChangeScript>,
+
     /// Dust relay feerate used to calculate the dust threshold for change outputs.
     ///
     /// If `None`, defaults to 3 sat/vB (the Bitcoin Core default for `-dustrelayfee`).
@@ -64,6 +83,92 @@ pub struct SelectorParams {
 
     /// Params for replacing tx(s).
     pub replace: Option<RbfParams>,
+
+    /// Split the change into multiple outputs.
+    ///
+    /// `None` means a single change output to [`change_script`](Self::change_script).
+    pub change_split: Option<ChangeSplit>,
+
+    /// Caps on the fee of the selection.
+    ///
+    /// The amount sent is the total value of the target outputs. The caps are checked by
+    /// [`InputCandidates::into_selection`] and [`Selector::check_fee_caps`], but not by
+    /// [`Selector::try_finalize`], which returns any selection that meets the target.
+    ///
+    /// [`InputCandidates::into_selection`]: crate::InputCandidates::into_selection
+    pub fee_caps: FeeCaps,
+
+    /// Prefer a selection without change, giving up a small excess as fee.
+    ///
+    /// If set, [`Selector::select_with_algorithm`] first searches for a changeless selection and
+    /// only runs the algorithm if there is none.
+    pub prefer_changeless: Option<PreferChangeless>,
+}
+
+/// Policy for splitting the change into multiple outputs, see [`SelectorParams::change_split`].
+///
+/// A single change output with an exact value is easy to tell apart from the payment. Splitting
+/// the change, randomizing the split and rounding the amounts makes change outputs look more like
+/// payments. The weights of all change outputs are accounted for by coin selection, and each
+/// change output must be worth at least [`SelectorParams::change_min_value`] and its dust
+/// threshold, otherwise no change is created.
+#[derive(Debug)]
+pub struct ChangeSplit {
+    /// Number of change outputs.
+    pub count: usize,
+    /// Further change scripts.
+    ///
+    /// The change outputs use [`SelectorParams::chosen_change_script`] followed by these scripts
+    /// in turn. If empty, all change outputs use the same script.
+    pub extra_scripts: Vec<ChangeScript>,
+    /// Round the value of all change outputs but the last down to a multiple of this amount.
+    pub round_to: Option<Amount>,
+}
+
+impl ChangeSplit {
+    /// Split the change into `count` outputs.
+    pub fn new(count: usize) -> Self {
+        Self {
+            count,
+            extra_scripts: Vec::new(),
+            round_to: None,
+        }
+    }
+}
+
+/// Parameters for preferring a selection without change, see
+/// [`SelectorParams::prefer_changeless`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreferChangeless {
+    /// Maximum excess over the target that is given up as fee instead of creating change.
+    pub tolerance: Amount,
+    /// Cap on the tolerance as a fraction of the amount sent, e.g. `0.01` for 1%.
+    pub max_fraction_of_send: Option<f64>,
+    /// Maximum branch and bound rounds when searching for a changeless selection.
+    pub max_rounds: usize,
+}
+
+impl PreferChangeless {
+    /// Prefer a changeless selection with an excess of at most `tolerance`.
+    pub fn new(tolerance: Amount) -> Self {
+        Self {
+            tolerance,
+            max_fraction_of_send: None,
+            max_rounds: 100_000,
+        }
+    }
+
+    /// The tolerance when sending `send_amount`, after applying
+    /// [`max_fraction_of_send`](Self::max_fraction_of_send).
+    pub fn tolerance_for(&self, send_amount: Amount) -> Amount {
+        match self.max_fraction_of_send {
+            Some(fraction) => {
+                let cap = Amount::from_sat((send_amount.to_sat() as f64 * fraction) as u64);
+                self.tolerance.min(cap)
+            }
+            None => self.tolerance,
+        }
+    }
 }
 
 /// Source of the change output script and its spending cost.
@@ -144,7 +249,7 @@ impl ChangeScript {
         }
     }
 
-    fn satisfaction_weight(&self) -> Result<Weight, SelectorError> {
+    pub(crate) fn satisfaction_weight(&self) -> Result<Weight, SelectorError> {
         match &self {
             ChangeScript::Script {
                 satisfaction_weight,
@@ -244,8 +349,95 @@ impl RbfParams {
             .max()
             .unwrap_or(FeeRate::ZERO)
     }
+
+    /// Check whether a replacement tx with `fee` and `weight` pays enough to replace the
+    /// original txs.
+    ///
+    /// The replacement must pay for the original txs and their descendants, plus its own size at
+    /// the incremental relay feerate, and must have a higher feerate than every original tx.
+    /// This only checks the fees, see [`Conflicts::check_replacement`] for the other rules.
+    ///
+    /// [`Conflicts::check_replacement`]: crate::Conflicts::check_replacement
+    pub fn check_replacement(&self, fee: Amount, weight: Weight) -> Result<(), ReplacementError> {
+        let replace = self.to_cs_replace();
+        let min_fee = Amount::from_sat(replace.fee)
+            + self
+                .incremental_relay_feerate
+                .fee_vb(weight.to_vbytes_ceil())
+                .unwrap_or(Amount::MAX_MONEY);
+        if fee < min_fee {
+            return Err(ReplacementError::InsufficientFee { fee, min_fee });
+        }
+        let feerate = fee / weight;
+        let original_feerate = self.max_feerate();
+        if feerate <= original_feerate {
+            return Err(ReplacementError::InsufficientFeerate {
+                feerate,
+                original_feerate,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Occurs when a replacement tx does not follow the replacement rules, see
+/// [`RbfParams::check_replacement`] and [`Conflicts::check_replacement`].
+///
+/// [`Conflicts::check_replacement`]: crate::Conflicts::check_replacement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplacementError {
+    /// The replacement does not pay enough absolute fee.
+    InsufficientFee {
+        /// Fee of the replacement.
+        fee: Amount,
+        /// Minimum fee of the replacement.
+        min_fee: Amount,
+    },
+    /// The replacement feerate is not higher than the feerate of an original tx.
+    InsufficientFeerate {
+        /// Feerate of the replacement.
+        feerate: FeeRate,
+        /// Highest feerate of the original txs.
+        original_feerate: FeeRate,
+    },
+    /// The replacement conflicts with the confirmed tx of this txid.
+    ConflictsWithConfirmed(Txid),
+    /// The replacement would evict more than [`MAX_REPLACEMENT_CANDIDATES`] txs.
+    TooManyEvictions {
+        /// Number of txs the replacement would evict.
+        count: usize,
+    },
+}
+
+impl fmt::Display for ReplacementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientFee { fee, min_fee } => {
+                write!(f, "replacement fee {fee} is below the minimum of {min_fee}")
+            }
+            Self::InsufficientFeerate {
+                feerate,
+                original_feerate,
+            } => write!(
+                f,
+                "replacement feerate {feerate:#} is not higher than the original feerate \
+                 {original_feerate:#}"
+            ),
+            Self::ConflictsWithConfirmed(txid) => {
+                write!(f, "replacement conflicts with confirmed transaction {txid}")
+            }
+            Self::TooManyEvictions { count } => write!(
+                f,
+                "replacement would evict {count} transactions, more than the maximum of \
+                 {MAX_REPLACEMENT_CANDIDATES}"
+            ),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ReplacementError {}
+
 impl SelectorParams {
     /// With default params.
     pub fn new(
@@ -257,10 +449,14 @@ impl SelectorParams {
             target_feerate,
             target_outputs,
             change_script,
+            change_script_alternatives: BTreeMap::new(),
             change_min_value: None,
             change_longterm_feerate: None,
             replace: None,
             change_dust_relay_feerate: None,
+            change_split: None,
+            fee_caps: FeeCaps::default(),
+            prefer_changeless: None,
         }
     }
 
@@ -292,29 +488,28 @@ impl SelectorParams {
     ///
     /// Returns [`SelectorError::Miniscript`] if the change descriptor is inherently unsatisfiable.
     pub fn to_cs_change_policy(&self) -> Result<bdk_coin_select::ChangePolicy, SelectorError> {
-        let change_script = self.change_script.source().script();
-        let min_non_dust = self.change_dust_relay_feerate.map_or_else(
-            || change_script.minimal_non_dust(),
-            |r| change_script.minimal_non_dust_custom(r),
-        );
+        let mut change_weights = bdk_coin_select::DrainWeights::NONE;
+        let mut min_value = 0_u64;
+        for (change_script, satisfaction_weight) in self.change_scripts()? {
+            let change_script = change_script.script();
+            let min_non_dust = self.change_dust_relay_feerate.map_or_else(
+                || change_script.minimal_non_dust(),
+                |r| change_script.minimal_non_dust_custom(r),
+            );
+            min_value += min_non_dust
+                .max(self.change_min_value.unwrap_or(Amount::ZERO))
+                .to_sat();
 
-        let change_weights = bdk_coin_select::DrainWeights {
-            output_weight: {
-                let temp_txout = bitcoin::TxOut {
-                    value: Amount::ZERO,
-                    script_pubkey: change_script,
-                };
-                temp_txout.weight().to_wu()
-            },
+            let temp_txout = bitcoin::TxOut {
+                value: Amount::ZERO,
+                script_pubkey: change_script,
+            };
+            change_weights.output_weight += temp_txout.weight().to_wu();
             // This code assumes that the change spend transaction is segwit.
-            spend_weight: bitcoin::TxIn::default().segwit_weight().to_wu()
-                + self.change_script.satisfaction_weight()?.to_wu(),
-            n_outputs: 1,
-        };
-
-        let min_value = min_non_dust
-            .max(self.change_min_value.unwrap_or(Amount::ZERO))
-            .to_sat();
+            change_weights.spend_weight +=
+                bitcoin::TxIn::default().segwit_weight().to_wu() + satisfaction_weight.to_wu();
+            change_weights.n_outputs += 1;
+        }
 
         Ok(
             if let Some(longterm_feerate) = self.change_longterm_feerate {
@@ -329,18 +524,175 @@ impl SelectorParams {
             },
         )
     }
+
+    /// The change script matching the script type of the most target outputs, out of
+    /// [`change_script`](Self::change_script) and
+    /// [`change_script_alternatives`](Self::change_script_alternatives).
+    pub fn chosen_change_script(&self) -> &ChangeScript {
+        let mut counts = BTreeMap::<ScriptType, usize>::new();
+        for output in &self.target_outputs {
+            if let Some(script_type) = output.script_pubkey_source.script_type() {
+                *counts.entry(script_type).or_default() += 1;
+            }
+        }
+        let count = |script_type: Option<ScriptType>| {
+            script_type.map_or(0, |script_type| {
+                counts.get(&script_type).copied().unwrap_or(0)
+            })
+        };
+        let mut chosen = (
+            count(self.change_script.source().script_type()),
+            &self.change_script,
+        );
+        for (&script_type, change_script) in &self.change_script_alternatives {
+            let count = count(Some(script_type));
+            if count > chosen.0 {
+                chosen = (count, change_script);
+            }
+        }
+        chosen.1
+    }
+
+    /// Replace [`change_script`](Self::change_script) with a script chosen at random out of
+    /// `change_script` and [`change_script_alternatives`](Self::change_script_alternatives).
+    ///
+    /// The alternatives are cleared, so the choice is final.
+    pub fn randomize_change_script(&mut self, rng: &mut impl RngCore) {
+        let alternatives = core::mem::take(&mut self.change_script_alternatives);
+        // Index 0 keeps the current change script.
+        let index = random_range(rng, alternatives.len() as u32 + 1) as usize;
+        if index > 0 {
+            if let Some(change_script) = alternatives.into_values().nth(index - 1) {
+                self.change_script = change_script;
+            }
+        }
+    }
+
+    /// The script and satisfaction weight of each change output.
+    fn change_scripts(&self) -> Result<Vec<(ScriptSource, Weight)>, SelectorError> {
+        let change_script = self.chosen_change_script();
+        let mut scripts = vec![(change_script.source(), change_script.satisfaction_weight()?)];
+        let count = match &self.change_split {
+            Some(split) => {
+                for change_script in &split.extra_scripts {
+                    scripts.push((change_script.source(), change_script.satisfaction_weight()?));
+                }
+                split.count.max(1)
+            }
+            None => 1,
+        };
+        Ok((0..count)
+            .map(|i| scripts[i % scripts.len()].clone())
+            .collect())
+    }
+
+    /// The script and minimum value of each change output.
+    fn change_outputs(&self) -> Result<Vec<(ScriptSource, Amount)>, SelectorError> {
+        Ok(self
+            .change_scripts()?
+            .into_iter()
+            .map(|(change_script, _)| {
+                let script = change_script.script();
+                let min_non_dust = self.change_dust_relay_feerate.map_or_else(
+                    || script.minimal_non_dust(),
+                    |r| script.minimal_non_dust_custom(r),
+                );
+                let min_value = min_non_dust.max(self.change_min_value.unwrap_or(Amount::ZERO));
+                (change_script, min_value)
+            })
+            .collect())
+    }
+}
+
+/// Split `total` over change outputs with the `min_values`, evenly or at random if there is an
+/// `rng`, and round all but the last output down to a multiple of `round_to`.
+fn split_change(
+    total: Amount,
+    min_values: &[Amount],
+    round_to: Option<Amount>,
+    rng: Option<&mut dyn RngCore>,
+) -> Vec<Amount> {
+    let spare = total
+        .checked_sub(min_values.iter().copied().sum())
+        .unwrap_or(Amount::ZERO)
+        .to_sat();
+    let shares = match rng {
+        Some(mut rng) => min_values
+            .iter()
+            .map(|_| u64::from(random_range(&mut rng, u16::MAX as u32)) + 1)
+            .collect::<Vec<_>>(),
+        None => vec![1; min_values.len()],
+    };
+    let total_shares = shares.iter().sum::<u64>();
+    let mut values = min_values
+        .iter()
+        .zip(&shares)
+        .map(|(min_value, &share)| {
+            let share = (u128::from(spare) * u128::from(share) / u128::from(total_shares)) as u64;
+            *min_value + Amount::from_sat(share)
+        })
+        .collect::<Vec<_>>();
+
+    let (last, rest) = values.split_last_mut().expect("must have a change output");
+    if let Some(round_to) = round_to.filter(|round_to| *round_to > Amount::ZERO) {
+        for (value, min_value) in rest.iter_mut().zip(min_values) {
+            let rounded = Amount::from_sat(value.to_sat() / round_to.to_sat() * round_to.to_sat());
+            if rounded >= *min_value {
+                *value = rounded;
+            }
+        }
+    }
+    // The last output takes what is left over from rounding.
+    *last = total - rest.iter().copied().sum::<Amount>();
+    values
 }
 
 /// Error when the selection is impossible with the input candidates
-#[derive(Debug)]
-pub struct CannotMeetTarget;
+///
+/// Reports how far the input candidates are from meeting the target, and how much value was
+/// excluded from the candidates, see [`InputCandidates::filter_with_reason`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CannotMeetTarget {
+    /// Value needed to meet the target: the value of the target outputs plus the fee.
+    pub needed: Amount,
+    /// Total value of the input candidates.
+    pub available: Amount,
+    /// The fee the target would cost when spending all input candidates without change.
+    pub fee: Amount,
+    /// Total value of the excluded inputs by [`ExclusionReason`].
+    pub excluded: BTreeMap<ExclusionReason, Amount>,
+}
+
+impl CannotMeetTarget {
+    /// How much value is missing to meet the target.
+    pub fn shortfall(&self) -> Amount {
+        self.needed
+            .checked_sub(self.available)
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Total value of the excluded inputs.
+    pub fn excluded_total(&self) -> Amount {
+        self.excluded.values().copied().sum()
+    }
+}
 
 impl fmt::Display for CannotMeetTarget {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "meeting the target is not possible with the input candidates"
-        )
+            "meeting the target is not possible with the input candidates: {} short (needed {} \
+             including {} fee, available {})",
+            self.shortfall(),
+            self.needed,
+            self.fee,
+            self.available,
+        )?;
+        for (i, (reason, value)) in self.excluded.iter().enumerate() {
+            let sep = if i == 0 { "; excluded" } else { "," };
+            write!(f, "{sep} {value} {reason}")?;
+        }
+        Ok(())
     }
 }
 
@@ -356,6 +708,8 @@ pub enum SelectorError {
     CannotMeetTarget(CannotMeetTarget),
     /// The provided assets cannot satisfy the change descriptor.
     InsufficientAssets,
+    /// The selection exceeds [`SelectorParams::fee_caps`].
+    FeeCap(FeeCapError),
     /// Input candidates have absolute timelocks of mixed units (some height-based, others
     /// time-based).
     ///
@@ -363,6 +717,9 @@ pub enum SelectorError {
     /// Filter the [`InputCandidates`] down to a single-unit subset before constructing the
     /// [`Selector`].
     LockTypeMismatch,
+    /// A script in [`SelectorParams::change_script_alternatives`] is not of the script type it
+    /// is keyed by.
+    ChangeScriptTypeMismatch(ScriptType),
 }
 
 impl fmt::Display for SelectorError {
@@ -373,9 +730,16 @@ impl fmt::Display for SelectorError {
             Self::InsufficientAssets => {
                 write!(f, "provided assets cannot satisfy the change descriptor")
             }
+            Self::FeeCap(err) => write!(f, "{err}"),
             Self::LockTypeMismatch => {
                 write!(f, "input candidates have absolute timelocks of mixed units")
             }
+            Self::ChangeScriptTypeMismatch(script_type) => {
+                write!(
+                    f,
+                    "change script alternative keyed by {script_type} is not {script_type}"
+                )
+            }
         }
     }
 }
@@ -383,6 +747,30 @@ impl fmt::Display for SelectorError {
 #[cfg(feature = "std")]
 impl std::error::Error for SelectorError {}
 
+/// Script types of the inputs picked by
+/// [`select_by_script_type`](Selector::select_by_script_type).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputScriptTypes {
+    /// All inputs are of the script type shared with the target outputs.
+    MatchingOutputs(ScriptType),
+    /// All inputs are of a single script type not used by the target outputs.
+    SingleType(ScriptType),
+    /// No single script type could meet the target, so inputs of any script type were used.
+    Mixed,
+}
+
+impl fmt::Display for InputScriptTypes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MatchingOutputs(script_type) => {
+                write!(f, "{script_type} inputs matching the outputs")
+            }
+            Self::SingleType(script_type) => write!(f, "{script_type} inputs only"),
+            Self::Mixed => write!(f, "mixed input script types"),
+        }
+    }
+}
+
 impl<'c> Selector<'c> {
     /// Create new input selector.
     ///
@@ -390,45 +778,63 @@ impl<'c> Selector<'c> {
     ///
     /// - If we are unable to create a change policy from the `params`.
     /// - If the target is unreachable given the total input value.
+    /// - If a change script alternative is not of the script type it is keyed by.
     pub fn new(
         candidates: &'c InputCandidates,
         params: SelectorParams,
     ) -> Result<Self, SelectorError> {
+        for (&script_type, change_script) in &params.change_script_alternatives {
+            if change_script.source().script_type() != Some(script_type) {
+                return Err(SelectorError::ChangeScriptTypeMismatch(script_type));
+            }
+        }
         let target = params.to_cs_target();
         let change_policy = params.to_cs_change_policy()?;
+        let change_outputs = params.change_outputs()?;
+        let change_round_to = params
+            .change_split
+            .as_ref()
+            .and_then(|split| split.round_to);
         let target_outputs = params.target_outputs;
-        let change_script = params.change_script.source();
-
-        if target.value() > candidates.groups().map(|grp| grp.value().to_sat()).sum() {
-            return Err(SelectorError::CannotMeetTarget(CannotMeetTarget));
-        }
+        let fee_caps = params.fee_caps;
+        let prefer_changeless = params.prefer_changeless;
 
         // Verify that all inputs agree on absolute timelock unit (height vs time).
         // Downstream stages (create_psbt, apply_anti_fee_sniping) rely on this invariant.
-        let mut unit: Option<bitcoin::absolute::LockTime> = None;
-        for lt in candidates.inputs().filter_map(Input::absolute_timelock) {
-            match unit {
-                Some(existing_unit) => {
-                    if !existing_unit.is_same_unit(lt) {
-                        return Err(SelectorError::LockTypeMismatch);
-                    }
-                }
-                None => unit = Some(lt),
-            }
+        if candidates.has_mixed_timelock_units() {
+            return Err(SelectorError::LockTypeMismatch);
         }
 
         let mut inner = bdk_coin_select::CoinSelector::new(candidates.coin_select_candidates());
         if candidates.must_select().is_some() {
             inner.select_next();
         }
-        Ok(Self {
+        let selector = Self {
             candidates,
             target,
             target_outputs,
             change_policy,
-            change_script,
+            change_outputs,
+            change_round_to,
+            fee_caps,
+            prefer_changeless,
             inner,
-        })
+        };
+
+        if target.value() > candidates.groups().map(|grp| grp.value().to_sat()).sum() {
+            return Err(SelectorError::CannotMeetTarget(
+                selector.cannot_meet_target(),
+            ));
+        }
+        Ok(selector)
+    }
+
+    /// Get the input candidates.
+    ///
+    /// The groups are in the same order as the candidates of the [`inner`](Self::inner) coin
+    /// selector.
+    pub fn candidates(&self) -> &'c InputCandidates {
+        self.candidates
     }
 
     /// Get the inner coin selector.
@@ -451,14 +857,140 @@ impl<'c> Selector<'c> {
         self.change_policy
     }
 
+    /// Diagnose why the target cannot be met with the input candidates.
+    ///
+    /// The fee is computed as if all input candidates were selected without change.
+    pub fn cannot_meet_target(&self) -> CannotMeetTarget {
+        let mut all = self.inner.clone();
+        all.select_all();
+        let fee = all.implied_fee(self.target, DrainWeights::NONE);
+        CannotMeetTarget {
+            needed: Amount::from_sat(self.target.value() + fee),
+            available: Amount::from_sat(all.selected_value()),
+            fee: Amount::from_sat(fee),
+            excluded: self.candidates.excluded_value(),
+        }
+    }
+
     /// Select with the provided `algorithm`.
+    ///
+    /// If [`SelectorParams::prefer_changeless`] is set, the `algorithm` only runs if
+    /// [`select_changeless`](Self::select_changeless) finds no selection.
     pub fn select_with_algorithm<F, E>(&mut self, mut algorithm: F) -> Result<(), E>
     where
         F: FnMut(&mut Selector) -> Result<(), E>,
     {
+        if self.select_changeless() {
+            return Ok(());
+        }
         algorithm(self)
     }
 
+    /// Select with the provided `algorithm`, using inputs of a single script type if possible.
+    ///
+    /// Script types are attempted in order of how many target outputs share them, then by the
+    /// total value of the input candidates of that type. For each attempt, groups of other
+    /// script types are banned in the [`inner`](Self::inner) coin selector. If no single script
+    /// type meets the target, the `algorithm` runs on all candidates.
+    ///
+    /// Only the script type of the must-select inputs is attempted, if there are any.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the `algorithm` when run on all candidates.
+    pub fn select_by_script_type<F, E>(&mut self, mut algorithm: F) -> Result<InputScriptTypes, E>
+    where
+        F: FnMut(&mut Selector) -> Result<(), E>,
+    {
+        let mut output_counts = BTreeMap::<ScriptType, usize>::new();
+        for output in &self.target_outputs {
+            if let Some(script_type) = output.script_pubkey_source.script_type() {
+                *output_counts.entry(script_type).or_default() += 1;
+            }
+        }
+        let group_types = self
+            .candidates
+            .groups()
+            .map(InputGroup::script_type)
+            .collect::<Vec<_>>();
+        let mut values = BTreeMap::<ScriptType, Amount>::new();
+        for group in self.candidates.groups() {
+            if let Some(script_type) = group.script_type() {
+                *values.entry(script_type).or_default() += group.value();
+            }
+        }
+        if let Some(must_select) = self.candidates.must_select() {
+            let must_select_type = must_select.script_type();
+            values.retain(|&script_type, _| Some(script_type) == must_select_type);
+        }
+        let mut script_types = values.into_iter().collect::<Vec<_>>();
+        script_types.sort_by_key(|&(script_type, value)| {
+            let count = output_counts.get(&script_type).copied().unwrap_or(0);
+            core::cmp::Reverse((count, value))
+        });
+
+        for (script_type, _) in script_types {
+            let mut attempt = self.clone();
+            for (index, group_type) in group_types.iter().enumerate() {
+                if *group_type != Some(script_type) && !attempt.inner.is_selected(index) {
+                    attempt.inner.ban(index);
+                }
+            }
+            if attempt.select_with_algorithm(&mut algorithm).is_ok()
+                && attempt.inner.is_target_met(attempt.target)
+            {
+                *self = attempt;
+                return Ok(if output_counts.contains_key(&script_type) {
+                    InputScriptTypes::MatchingOutputs(script_type)
+                } else {
+                    InputScriptTypes::SingleType(script_type)
+                });
+            }
+        }
+
+        self.select_with_algorithm(algorithm)?;
+        Ok(InputScriptTypes::Mixed)
+    }
+
+    /// Search for a selection whose excess over the target is within the tolerance of
+    /// [`SelectorParams::prefer_changeless`], and give up the excess as fee.
+    ///
+    /// Returns whether such a selection was found. If not, or if `prefer_changeless` is unset,
+    /// the selector is left unchanged.
+    pub fn select_changeless(&mut self) -> bool {
+        let Some(prefer_changeless) = self.prefer_changeless else {
+            return false;
+        };
+        let tolerance = prefer_changeless
+            .tolerance_for(Amount::from_sat(self.target.value()))
+            .to_sat();
+        // Change is created for an excess over `min_value` after paying for the change output,
+        // so the excess without change is at most the tolerance.
+        let drain_fee = self
+            .target
+            .fee
+            .rate
+            .implied_fee_wu(self.change_policy.drain_weights.output_weight);
+        let change_policy = bdk_coin_select::ChangePolicy {
+            min_value: tolerance.saturating_sub(drain_fee),
+            drain_weights: self.change_policy.drain_weights,
+        };
+        let mut attempt = self.clone();
+        if run_changeless_bnb(
+            &mut attempt,
+            change_policy,
+            tolerance,
+            prefer_changeless.max_rounds,
+        )
+        .is_err()
+        {
+            return false;
+        }
+        attempt.change_policy.min_value = self.change_policy.min_value.max(change_policy.min_value);
+        *self = attempt;
+        true
+    }
+
     /// Select all.
     pub fn select_all(&mut self) {
         self.inner.select_all();
@@ -483,10 +1015,46 @@ impl<'c> Selector<'c> {
         Some(has_drain)
     }
 
+    /// Check the current selection against [`SelectorParams::fee_caps`].
+    ///
+    /// # Errors
+    ///
+    /// - [`SelectorError::CannotMeetTarget`] if the target is not met yet.
+    /// - [`SelectorError::FeeCap`] if a fee cap is exceeded.
+    pub fn check_fee_caps(&self) -> Result<(), SelectorError> {
+        if !self.inner.is_target_met(self.target) {
+            return Err(SelectorError::CannotMeetTarget(self.cannot_meet_target()));
+        }
+        let drain = self.inner.drain(self.target, self.change_policy);
+        let fee = self.inner.fee(self.target.value(), drain.value);
+        let weight = self.inner.weight(self.target.outputs, drain.weights);
+        self.fee_caps
+            .check(
+                Amount::from_sat(fee.max(0) as u64),
+                Weight::from_wu(weight),
+                Amount::from_sat(self.target.value()),
+            )
+            .map_err(SelectorError::FeeCap)
+    }
+
     /// Try get final selection.
     ///
-    /// Return `None` if target is not met yet.
+    /// Return `None` if target is not met yet. The returned selection may exceed
+    /// [`SelectorParams::fee_caps`], which are only checked by
+    /// [`check_fee_caps`](Self::check_fee_caps).
     pub fn try_finalize(&self) -> Option<Selection> {
+        self.finalize(None)
+    }
+
+    /// Try get final selection, splitting the change at random with `rng`.
+    ///
+    /// Same as [`try_finalize`](Self::try_finalize), but if [`SelectorParams::change_split`] is
+    /// set, the change is split into outputs of random values instead of equal values.
+    pub fn try_finalize_with_rng(&self, rng: &mut impl RngCore) -> Option<Selection> {
+        self.finalize(Some(rng))
+    }
+
+    fn finalize(&self, rng: Option<&mut dyn RngCore>) -> Option<Selection> {
         if !self.inner.is_target_met(self.target) {
             return None;
         }
@@ -500,13 +1068,26 @@ impl<'c> Selector<'c> {
             .cloned()
             .collect();
         let mut outputs = self.target_outputs.clone();
+        let mut change = Vec::new();
         if maybe_change.is_some() {
-            outputs.push(Output::from((
-                self.change_script.clone(),
+            let min_values = self
+                .change_outputs
+                .iter()
+                .map(|(_, min_value)| *min_value)
+                .collect::<Vec<_>>();
+            let values = split_change(
                 Amount::from_sat(maybe_change.value),
-            )));
+                &min_values,
+                self.change_round_to,
+                rng,
+            );
+            for ((change_script, _), value) in self.change_outputs.iter().zip(values) {
+                let output = Output::from((change_script.clone(), value));
+                change.push(output.txout());
+                outputs.push(output);
+            }
         }
-        Some(Selection::new(inputs, outputs))
+        Some(Selection::with_change(inputs, outputs, change))
     }
 }
 
@@ -520,8 +1101,16 @@ mod tests {
     };
     use miniscript::{plan::Assets, DescriptorPublicKey};
     use std::string::ToString;
+    use std::vec::Vec;
 
     fn setup_cltv_input(cltv: absolute::LockTime) -> anyhow::Result<Input> {
+        setup_cltv_input_with_status(cltv, None)
+    }
+
+    fn setup_cltv_input_with_status(
+        cltv: absolute::LockTime,
+        status: Option<ConfirmationStatus>,
+    ) -> anyhow::Result<Input> {
         let secp = Secp256k1::new();
         let secret_key = SecretKey::from_slice(&[1_u8; 32])?;
         let public_key = PrivateKey::new(secret_key, bitcoin::Network::Regtest).public_key(&secp);
@@ -541,7 +1130,259 @@ mod tests {
                 value: Amount::ONE_BTC,
             }],
         };
-        Ok(Input::from_prev_tx(plan, prev_tx, 0, None)?)
+        Ok(Input::from_prev_tx(plan, prev_tx, 0, status)?)
+    }
+
+    #[test]
+    fn test_cannot_meet_target_diagnostics() -> anyhow::Result<()> {
+        let status = Some(ConfirmationStatus::new(1, None)?);
+        let unlocked =
+            setup_cltv_input_with_status(absolute::LockTime::from_consensus(100), status)?;
+        let locked =
+            setup_cltv_input_with_status(absolute::LockTime::from_consensus(10_000), status)?;
+        let tip_height = absolute::Height::from_consensus(5_000)?;
+        let candidates = InputCandidates::new([], [unlocked, locked])
+            .filter_with_reason(filter_unspendable_with_reason(tip_height, None));
+        assert_eq!(candidates.inputs().count(), 1);
+
+        let params = SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(10),
+            vec![Output::with_script(
+                ScriptBuf::new(),
+                Amount::from_btc(1.5)?,
+            )],
+            ChangeScript::from_script(ScriptBuf::new(), Weight::ZERO),
+        );
+        let err = match Selector::new(&candidates, params) {
+            Err(SelectorError::CannotMeetTarget(err)) => err,
+            res => panic!("unexpected result: {res:?}"),
+        };
+        assert_eq!(err.available, Amount::ONE_BTC);
+        assert!(err.fee > Amount::ZERO);
+        assert_eq!(err.needed, Amount::from_btc(1.5)? + err.fee);
+        assert_eq!(err.shortfall(), Amount::from_btc(0.5)? + err.fee);
+        assert_eq!(
+            err.excluded.into_iter().collect::<Vec<_>>(),
+            vec![(ExclusionReason::BlockTimelocked, Amount::ONE_BTC)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefer_changeless() -> anyhow::Result<()> {
+        let input = setup_cltv_input(absolute::LockTime::from_consensus(10_000))?;
+        let candidates = InputCandidates::new([], [input]);
+        // Leaves an excess of a bit less than 10_000 sats.
+        let params = |prefer_changeless| SelectorParams {
+            prefer_changeless,
+            ..SelectorParams::new(
+                FeeRate::from_sat_per_vb_u32(1),
+                vec![Output::with_script(
+                    ScriptBuf::new(),
+                    Amount::from_sat(99_990_000),
+                )],
+                ChangeScript::from_script(ScriptBuf::new(), Weight::ZERO),
+            )
+        };
+        let has_change = |prefer_changeless| -> anyhow::Result<bool> {
+            let mut selector = Selector::new(&candidates, params(prefer_changeless))?;
+            selector.select_with_algorithm(|s: &mut Selector| s.select_until_target_met())?;
+            Ok(selector.has_change().expect("target must be met"))
+        };
+
+        assert!(has_change(None)?);
+        let tolerance = PreferChangeless::new(Amount::from_sat(20_000));
+        assert!(!has_change(Some(tolerance))?);
+        // The tolerance is capped to 0.005% of the amount sent, about 5_000 sats.
+        let capped = PreferChangeless {
+            max_fraction_of_send: Some(0.00005),
+            ..tolerance
+        };
+        assert!(has_change(Some(capped))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_changeless_overpays_at_most_tolerance() -> anyhow::Result<()> {
+        let input = setup_cltv_input(absolute::LockTime::from_consensus(10_000))?;
+        let candidates = InputCandidates::new([], [input]);
+        let tolerance = Amount::from_sat(5_000);
+        let params = |value| SelectorParams {
+            prefer_changeless: Some(PreferChangeless::new(tolerance)),
+            ..SelectorParams::new(
+                FeeRate::from_sat_per_vb_u32(2),
+                vec![Output::with_script(
+                    ScriptBuf::new(),
+                    Amount::from_sat(value),
+                )],
+                ChangeScript::from_script(ScriptBuf::from_bytes(vec![0x51; 34]), Weight::ZERO),
+            )
+        };
+
+        let mut changeless_count = 0;
+        for value in (99_985_000..99_999_000).step_by(20) {
+            let mut selector = Selector::new(&candidates, params(value))?;
+            let mut all = selector.clone();
+            all.select_all();
+            let excess_without_change = all
+                .inner()
+                .excess(all.target(), bdk_coin_select::Drain::NONE);
+            let expected = (0..=tolerance.to_sat() as i64).contains(&excess_without_change);
+
+            assert_eq!(selector.select_changeless(), expected, "value {value}");
+            if expected {
+                changeless_count += 1;
+                assert_eq!(selector.has_change(), Some(false));
+                let overpayment = selector
+                    .inner()
+                    .excess(selector.target(), bdk_coin_select::Drain::NONE);
+                assert!(overpayment <= tolerance.to_sat() as i64);
+            }
+        }
+        assert!(changeless_count > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_split() -> anyhow::Result<()> {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let input = setup_cltv_input(absolute::LockTime::from_consensus(10_000))?;
+        let candidates = InputCandidates::new([], [input]);
+        let scripts = (0..3_u8)
+            .map(|i| ScriptBuf::from_bytes(vec![0x51 + i]))
+            .collect::<Vec<_>>();
+        let feerate = FeeRate::from_sat_per_vb_u32(2);
+        let params = |change_min_value| SelectorParams {
+            change_min_value,
+            change_split: Some(ChangeSplit {
+                extra_scripts: scripts[1..]
+                    .iter()
+                    .map(|script| ChangeScript::from_script(script.clone(), Weight::ZERO))
+                    .collect(),
+                round_to: Some(Amount::from_sat(100_000)),
+                ..ChangeSplit::new(3)
+            }),
+            ..SelectorParams::new(
+                feerate,
+                vec![Output::with_script(
+                    ScriptBuf::new(),
+                    Amount::from_sat(10_000),
+                )],
+                ChangeScript::from_script(scripts[0].clone(), Weight::ZERO),
+            )
+        };
+
+        let mut selector = Selector::new(&candidates, params(None))?;
+        selector.select_all();
+        let selection = selector
+            .try_finalize_with_rng(&mut StdRng::seed_from_u64(0))
+            .expect("target must be met");
+        let change = &selection.outputs()[1..];
+        assert_eq!(
+            change
+                .iter()
+                .map(|output| output.script_pubkey())
+                .collect::<Vec<_>>(),
+            scripts
+        );
+        assert!(change[..2]
+            .iter()
+            .all(|output| output.value.to_sat() % 100_000 == 0));
+        // The split is random, but deterministic for the same rng.
+        let again = selector
+            .try_finalize_with_rng(&mut StdRng::seed_from_u64(0))
+            .expect("target must be met");
+        let values = |selection: &Selection| {
+            selection
+                .outputs()
+                .iter()
+                .map(|output| output.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(&again), values(&selection));
+        assert_ne!(change[0].value, change[1].value);
+
+        // The fee pays for all change outputs.
+        let psbt = selection.create_psbt(PsbtParams {
+            mandate_full_tx_for_segwit_v0: false,
+            ..Default::default()
+        })?;
+        let input = &selection.inputs()[0];
+        let weight = crate::estimate_tx_weight(
+            &psbt.unsigned_tx,
+            [(input.satisfaction_weight(), input.is_segwit())],
+        );
+        assert!(psbt.fee()? >= feerate * weight);
+
+        // Every change output must be worth at least `change_min_value`.
+        let mut selector = Selector::new(&candidates, params(Some(Amount::from_btc(0.4)?)))?;
+        selector.select_all();
+        assert_eq!(selector.has_change(), Some(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_script_matches_recipients() -> anyhow::Result<()> {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let input = setup_cltv_input(absolute::LockTime::from_consensus(10_000))?;
+        let candidates = InputCandidates::new([], [input]);
+        let p2wpkh = ScriptBuf::from_bytes([&[0x00, 0x14][..], &[1; 20]].concat());
+        let p2tr = |i| ScriptBuf::from_bytes([&[0x51, 0x20][..], &[i; 32]].concat());
+        let params = || SelectorParams {
+            change_script_alternatives: [(
+                ScriptType::P2tr,
+                ChangeScript::from_script(p2tr(0), Weight::from_wu(66)),
+            )]
+            .into(),
+            ..SelectorParams::new(
+                FeeRate::from_sat_per_vb_u32(2),
+                vec![
+                    Output::with_script(p2tr(1), Amount::from_sat(10_000)),
+                    Output::with_script(p2tr(2), Amount::from_sat(10_000)),
+                    Output::with_script(p2wpkh.clone(), Amount::from_sat(10_000)),
+                ],
+                ChangeScript::from_script(p2wpkh.clone(), Weight::from_wu(108)),
+            )
+        };
+
+        let matched = params();
+        assert_eq!(
+            matched.chosen_change_script().source().script_type(),
+            Some(ScriptType::P2tr)
+        );
+        // Coin selection accounts for the weight of the chosen change output.
+        let change_policy = matched.to_cs_change_policy()?;
+        assert_eq!(change_policy.drain_weights.output_weight, 43 * 4);
+        let mut selector = Selector::new(&candidates, matched)?;
+        selector.select_all();
+        let selection = selector.try_finalize().expect("target must be met");
+        assert_eq!(selection.outputs()[3].script_pubkey(), p2tr(0));
+
+        // A random choice is final.
+        let choices = (0..8)
+            .map(|seed| {
+                let mut params = params();
+                params.randomize_change_script(&mut StdRng::seed_from_u64(seed));
+                assert!(params.change_script_alternatives.is_empty());
+                params.chosen_change_script().source().script()
+            })
+            .collect::<Vec<_>>();
+        assert!(choices.contains(&p2wpkh));
+        assert!(choices.contains(&p2tr(0)));
+
+        // An alternative must be of the script type it is keyed by.
+        let mut mismatched = params();
+        mismatched.change_script_alternatives.insert(
+            ScriptType::P2wsh,
+            ChangeScript::from_script(p2tr(3), Weight::from_wu(66)),
+        );
+        assert!(matches!(
+            Selector::new(&candidates, mismatched),
+            Err(SelectorError::ChangeScriptTypeMismatch(ScriptType::P2wsh))
+        ));
+        Ok(())
     }
 
     #[test]
@@ -560,4 +1401,77 @@ mod tests {
         ));
         Ok(())
     }
+
+    #[test]
+    fn test_fee_caps() -> anyhow::Result<()> {
+        let input = setup_cltv_input(absolute::LockTime::from_consensus(10_000))?;
+        let candidates = InputCandidates::new([], [input]);
+        let params = |fee_caps| SelectorParams {
+            fee_caps,
+            ..SelectorParams::new(
+                FeeRate::from_sat_per_vb_u32(10),
+                vec![Output::with_script(
+                    ScriptBuf::new(),
+                    Amount::from_sat(10_000),
+                )],
+                ChangeScript::from_script(ScriptBuf::new(), Weight::ZERO),
+            )
+        };
+        let select = |fee_caps| {
+            candidates.clone().into_selection(
+                |s: &mut Selector| s.select_until_target_met(),
+                params(fee_caps),
+            )
+        };
+
+        let selection = select(FeeCaps::default())?;
+        let psbt = selection.create_psbt(PsbtParams {
+            mandate_full_tx_for_segwit_v0: false,
+            ..Default::default()
+        })?;
+        let fee = psbt.fee()?;
+
+        let max_fee = FeeCaps {
+            max_fee: Some(fee - Amount::from_sat(100)),
+            ..Default::default()
+        };
+        let max_feerate = FeeCaps {
+            max_feerate: Some(FeeRate::from_sat_per_vb_u32(5)),
+            ..Default::default()
+        };
+        let max_fraction = FeeCaps {
+            max_fee_fraction_of_send: Some(0.05),
+            ..Default::default()
+        };
+        for fee_caps in [max_fee, max_feerate, max_fraction] {
+            let result = select(fee_caps);
+            assert!(
+                matches!(
+                    result,
+                    Err(IntoSelectionError::Selector(SelectorError::FeeCap(_)))
+                ),
+                "{fee_caps:?} must be exceeded"
+            );
+        }
+
+        // The final PSBT is re-checked. Its amount sent excludes the change output.
+        assert_eq!(selection.outputs().len(), 2);
+        for fee_caps in [max_fee, max_feerate, max_fraction] {
+            let result = selection.create_psbt(PsbtParams {
+                mandate_full_tx_for_segwit_v0: false,
+                fee_caps,
+                ..Default::default()
+            });
+            assert!(matches!(result, Err(CreatePsbtError::FeeCap(_))));
+        }
+
+        let generous = FeeCaps {
+            max_fee: Some(fee),
+            max_feerate: Some(FeeRate::from_sat_per_vb_u32(20)),
+            max_fee_fraction_of_send: Some(0.5),
+        };
+        assert!(select(generous).is_ok());
+
+        Ok(())
+    }
 }
@@ -3,8 +3,9 @@ use alloc::string::ToString;
 use alloc::vec::Vec;
 
 use bitcoin::{
-    psbt::{GetKey, GetKeyError, KeyRequest},
+    psbt::{GetKey, GetKeyError, KeyRequest, PsbtSighashType, SigningErrors, SigningKeysMap},
     secp256k1::{self, Secp256k1},
+    EcdsaSighashType, OutPoint, Psbt, TapSighashType,
 };
 use miniscript::bitcoin;
 use miniscript::descriptor::{DescriptorSecretKey, KeyMap};
@@ -66,6 +67,102 @@ impl GetKey for Signer {
     }
 }
 
+impl Signer {
+    /// Sign the `psbt`, refusing to sign if any input requests a sighash type other than the one
+    /// expected for it.
+    ///
+    /// `sighash_types` maps the outpoint of each input to its expected sighash type, typically
+    /// the same map that was passed as [`PsbtParams::sighash_types`]. Inputs without an entry are
+    /// expected to use the default sighash type. No input is signed if any of them mismatch.
+    ///
+    /// [`PsbtParams::sighash_types`]: crate::PsbtParams::sighash_types
+    pub fn sign_psbt<C: secp256k1::Signing + secp256k1::Verification>(
+        &self,
+        psbt: &mut Psbt,
+        sighash_types: &BTreeMap<OutPoint, PsbtSighashType>,
+        secp: &Secp256k1<C>,
+    ) -> Result<SigningKeysMap, SignPsbtError> {
+        for (input_index, (txin, psbt_input)) in
+            psbt.unsigned_tx.input.iter().zip(&psbt.inputs).enumerate()
+        {
+            let expected = sighash_types.get(&txin.previous_output).copied();
+            let requested = psbt_input.sighash_type;
+            let default = if is_taproot_input(psbt_input, txin.previous_output.vout) {
+                PsbtSighashType::from(TapSighashType::Default)
+            } else {
+                PsbtSighashType::from(EcdsaSighashType::All)
+            };
+            if expected.unwrap_or(default) != requested.unwrap_or(default) {
+                return Err(SignPsbtError::SighashMismatch {
+                    input_index,
+                    expected,
+                    requested,
+                });
+            }
+        }
+        psbt.sign(self, secp)
+            .map_err(|(_, errors)| SignPsbtError::Signing(errors))
+    }
+}
+
+/// Whether the PSBT input spends a taproot output.
+fn is_taproot_input(psbt_input: &bitcoin::psbt::Input, vout: u32) -> bool {
+    if psbt_input.tap_internal_key.is_some() {
+        return true;
+    }
+    if let Some(txout) = &psbt_input.witness_utxo {
+        return txout.script_pubkey.is_p2tr();
+    }
+    psbt_input
+        .non_witness_utxo
+        .as_ref()
+        .and_then(|tx| tx.output.get(vout as usize))
+        .is_some_and(|txout| txout.script_pubkey.is_p2tr())
+}
+
+/// Error when signing a PSBT with [`Signer::sign_psbt`].
+#[derive(Debug)]
+pub enum SignPsbtError {
+    /// The input requests a sighash type other than the expected one.
+    SighashMismatch {
+        /// Index of the input.
+        input_index: usize,
+        /// The expected sighash type, `None` meaning the default.
+        expected: Option<PsbtSighashType>,
+        /// The sighash type requested by the PSBT input.
+        requested: Option<PsbtSighashType>,
+    },
+    /// Signing failed.
+    Signing(SigningErrors),
+}
+
+impl core::fmt::Display for SignPsbtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SighashMismatch {
+                input_index,
+                expected,
+                requested,
+            } => {
+                write!(f, "input {input_index} requests sighash type ")?;
+                match requested {
+                    Some(ty) => write!(f, "{ty}")?,
+                    None => write!(f, "default")?,
+                }
+                write!(f, " but expected ")?;
+                match expected {
+                    Some(ty) => write!(f, "{ty}"),
+                    None => write!(f, "default"),
+                }
+            }
+            Self::Signing(errors) => write!(f, "failed to sign inputs: {errors:?}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SignPsbtError {}
+
 #[cfg_attr(coverage_nightly, coverage(off))]
 #[cfg(test)]
 mod test {
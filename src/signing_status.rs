@@ -0,0 +1,348 @@
+use crate::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use bitcoin::bip32::{Fingerprint, KeySource};
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::{psbt, OutPoint, Psbt};
+use miniscript::{bitcoin, psbt::PsbtInputSatisfier};
+
+use crate::finalizer::retain_requested_sighash_sigs;
+use crate::{Finalizer, MissingRequirements, MissingSignature};
+
+/// Progress of signing a PSBT against the spending plans of a [`Finalizer`].
+///
+/// Useful for coordinating multisig spends: for each input it reports which keys (by master
+/// fingerprint and derivation path) have already provided a signature required by the input's
+/// [`Plan`], and which keys still have to sign. Required signatures whose key origin cannot be
+/// determined are reported separately as unknown. Only signatures of the sighash type requested
+/// for the input are counted, see [`Finalizer::with_sighash_types`].
+///
+/// [`Plan`]: miniscript::plan::Plan
+#[derive(Debug, Clone)]
+pub struct SigningStatus {
+    inputs: BTreeMap<usize, InputSigningStatus>,
+}
+
+impl SigningStatus {
+    /// Get the signing status of every input of the `psbt` under the plans of the `finalizer`.
+    pub fn new(psbt: &Psbt, finalizer: &Finalizer) -> Self {
+        let mut psbt = psbt.clone();
+        let mut inputs = BTreeMap::new();
+
+        for input_index in 0..psbt.inputs.len() {
+            let psbt_input = &psbt.inputs[input_index];
+            if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
+                inputs.insert(input_index, InputSigningStatus::Finalized);
+                continue;
+            }
+            let outpoint = psbt.unsigned_tx.input[input_index].previous_output;
+            let plan = match finalizer.plans.get(&outpoint) {
+                Some(plan) => plan,
+                None => {
+                    inputs.insert(input_index, InputSigningStatus::NoPlan(outpoint));
+                    continue;
+                }
+            };
+            let requested = finalizer.sighash_types.get(&outpoint).copied();
+            psbt.inputs[input_index] = retain_requested_sighash_sigs(psbt_input, requested);
+
+            let psbt_input = &psbt.inputs[input_index];
+            let stfr = PsbtInputSatisfier::new(&psbt, input_index);
+            let mut progress = SigningProgress {
+                signed: Vec::new(),
+                needed: Vec::new(),
+                unknown: Vec::new(),
+                missing: MissingRequirements::of(plan, &psbt, input_index),
+            };
+            for placeholder in plan.witness_template() {
+                let sig = match MissingSignature::from_placeholder(placeholder) {
+                    Some(sig) => sig,
+                    None => continue,
+                };
+                let is_signed = placeholder.satisfy_self(&stfr).is_some();
+                match (signature_key_source(&sig, psbt_input), is_signed) {
+                    (Some(key_source), true) => progress.signed.push(key_source),
+                    (Some(key_source), false) => progress.needed.push(key_source),
+                    (None, true) => {}
+                    (None, false) => progress.unknown.push(sig),
+                }
+            }
+            inputs.insert(input_index, InputSigningStatus::Pending(progress));
+        }
+
+        Self { inputs }
+    }
+
+    /// Get the signing status of the input at `input_index`.
+    pub fn input(&self, input_index: usize) -> Option<&InputSigningStatus> {
+        self.inputs.get(&input_index)
+    }
+
+    /// Get the signing status of each input as a map of `input_index` to [`InputSigningStatus`].
+    pub fn inputs(&self) -> &BTreeMap<usize, InputSigningStatus> {
+        &self.inputs
+    }
+
+    /// Whether every input is either finalized or has everything its plan requires, meaning that
+    /// [`Finalizer::finalize`] is expected to succeed.
+    pub fn is_ready_to_finalize(&self) -> bool {
+        self.inputs.values().all(InputSigningStatus::is_complete)
+    }
+
+    /// Master fingerprints of the keys that still have to sign at least one input.
+    ///
+    /// Keys of unknown origin are not included, see [`has_unknown_signers`].
+    ///
+    /// [`has_unknown_signers`]: Self::has_unknown_signers
+    pub fn needed_signers(&self) -> BTreeSet<Fingerprint> {
+        self.inputs
+            .values()
+            .filter_map(InputSigningStatus::progress)
+            .flat_map(|progress| progress.needed.iter().map(|(fingerprint, _)| *fingerprint))
+            .collect()
+    }
+
+    /// Whether some input still needs a signature from a key whose origin is unknown.
+    pub fn has_unknown_signers(&self) -> bool {
+        self.inputs
+            .values()
+            .filter_map(InputSigningStatus::progress)
+            .any(|progress| !progress.unknown.is_empty())
+    }
+}
+
+/// Signing status of a single PSBT input.
+#[derive(Debug, Clone)]
+pub enum InputSigningStatus {
+    /// The input is already finalized.
+    Finalized,
+    /// There is no plan for the outpoint spent by the input.
+    NoPlan(OutPoint),
+    /// The input is not finalized yet.
+    Pending(SigningProgress),
+}
+
+impl InputSigningStatus {
+    /// Whether the input is finalized or has everything its plan requires.
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Self::Finalized => true,
+            Self::NoPlan(_) => false,
+            Self::Pending(progress) => progress.is_complete(),
+        }
+    }
+
+    /// Get the signing progress if the input is not finalized yet.
+    pub fn progress(&self) -> Option<&SigningProgress> {
+        match self {
+            Self::Pending(progress) => Some(progress),
+            _ => None,
+        }
+    }
+}
+
+/// Signing progress of an input that is not finalized yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningProgress {
+    /// Keys that provided a signature required by the plan.
+    pub signed: Vec<KeySource>,
+    /// Keys that still have to provide a signature required by the plan.
+    pub needed: Vec<KeySource>,
+    /// Signatures still required from keys whose origin is unknown, either because the key has
+    /// no derivation path or because it is only known by a hash that the PSBT input does not
+    /// resolve. These are not part of `needed`.
+    pub unknown: Vec<MissingSignature>,
+    /// Everything the plan is still missing, including hash preimages and timelocks.
+    pub missing: MissingRequirements,
+}
+
+impl SigningProgress {
+    /// Whether the plan has everything it requires.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Whether the key with the `fingerprint` still has to sign the input.
+    pub fn needs_signer(&self, fingerprint: Fingerprint) -> bool {
+        self.needed.iter().any(|(fp, _)| *fp == fingerprint)
+    }
+}
+
+/// Get the origin of the key that provides the signature, looking up keys that are only known by
+/// their hash in the PSBT input.
+fn signature_key_source(sig: &MissingSignature, psbt_input: &psbt::Input) -> Option<KeySource> {
+    match sig {
+        MissingSignature::Ecdsa(key) | MissingSignature::Schnorr { key, .. } => {
+            Some((key.master_fingerprint(), key.full_derivation_path()?))
+        }
+        MissingSignature::EcdsaKeyHash(hash) => psbt_input
+            .bip32_derivation
+            .iter()
+            .find(|(pk, _)| hash160::Hash::hash(&pk.serialize()) == *hash)
+            .map(|(_, key_source)| key_source.clone()),
+        MissingSignature::SchnorrKeyHash { hash, .. } => psbt_input
+            .tap_key_origins
+            .iter()
+            .find(|(pk, _)| hash160::Hash::hash(&pk.serialize()) == *hash)
+            .map(|(_, (_, key_source))| key_source.clone()),
+    }
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Output, PsbtParams, Selection, Signer};
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{absolute, transaction, Amount, ScriptBuf, Transaction, TxIn, TxOut};
+    use miniscript::descriptor::KeyMap;
+    use miniscript::plan::Assets;
+    use miniscript::Descriptor;
+    use std::string::String;
+
+    #[test]
+    fn test_multisig_signing_progress() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let keys = (0..3u8)
+            .map(|i| {
+                let xprv = Xpriv::new_master(bitcoin::Network::Testnet, &[i; 32])?;
+                Ok(format!("{xprv}/0/*"))
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        let (desc, keymap) =
+            Descriptor::parse_descriptor(&secp, &format!("wsh(multi(2,{}))", keys.join(",")))?;
+        let desc = desc.at_derivation_index(0)?;
+
+        // Plan to spend with the first two keys.
+        let signers = keymap
+            .iter()
+            .take(2)
+            .map(|(pk, sk)| (pk.master_fingerprint(), (pk.clone(), sk.clone())))
+            .collect::<Vec<_>>();
+        let assets = signers
+            .iter()
+            .fold(Assets::new(), |assets, (_, (pk, _))| assets.add(pk.clone()));
+        let plan = desc.clone().plan(&assets).expect("failed to create plan");
+        let prev_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: desc.script_pubkey(),
+                value: Amount::from_sat(100_000),
+            }],
+        };
+        let input = crate::Input::from_prev_tx(plan, prev_tx, 0, None)?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        let status = SigningStatus::new(&psbt, &finalizer);
+        assert!(!status.is_ready_to_finalize());
+        let expected_signers = signers.iter().map(|(fp, _)| *fp).collect::<BTreeSet<_>>();
+        assert_eq!(status.needed_signers(), expected_signers);
+        let progress = status.input(0).and_then(InputSigningStatus::progress);
+        let progress = progress.expect("input is pending");
+        assert!(progress.signed.is_empty());
+        assert_eq!(progress.needed.len(), 2);
+
+        // The first signer signs.
+        let (first_fp, first_key) = signers[0].clone();
+        let first: KeyMap = core::iter::once(first_key).collect();
+        psbt.sign(&Signer(first), &secp).expect("signing failed");
+
+        let status = SigningStatus::new(&psbt, &finalizer);
+        assert!(!status.is_ready_to_finalize());
+        let progress = status.input(0).and_then(InputSigningStatus::progress);
+        let progress = progress.expect("input is pending");
+        assert_eq!(progress.signed.len(), 1);
+        assert_eq!(progress.signed[0].0, first_fp);
+        assert!(!progress.needs_signer(first_fp));
+        assert!(progress.needs_signer(signers[1].0));
+        assert_eq!(progress.missing.signatures.len(), 1);
+
+        // The second signer completes the input.
+        let second: KeyMap = core::iter::once(signers[1].1.clone()).collect();
+        psbt.sign(&Signer(second), &secp).expect("signing failed");
+
+        let status = SigningStatus::new(&psbt, &finalizer);
+        assert!(status.is_ready_to_finalize());
+        assert!(status.needed_signers().is_empty());
+        assert!(finalizer.finalize(&mut psbt).is_finalized());
+        assert!(matches!(
+            SigningStatus::new(&psbt, &finalizer).input(0),
+            Some(InputSigningStatus::Finalized)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_key_origin_is_reported() -> anyhow::Result<()> {
+        use bitcoin::secp256k1::{PublicKey, SecretKey};
+        use miniscript::plan::AssetProvider;
+        use miniscript::{DefiniteDescriptorKey, ExtParams, Miniscript, Segwitv0};
+
+        // Knows the key behind the raw key hash, but not its origin.
+        struct RawPkhProvider(bitcoin::PublicKey);
+        impl AssetProvider<DefiniteDescriptorKey> for RawPkhProvider {
+            fn provider_lookup_raw_pkh_ecdsa_sig(
+                &self,
+                hash: &hash160::Hash,
+            ) -> Option<bitcoin::PublicKey> {
+                (self.0.pubkey_hash().to_raw_hash() == *hash).then_some(self.0)
+            }
+        }
+
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32])?;
+        let pk = bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, &sk));
+        let hash = pk.pubkey_hash().to_raw_hash();
+        let ms = Miniscript::<DefiniteDescriptorKey, Segwitv0>::from_str_ext(
+            &format!("c:expr_raw_pkh({hash})"),
+            &ExtParams::allow_all(),
+        )?;
+        let desc = Descriptor::new_wsh(ms)?;
+        let plan = desc
+            .clone()
+            .plan(&RawPkhProvider(pk))
+            .expect("failed to create plan");
+        let prev_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: desc.script_pubkey(),
+                value: Amount::from_sat(100_000),
+            }],
+        };
+        let input = crate::Input::from_prev_tx(plan, prev_tx, 0, None)?;
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(9_000));
+        let selection = Selection::new(vec![input], vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        let finalizer = selection.into_finalizer();
+
+        let status = SigningStatus::new(&psbt, &finalizer);
+        assert!(status.has_unknown_signers());
+        assert!(status.needed_signers().is_empty());
+        let progress = status.input(0).and_then(InputSigningStatus::progress);
+        let progress = progress.expect("input is pending");
+        assert!(progress.needed.is_empty());
+        assert_eq!(progress.unknown, vec![MissingSignature::EcdsaKeyHash(hash)]);
+
+        // Once the PSBT input resolves the key hash, the key is reported as needed.
+        let key_source = (Fingerprint::from([1, 2, 3, 4]), "m/0".parse()?);
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pk.inner, key_source.clone());
+        let status = SigningStatus::new(&psbt, &finalizer);
+        assert!(!status.has_unknown_signers());
+        let progress = status.input(0).and_then(InputSigningStatus::progress);
+        let progress = progress.expect("input is pending");
+        assert!(progress.unknown.is_empty());
+        assert_eq!(progress.needed, vec![key_source]);
+
+        Ok(())
+    }
+}
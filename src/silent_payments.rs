@@ -0,0 +1,549 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::bech32::{primitives::decode::CheckedHrpstring, Bech32m};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Keypair, TapTweak, TweakedPublicKey, XOnlyPublicKey};
+use bitcoin::psbt::{self, GetKey, KeyRequest};
+use bitcoin::secp256k1::{self, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::{OutPoint, ScriptBuf};
+use miniscript::bitcoin;
+
+use crate::collections::BTreeMap;
+use crate::Input;
+
+/// "Nothing up my sleeve" point H from BIP341, used as internal key to disable key-path spends.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// A [BIP352] silent payment address.
+///
+/// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SilentPaymentAddress {
+    /// Scan public key (`B_scan`).
+    pub scan_key: PublicKey,
+    /// Spend public key (`B_m`).
+    pub spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// Create from the `scan_key` and `spend_key`.
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey) -> Self {
+        Self {
+            scan_key,
+            spend_key,
+        }
+    }
+}
+
+/// Error parsing a [`SilentPaymentAddress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseSilentPaymentAddressError {
+    /// The string is not valid bech32m.
+    Bech32,
+    /// The human-readable part is not one of `sp`, `tsp` or `sprt`.
+    InvalidHrp,
+    /// The address version is not supported.
+    UnsupportedVersion,
+    /// The address does not encode two valid public keys.
+    InvalidKeys,
+}
+
+impl fmt::Display for ParseSilentPaymentAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bech32 => write!(f, "invalid bech32m encoding"),
+            Self::InvalidHrp => write!(f, "invalid silent payment human-readable part"),
+            Self::UnsupportedVersion => write!(f, "unsupported silent payment version"),
+            Self::InvalidKeys => write!(f, "invalid silent payment public keys"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseSilentPaymentAddressError {}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = ParseSilentPaymentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hrpstring = CheckedHrpstring::new::<Bech32m>(s)
+            .map_err(|_| ParseSilentPaymentAddressError::Bech32)?;
+        if !["sp", "tsp", "sprt"]
+            .iter()
+            .any(|hrp| hrpstring.hrp().as_str().eq_ignore_ascii_case(hrp))
+        {
+            return Err(ParseSilentPaymentAddressError::InvalidHrp);
+        }
+        let version = hrpstring
+            .remove_witness_version()
+            .ok_or(ParseSilentPaymentAddressError::UnsupportedVersion)?
+            .to_u8();
+        let data = hrpstring.byte_iter().collect::<Vec<u8>>();
+        // Version 0 must be exactly 66 bytes. Future versions may append data, which version 0
+        // senders ignore. Version 31 is reserved for a backwards incompatible change.
+        let keys = match version {
+            0 if data.len() == 66 => &data[..],
+            1..=30 if data.len() >= 66 => &data[..66],
+            _ => return Err(ParseSilentPaymentAddressError::UnsupportedVersion),
+        };
+        let scan_key = PublicKey::from_slice(&keys[..33])
+            .map_err(|_| ParseSilentPaymentAddressError::InvalidKeys)?;
+        let spend_key = PublicKey::from_slice(&keys[33..])
+            .map_err(|_| ParseSilentPaymentAddressError::InvalidKeys)?;
+        Ok(Self::new(scan_key, spend_key))
+    }
+}
+
+/// Error when resolving silent payment outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    /// The input is not eligible for silent payments under BIP352.
+    IneligibleInput(OutPoint),
+    /// The signer does not have the private key of the input.
+    MissingPrivateKey(OutPoint),
+    /// The selection has no inputs.
+    NoInputs,
+    /// The input keys or the shared secret are invalid (e.g. the private keys sum to zero).
+    InvalidSharedSecret,
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IneligibleInput(op) => {
+                write!(f, "input {op} is not eligible for silent payments")
+            }
+            Self::MissingPrivateKey(op) => write!(f, "missing private key for input {op}"),
+            Self::NoInputs => write!(f, "silent payments require at least one input"),
+            Self::InvalidSharedSecret => write!(f, "invalid silent payment shared secret"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentError {}
+
+/// Filter out inputs that are not eligible for silent payments.
+///
+/// Eligible inputs are P2TR (unless the internal key is the BIP341 NUMS point), P2WPKH,
+/// P2SH-P2WPKH and P2PKH inputs with a plan. Use this before selection so that
+/// [`Selection::resolve_silent_payments`](crate::Selection::resolve_silent_payments) does not
+/// reject the selected inputs.
+pub fn filter_silent_payment_eligible() -> impl Fn(&Input) -> bool {
+    |input| is_silent_payment_eligible(input)
+}
+
+fn is_silent_payment_eligible(input: &Input) -> bool {
+    let Some(plan) = input.plan() else {
+        return false;
+    };
+    let mut psbt_input = psbt::Input::default();
+    plan.update_psbt_input(&mut psbt_input);
+    let spk = &input.prev_txout().script_pubkey;
+    if spk.is_p2tr() {
+        return psbt_input
+            .tap_internal_key
+            .is_some_and(|key| key.serialize() != NUMS_H);
+    }
+    spk.is_p2wpkh()
+        || spk.is_p2pkh()
+        || (spk.is_p2sh()
+            && psbt_input
+                .redeem_script
+                .as_ref()
+                .is_some_and(|script| script.is_p2wpkh()))
+}
+
+/// Get the private key `a_i` that `input` contributes to the silent payment shared secret.
+fn input_secret_key<K, C>(
+    input: &Input,
+    signer: &K,
+    secp: &Secp256k1<C>,
+) -> Result<SecretKey, SilentPaymentError>
+where
+    K: GetKey,
+    C: secp256k1::Signing + secp256k1::Verification,
+{
+    let outpoint = input.prev_outpoint();
+    if !is_silent_payment_eligible(input) {
+        return Err(SilentPaymentError::IneligibleInput(outpoint));
+    }
+    let plan = input.plan().expect("eligible input must have plan");
+    let mut psbt_input = psbt::Input::default();
+    plan.update_psbt_input(&mut psbt_input);
+    let get_key = |requests: [Option<KeyRequest>; 2]| {
+        requests
+            .into_iter()
+            .flatten()
+            .find_map(|request| signer.get_key(request, secp).ok().flatten())
+            .ok_or(SilentPaymentError::MissingPrivateKey(outpoint))
+    };
+
+    let spk = &input.prev_txout().script_pubkey;
+    if spk.is_p2tr() {
+        let internal_key = psbt_input
+            .tap_internal_key
+            .expect("eligible input must have internal key");
+        let origin = psbt_input
+            .tap_key_origins
+            .get(&internal_key)
+            .map(|(_, origin)| KeyRequest::Bip32(origin.clone()));
+        let prv = get_key([origin, Some(KeyRequest::XOnlyPubkey(internal_key))])?;
+        let keypair = Keypair::from_secret_key(secp, &prv.inner)
+            .tap_tweak(secp, psbt_input.tap_merkle_root)
+            .to_keypair();
+        let (output_key, parity) = keypair.x_only_public_key();
+        if ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(output_key))
+            != *spk
+        {
+            return Err(SilentPaymentError::MissingPrivateKey(outpoint));
+        }
+        let secret_key = keypair.secret_key();
+        return Ok(match parity {
+            secp256k1::Parity::Odd => secret_key.negate(),
+            secp256k1::Parity::Even => secret_key,
+        });
+    }
+
+    let (pubkey, origin) = psbt_input
+        .bip32_derivation
+        .iter()
+        .next()
+        .ok_or(SilentPaymentError::IneligibleInput(outpoint))?;
+    let prv = get_key([
+        Some(KeyRequest::Bip32(origin.clone())),
+        Some(KeyRequest::Pubkey(bitcoin::PublicKey::new(*pubkey))),
+    ])?;
+    if prv.public_key(secp).inner != *pubkey {
+        return Err(SilentPaymentError::MissingPrivateKey(outpoint));
+    }
+    Ok(prv.inner)
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_ref());
+    engine.input(tag_hash.as_ref());
+    for bytes in data {
+        engine.input(bytes);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Compute the script pubkeys of `addresses` paid to by a transaction spending `inputs`.
+///
+/// Returned scripts are in the same order as `addresses`.
+pub(crate) fn silent_payment_scripts<'a, K, C>(
+    inputs: &[Input],
+    addresses: impl IntoIterator<Item = &'a SilentPaymentAddress>,
+    signer: &K,
+    secp: &Secp256k1<C>,
+) -> Result<Vec<ScriptBuf>, SilentPaymentError>
+where
+    K: GetKey,
+    C: secp256k1::Signing + secp256k1::Verification,
+{
+    let mut secret_key = Option::<SecretKey>::None;
+    for input in inputs {
+        let input_key = input_secret_key(input, signer, secp)?;
+        secret_key = Some(match secret_key {
+            Some(sum) => sum
+                .add_tweak(&Scalar::from(input_key))
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?,
+            None => input_key,
+        });
+    }
+    let secret_key = secret_key.ok_or(SilentPaymentError::NoInputs)?;
+    let pubkey = PublicKey::from_secret_key(secp, &secret_key);
+
+    let smallest_outpoint = inputs
+        .iter()
+        .map(|input| bitcoin::consensus::serialize(&input.prev_outpoint()))
+        .min()
+        .expect("inputs must not be empty");
+    let input_hash = Scalar::from_be_bytes(tagged_hash(
+        "BIP0352/Inputs",
+        &[&smallest_outpoint, &pubkey.serialize()],
+    ))
+    .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+    let tweaked_secret_key = Scalar::from(
+        secret_key
+            .mul_tweak(&input_hash)
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?,
+    );
+
+    let mut counters = BTreeMap::<PublicKey, u32>::new();
+    addresses
+        .into_iter()
+        .map(|address| {
+            let shared_secret = address
+                .scan_key
+                .mul_tweak(secp, &tweaked_secret_key)
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            let k = counters.entry(address.scan_key).or_insert(0);
+            let t_k = Scalar::from_be_bytes(tagged_hash(
+                "BIP0352/SharedSecret",
+                &[&shared_secret.serialize(), &k.to_be_bytes()],
+            ))
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            *k += 1;
+            let output_key = address
+                .spend_key
+                .add_exp_tweak(secp, &t_k)
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            Ok(ScriptBuf::new_p2tr_tweaked(
+                TweakedPublicKey::dangerous_assume_tweaked(XOnlyPublicKey::from(output_key)),
+            ))
+        })
+        .collect()
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, parse_descriptor, TR_XPRV, WPKH_XPRV, WSH_XPRV};
+    use crate::{Output, PsbtParams, Selection, Signer};
+    use bitcoin::{Amount, TxOut};
+    use std::string::ToString;
+
+    fn input_at(
+        descriptor: &str,
+        index: u32,
+        outpoint: OutPoint,
+    ) -> anyhow::Result<(Input, Signer)> {
+        let (desc, keymap) = parse_descriptor(descriptor)?;
+        let def_desc = desc.at_derivation_index(index)?;
+        let script_pubkey = def_desc.script_pubkey();
+        let plan = def_desc
+            .plan(&assets(&keymap))
+            .expect("failed to create plan");
+        let txout = TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        };
+        Ok((
+            Input::from_prev_txout(plan, outpoint, txout, None, false),
+            Signer(keymap),
+        ))
+    }
+
+    /// Receiver side of BIP352: `P_k = B_spend + hash(input_hash·b_scan·A || k)·G`.
+    fn receiver_script(
+        b_scan: &SecretKey,
+        spend_key: &PublicKey,
+        input_pubkey_sum: &PublicKey,
+        smallest_outpoint: OutPoint,
+        k: u32,
+    ) -> ScriptBuf {
+        let secp = Secp256k1::new();
+        let input_hash = Scalar::from_be_bytes(tagged_hash(
+            "BIP0352/Inputs",
+            &[
+                &bitcoin::consensus::serialize(&smallest_outpoint),
+                &input_pubkey_sum.serialize(),
+            ],
+        ))
+        .unwrap();
+        let shared_secret = input_pubkey_sum
+            .mul_tweak(&secp, &Scalar::from(b_scan.mul_tweak(&input_hash).unwrap()))
+            .unwrap();
+        let t_k = Scalar::from_be_bytes(tagged_hash(
+            "BIP0352/SharedSecret",
+            &[&shared_secret.serialize(), &k.to_be_bytes()],
+        ))
+        .unwrap();
+        let output_key = spend_key.add_exp_tweak(&secp, &t_k).unwrap();
+        ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+            output_key.into(),
+        ))
+    }
+
+    #[test]
+    fn test_parse_silent_payment_address() -> anyhow::Result<()> {
+        let address: SilentPaymentAddress = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv".parse()?;
+        assert_eq!(
+            address.scan_key.to_string(),
+            "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4"
+        );
+        assert_eq!(
+            address.spend_key.to_string(),
+            "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36"
+        );
+        assert!("bc1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+            .parse::<SilentPaymentAddress>()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_silent_payments_matches_receiver() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let op_a = OutPoint::new(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16".parse()?,
+            0,
+        );
+        let op_b = OutPoint::new(
+            "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d".parse()?,
+            0,
+        );
+        let (input_a, signer_a) = input_at(TR_XPRV, 0, op_a)?;
+        let (input_b, signer_b) = input_at(WPKH_XPRV, 0, op_b)?;
+        let signer = Signer(signer_a.0.into_iter().chain(signer_b.0).collect());
+
+        let b_scan = SecretKey::from_slice(&[3_u8; 32])?;
+        let b_spend = SecretKey::from_slice(&[4_u8; 32])?;
+        let address = SilentPaymentAddress::new(
+            PublicKey::from_secret_key(&secp, &b_scan),
+            PublicKey::from_secret_key(&secp, &b_spend),
+        );
+
+        let mut selection = Selection::new(
+            vec![input_a.clone(), input_b.clone()],
+            vec![
+                Output::with_silent_payment(address, Amount::from_sat(10_000)),
+                Output::with_silent_payment(address, Amount::from_sat(20_000)),
+            ],
+        );
+        let placeholder_weight = selection.outputs()[0].txout().weight();
+        assert!(matches!(
+            selection.create_psbt(PsbtParams::default()),
+            Err(crate::CreatePsbtError::UnresolvedSilentPayment(0))
+        ));
+
+        selection.resolve_silent_payments(&signer, &secp)?;
+        assert!(selection
+            .outputs()
+            .iter()
+            .all(|o| o.silent_payment().is_none()));
+        assert_eq!(selection.outputs()[0].txout().weight(), placeholder_weight);
+
+        // Sum of input public keys, as seen by the receiver.
+        let input_pubkey_sum = [&input_a, &input_b]
+            .iter()
+            .map(|input| {
+                PublicKey::from_secret_key(
+                    &secp,
+                    &input_secret_key(input, &signer, &secp).expect("must have key"),
+                )
+            })
+            .reduce(|a, b| a.combine(&b).unwrap())
+            .unwrap();
+        let smallest = [op_a, op_b]
+            .into_iter()
+            .min_by_key(bitcoin::consensus::serialize)
+            .unwrap();
+        for (k, output) in selection.outputs().iter().enumerate() {
+            assert_eq!(
+                output.script_pubkey(),
+                receiver_script(
+                    &b_scan,
+                    &address.spend_key,
+                    &input_pubkey_sum,
+                    smallest,
+                    k as u32
+                )
+            );
+        }
+        assert_ne!(
+            selection.outputs()[0].script_pubkey(),
+            selection.outputs()[1].script_pubkey()
+        );
+
+        let psbt = selection.create_psbt(PsbtParams {
+            mandate_full_tx_for_segwit_v0: false,
+            ..Default::default()
+        })?;
+        assert!(psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .all(|txout| txout.script_pubkey.is_p2tr()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_silent_payments_rejects_ineligible_input() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let (input, signer) = input_at(WSH_XPRV, 0, OutPoint::null())?;
+        assert!(!filter_silent_payment_eligible()(&input));
+
+        let key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3_u8; 32])?);
+        let mut selection = Selection::new(
+            vec![input],
+            vec![Output::with_silent_payment(
+                SilentPaymentAddress::new(key, key),
+                Amount::from_sat(10_000),
+            )],
+        );
+        assert_eq!(
+            selection.resolve_silent_payments(&signer, &secp),
+            Err(SilentPaymentError::IneligibleInput(OutPoint::null()))
+        );
+        Ok(())
+    }
+
+    /// Input spending P2WPKH of the raw private key `secret_key`.
+    fn vector_input(secret_key: &str, outpoint: OutPoint) -> anyhow::Result<(Input, Signer)> {
+        let prv = bitcoin::PrivateKey::new(secret_key.parse()?, bitcoin::Network::Bitcoin);
+        input_at(&format!("wpkh({})", prv.to_wif()), 0, outpoint)
+    }
+
+    /// Sending test vectors of BIP352, with P2WPKH inputs of the given private keys.
+    #[test]
+    fn test_bip352_vectors() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let address: SilentPaymentAddress = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv".parse()?;
+        let key_a = "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1";
+        let key_b = "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16";
+        let txid_a = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+        let txid_b = "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d";
+        type Case<'a> = (&'a [(&'a str, &'a str, u32)], &'a str);
+        let cases: &[Case] = &[
+            // Simple send: two inputs.
+            (
+                &[(key_a, txid_a, 0), (key_b, txid_b, 0)],
+                "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+            ),
+            // Simple send: two inputs, order reversed.
+            (
+                &[(key_b, txid_b, 0), (key_a, txid_a, 0)],
+                "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+            ),
+            // Simple send: two inputs from the same transaction.
+            (
+                &[(key_a, txid_a, 3), (key_b, txid_a, 7)],
+                "79e71baa2ba3fc66396de3a04f168c7bf24d6870ec88ca877754790c1db357b6",
+            ),
+        ];
+        for &(inputs, expected) in cases {
+            let mut signer = Signer(Default::default());
+            let mut selection_inputs = Vec::new();
+            for &(key, txid, vout) in inputs {
+                let (input, input_signer) = vector_input(key, OutPoint::new(txid.parse()?, vout))?;
+                signer.0.extend(input_signer.0);
+                selection_inputs.push(input);
+            }
+            let mut selection = Selection::new(
+                selection_inputs,
+                vec![Output::with_silent_payment(
+                    address,
+                    Amount::from_sat(10_000),
+                )],
+            );
+            selection.resolve_silent_payments(&signer, &secp)?;
+            let output_key: XOnlyPublicKey = expected.parse()?;
+            assert_eq!(
+                selection.outputs()[0].script_pubkey(),
+                ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(output_key))
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Fixtures shared by the unit tests.
+#![allow(unused)]
+
+use alloc::{vec, vec::Vec};
+
+use bitcoin::{
+    absolute, hashes::Hash, secp256k1::Secp256k1, transaction, Amount, OutPoint, Transaction, TxIn,
+    TxOut, Txid,
+};
+use miniscript::{bitcoin, descriptor::KeyMap, plan::Assets, Descriptor, DescriptorPublicKey};
+
+use crate::{ConfirmationStatus, DefiniteDescriptor, Input};
+
+pub const TR_XPRV: &str = "tr(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/86h/1h/0h/0/*)";
+pub const TR_CHANGE_XPRV: &str = "tr(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/86h/1h/0h/1/*)";
+pub const WPKH_XPRV: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84h/1h/0h/0/*)";
+pub const PKH_XPRV: &str = "pkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/44h/1h/0h/0/*)";
+pub const WSH_XPRV: &str = "wsh(pk(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84h/1h/0h/1/*))";
+
+// Descriptors of other wallets, for tests with several parties.
+pub const TR_XPRV_2: &str = "tr(tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj/86h/1h/0h/0/*)";
+pub const WPKH_XPRV_2: &str = "wpkh(tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj/84h/1h/0h/0/*)";
+pub const TR_XPRV_3: &str = "tr(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/86h/1h/0h/0/*)";
+
+/// Parse a descriptor with private keys into the public descriptor and its keys.
+pub fn parse_descriptor(
+    descriptor: &str,
+) -> anyhow::Result<(Descriptor<DescriptorPublicKey>, KeyMap)> {
+    Ok(Descriptor::parse_descriptor(&Secp256k1::new(), descriptor)?)
+}
+
+/// `descriptor` derived at `index`.
+pub fn descriptor_at(descriptor: &str, index: u32) -> anyhow::Result<DefiniteDescriptor> {
+    Ok(parse_descriptor(descriptor)?.0.at_derivation_index(index)?)
+}
+
+/// Assets to sign with every key of `keymap`.
+pub fn assets(keymap: &KeyMap) -> Assets {
+    keymap.keys().fold(Assets::new(), |a, k| a.add(k.clone()))
+}
+
+/// A tx with `output`, whose txid differs for each `n`.
+pub fn prev_tx(n: u32, output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), n),
+            ..Default::default()
+        }],
+        output,
+    }
+}
+
+/// An input spending `value` paid to `desc` by [`prev_tx`] `n`.
+pub fn input(
+    desc: &DefiniteDescriptor,
+    assets: &Assets,
+    value: Amount,
+    n: u32,
+    status: Option<ConfirmationStatus>,
+) -> anyhow::Result<Input> {
+    let prev_tx = prev_tx(
+        n,
+        vec![TxOut {
+            script_pubkey: desc.script_pubkey(),
+            value,
+        }],
+    );
+    let plan = desc.clone().plan(assets).expect("failed to create plan");
+    Ok(Input::from_prev_tx(plan, prev_tx, 0, status)?)
+}
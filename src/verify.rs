@@ -0,0 +1,356 @@
+use crate::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use bitcoin::{
+    psbt,
+    secp256k1::{Secp256k1, Verification},
+    sighash::Prevouts,
+    OutPoint, Psbt, TxOut, Witness,
+};
+use miniscript::{bitcoin, interpreter, Interpreter};
+
+/// Verify the final scriptSig and witness of every input of a finalized PSBT.
+///
+/// Each input is executed against the output it spends, taken from the `non_witness_utxo` or
+/// `witness_utxo` of the PSBT input, using the miniscript [`Interpreter`]. This runs offline
+/// and catches a wrong plan or a bad foreign input before the transaction is extracted and
+/// broadcast.
+///
+/// Note that the interpreter only understands scripts that can be parsed as miniscript. Inputs
+/// spending other scripts cannot be checked, and are reported with
+/// [`InputScriptError::UnsupportedScript`] rather than as invalid.
+///
+/// # Errors
+///
+/// Returns a [`VerifyScriptsError`] containing the failure of every input that did not pass.
+pub fn verify_final_scripts<C: Verification>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+) -> Result<(), VerifyScriptsError> {
+    let mut failures = BTreeMap::new();
+
+    let mut utxo_failures = BTreeMap::new();
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .zip(&psbt.unsigned_tx.input)
+        .enumerate()
+        .map(|(input_index, (psbt_input, txin))| {
+            spent_txout(psbt_input, txin.previous_output)
+                .map_err(|e| utxo_failures.insert(input_index, e))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    let all_prevouts = prevouts.iter().cloned().collect::<Option<Vec<_>>>();
+
+    for (input_index, (psbt_input, txin)) in
+        psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate()
+    {
+        if psbt_input.final_script_sig.is_none() && psbt_input.final_script_witness.is_none() {
+            failures.insert(input_index, InputScriptError::NotFinalized);
+            continue;
+        }
+        let prevout = match &prevouts[input_index] {
+            Some(prevout) => prevout,
+            None => {
+                let e = utxo_failures
+                    .remove(&input_index)
+                    .expect("failure of a missing prevout must be recorded");
+                failures.insert(input_index, e);
+                continue;
+            }
+        };
+        let prevouts = match &all_prevouts {
+            Some(all_prevouts) => Prevouts::All(all_prevouts),
+            None => Prevouts::One(input_index, prevout.clone()),
+        };
+
+        let script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+        let witness = psbt_input
+            .final_script_witness
+            .clone()
+            .unwrap_or_else(Witness::new);
+        let interpreter = match Interpreter::from_txdata(
+            &prevout.script_pubkey,
+            &script_sig,
+            &witness,
+            txin.sequence,
+            psbt.unsigned_tx.lock_time,
+        ) {
+            Ok(interpreter) => interpreter,
+            Err(
+                e @ (interpreter::Error::Miniscript(_) | interpreter::Error::TapAnnexUnsupported),
+            ) => {
+                failures.insert(input_index, InputScriptError::UnsupportedScript(e));
+                continue;
+            }
+            Err(e) => {
+                failures.insert(input_index, InputScriptError::Interpreter(e));
+                continue;
+            }
+        };
+        let result = interpreter
+            .iter(secp, &psbt.unsigned_tx, input_index, &prevouts)
+            .try_for_each(|constraint| constraint.map(|_| ()));
+        if let Err(e) = result {
+            failures.insert(input_index, InputScriptError::Interpreter(e));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(VerifyScriptsError(failures))
+    }
+}
+
+/// The output spent by the PSBT input.
+///
+/// The `non_witness_utxo` is preferred when present, and must be the transaction of `outpoint`
+/// and agree with the `witness_utxo`.
+pub(crate) fn spent_txout(
+    psbt_input: &psbt::Input,
+    outpoint: OutPoint,
+) -> Result<TxOut, InputScriptError> {
+    let tx = match &psbt_input.non_witness_utxo {
+        Some(tx) => tx,
+        None => {
+            return psbt_input
+                .witness_utxo
+                .clone()
+                .ok_or(InputScriptError::MissingUtxo)
+        }
+    };
+    if tx.compute_txid() != outpoint.txid {
+        return Err(InputScriptError::UtxoMismatch);
+    }
+    let txout = tx
+        .output
+        .get(outpoint.vout as usize)
+        .ok_or(InputScriptError::MissingUtxo)?;
+    if psbt_input
+        .witness_utxo
+        .as_ref()
+        .is_some_and(|witness_utxo| witness_utxo != txout)
+    {
+        return Err(InputScriptError::UtxoMismatch);
+    }
+    Ok(txout.clone())
+}
+
+/// Error when verifying the final scripts of a PSBT with [`verify_final_scripts`].
+#[derive(Debug)]
+pub struct VerifyScriptsError(BTreeMap<usize, InputScriptError>);
+
+impl VerifyScriptsError {
+    /// Get the failures as a map of `input_index` to [`InputScriptError`].
+    pub fn failures(&self) -> &BTreeMap<usize, InputScriptError> {
+        &self.0
+    }
+
+    /// Get the failure of the input at `input_index`, if it failed.
+    pub fn failure(&self, input_index: usize) -> Option<&InputScriptError> {
+        self.0.get(&input_index)
+    }
+}
+
+impl Display for VerifyScriptsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "script verification failed for")?;
+        for (i, (input_index, e)) in self.0.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(f, "{sep} input {input_index} ({e})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyScriptsError {}
+
+/// Reason an input failed script verification.
+#[derive(Debug)]
+pub enum InputScriptError {
+    /// The input has neither a final scriptSig nor a final witness.
+    NotFinalized,
+    /// The output spent by the input is unknown.
+    MissingUtxo,
+    /// The `non_witness_utxo` of the input is not the transaction of the spent outpoint, or
+    /// disagrees with the `witness_utxo`.
+    UtxoMismatch,
+    /// Executing the input's scripts failed.
+    Interpreter(interpreter::Error),
+    /// The input's scripts cannot be parsed as miniscript, so they could not be checked. This
+    /// does not mean that the input is invalid.
+    UnsupportedScript(interpreter::Error),
+}
+
+impl InputScriptError {
+    /// Whether the input could not be checked, rather than found invalid.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(self, Self::UnsupportedScript(_))
+    }
+}
+
+impl Display for InputScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFinalized => write!(f, "input is not finalized"),
+            Self::MissingUtxo => write!(f, "missing utxo"),
+            Self::UtxoMismatch => write!(f, "non-witness utxo does not match the spent output"),
+            Self::Interpreter(e) => write!(f, "{e}"),
+            Self::UnsupportedScript(e) => write!(f, "script cannot be checked: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InputScriptError {}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, parse_descriptor, prev_tx, TR_XPRV, WPKH_XPRV};
+    use crate::{Output, PsbtParams, Selection, Signer};
+    use bitcoin::opcodes::all::{OP_ADD, OP_EQUAL};
+    use bitcoin::{absolute, script::Builder, transaction, Amount, ScriptBuf, Transaction, TxIn};
+
+    fn signed_psbt() -> anyhow::Result<Psbt> {
+        let secp = Secp256k1::new();
+        let mut inputs = Vec::new();
+        let mut keymap = miniscript::descriptor::KeyMap::new();
+        for (vout, descriptor) in [TR_XPRV, WPKH_XPRV].into_iter().enumerate() {
+            let (desc, keys) = parse_descriptor(descriptor)?;
+            let desc = desc.at_derivation_index(0)?;
+            let assets = assets(&keys);
+            keymap.extend(keys);
+            // Spend output `vout`, after `vout` empty outputs.
+            let empty = TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::ZERO,
+            };
+            let mut output = vec![empty; vout];
+            output.push(TxOut {
+                script_pubkey: desc.script_pubkey(),
+                value: Amount::from_sat(100_000),
+            });
+            let prev_tx = prev_tx(0, output);
+            let plan = desc.plan(&assets).expect("failed to create plan");
+            inputs.push(crate::Input::from_prev_tx(plan, prev_tx, vout, None)?);
+        }
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(150_000));
+        let selection = Selection::new(inputs, vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        psbt.sign(&Signer(keymap), &secp).expect("signing failed");
+        assert!(selection
+            .into_finalizer()
+            .finalize(&mut psbt)
+            .is_finalized());
+        Ok(psbt)
+    }
+
+    #[test]
+    fn test_verify_final_scripts() -> anyhow::Result<()> {
+        let secp = Secp256k1::verification_only();
+        let psbt = signed_psbt()?;
+        verify_final_scripts(&psbt, &secp)?;
+
+        // Spending a different output invalidates the signatures of every input.
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(140_000);
+        let err = verify_final_scripts(&tampered, &secp).expect_err("must fail");
+        assert_eq!(err.failures().len(), 2);
+        assert!(matches!(
+            err.failure(0),
+            Some(InputScriptError::Interpreter(_))
+        ));
+
+        // Inputs that are not finalized or miss their utxo are reported.
+        let mut incomplete = psbt;
+        incomplete.inputs[0].final_script_witness = None;
+        incomplete.inputs[1].witness_utxo = None;
+        incomplete.inputs[1].non_witness_utxo = None;
+        let err = verify_final_scripts(&incomplete, &secp).expect_err("must fail");
+        assert!(matches!(
+            err.failure(0),
+            Some(InputScriptError::NotFinalized)
+        ));
+        assert!(matches!(
+            err.failure(1),
+            Some(InputScriptError::MissingUtxo)
+        ));
+
+        // The non-witness utxo must be the spent transaction and agree with the witness utxo.
+        let psbt = signed_psbt()?;
+        assert!(psbt.inputs[1].non_witness_utxo.is_some());
+        let mut mismatched = psbt.clone();
+        mismatched.inputs[1]
+            .witness_utxo
+            .as_mut()
+            .expect("must have witness utxo")
+            .value = Amount::from_sat(1_000_000);
+        let err = verify_final_scripts(&mismatched, &secp).expect_err("must fail");
+        assert!(matches!(
+            err.failure(1),
+            Some(InputScriptError::UtxoMismatch)
+        ));
+        let mut mismatched = psbt;
+        mismatched.inputs[1]
+            .non_witness_utxo
+            .as_mut()
+            .expect("must have non-witness utxo")
+            .lock_time = absolute::LockTime::from_consensus(1);
+        let err = verify_final_scripts(&mismatched, &secp).expect_err("must fail");
+        assert!(matches!(
+            err.failure(1),
+            Some(InputScriptError::UtxoMismatch)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_final_scripts_unsupported_script() -> anyhow::Result<()> {
+        let secp = Secp256k1::verification_only();
+        // `OP_ADD 2 OP_EQUAL` is a valid script, but not miniscript.
+        let witness_script = Builder::new()
+            .push_opcode(OP_ADD)
+            .push_int(2)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let prev_tx = prev_tx(
+            0,
+            vec![TxOut {
+                script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+                value: Amount::from_sat(100_000),
+            }],
+        );
+        let unsigned_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.compute_txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(90_000),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+        psbt.inputs[0].witness_utxo = Some(prev_tx.output[0].clone());
+        psbt.inputs[0].final_script_witness = Some(Witness::from_slice(&[
+            vec![1],
+            vec![1],
+            witness_script.to_bytes(),
+        ]));
+
+        let err = verify_final_scripts(&psbt, &secp).expect_err("cannot be checked");
+        let failure = err.failure(0).expect("must be reported");
+        assert!(matches!(failure, InputScriptError::UnsupportedScript(_)));
+        assert!(failure.is_unsupported());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use bitcoin::{psbt, Amount, FeeRate, Psbt, Weight};
+use miniscript::bitcoin;
+
+use crate::input::{estimate_tx_weight, final_satisfaction_weight};
+use crate::Selection;
+
+impl Selection {
+    /// Compare the actual satisfaction weight of each input of the finalized `psbt` with the
+    /// estimate used by this selection.
+    ///
+    /// The estimate of an input is its [`Input::satisfaction_weight`], so an input whose
+    /// signatures or witness turn out larger than planned, like a foreign input with a wrong
+    /// estimate, makes the transaction pay less than the target feerate. The returned
+    /// [`WeightCheck`] reports the real feerate and flags such inputs.
+    ///
+    /// Inputs of the `psbt` are matched with the inputs of the selection by outpoint.
+    ///
+    /// # Errors
+    ///
+    /// - If an input of the `psbt` is not part of the selection.
+    /// - If an input of the `psbt` is not finalized.
+    /// - If the fee of the `psbt` cannot be computed.
+    ///
+    /// [`Input::satisfaction_weight`]: crate::Input::satisfaction_weight
+    pub fn check_weight(&self, psbt: &Psbt) -> Result<WeightCheck, CheckWeightError> {
+        let mut inputs = Vec::with_capacity(psbt.inputs.len());
+        let mut estimates = Vec::with_capacity(psbt.inputs.len());
+        for (input_index, (psbt_input, txin)) in
+            psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate()
+        {
+            let input = self
+                .inputs()
+                .iter()
+                .find(|input| input.prev_outpoint() == txin.previous_output)
+                .ok_or(CheckWeightError::UnknownInput(input_index))?;
+            if psbt_input.final_script_sig.is_none() && psbt_input.final_script_witness.is_none() {
+                return Err(CheckWeightError::NotFinalized(input_index));
+            }
+            inputs.push(InputWeightCheck {
+                input_index,
+                estimated: Weight::from_wu(input.satisfaction_weight()),
+                actual: Weight::from_wu(final_satisfaction_weight(psbt_input)),
+            });
+            estimates.push((input.satisfaction_weight(), input.is_segwit()));
+        }
+
+        let fee = psbt.fee().map_err(CheckWeightError::Fee)?;
+        let actual_weight = psbt.clone().extract_tx_unchecked_fee_rate().weight();
+        let estimated_weight = estimate_tx_weight(&psbt.unsigned_tx, estimates);
+
+        Ok(WeightCheck {
+            inputs,
+            fee,
+            estimated_weight,
+            actual_weight,
+        })
+    }
+}
+
+/// Result of [`Selection::check_weight`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightCheck {
+    /// Estimated and actual satisfaction weight of each input.
+    pub inputs: Vec<InputWeightCheck>,
+    /// The fee paid by the transaction.
+    pub fee: Amount,
+    /// The weight of the transaction as estimated by the selection.
+    pub estimated_weight: Weight,
+    /// The actual weight of the finalized transaction.
+    pub actual_weight: Weight,
+}
+
+impl WeightCheck {
+    /// The feerate the finalized transaction actually pays.
+    pub fn actual_feerate(&self) -> FeeRate {
+        self.fee / self.actual_weight
+    }
+
+    /// The feerate the transaction pays according to the estimated weight.
+    pub fn estimated_feerate(&self) -> FeeRate {
+        self.fee / self.estimated_weight
+    }
+
+    /// The inputs whose satisfaction weight was underestimated.
+    pub fn underestimated_inputs(&self) -> impl Iterator<Item = &InputWeightCheck> {
+        self.inputs.iter().filter(|input| input.is_underestimated())
+    }
+
+    /// Whether the satisfaction weight of every input was estimated high enough.
+    pub fn is_ok(&self) -> bool {
+        self.underestimated_inputs().next().is_none()
+    }
+}
+
+/// Estimated and actual satisfaction weight of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputWeightCheck {
+    /// Index of the input in the PSBT.
+    pub input_index: usize,
+    /// The satisfaction weight used by the selection.
+    pub estimated: Weight,
+    /// The weight of the final scriptSig and witness.
+    pub actual: Weight,
+}
+
+impl InputWeightCheck {
+    /// Whether the actual satisfaction weight exceeds the estimate.
+    pub fn is_underestimated(&self) -> bool {
+        self.actual > self.estimated
+    }
+}
+
+/// Error when checking the weight of a finalized PSBT with [`Selection::check_weight`].
+#[derive(Debug)]
+pub enum CheckWeightError {
+    /// The input at the index is not part of the selection.
+    UnknownInput(usize),
+    /// The input at the index is not finalized.
+    NotFinalized(usize),
+    /// Failed to compute the fee.
+    Fee(psbt::Error),
+}
+
+impl Display for CheckWeightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownInput(index) => write!(f, "input {index} is not part of the selection"),
+            Self::NotFinalized(index) => write!(f, "input {index} is not finalized"),
+            Self::Fee(e) => write!(f, "failed to compute fee: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CheckWeightError {}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assets, input, parse_descriptor, WPKH_XPRV};
+    use crate::{Input, Output, PsbtParams, Signer};
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{ScriptBuf, Sequence};
+
+    #[test]
+    fn test_check_weight_flags_underestimated_input() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let (desc, keymap) = parse_descriptor(WPKH_XPRV)?;
+        let assets = assets(&keymap);
+        let mut inputs = Vec::new();
+        let mut psbt_inputs = Vec::new();
+        for index in 0..2 {
+            let desc = desc.at_derivation_index(index)?;
+            let input = input(&desc, &assets, Amount::from_sat(100_000), index, None)?;
+            psbt_inputs.push(
+                Selection::new(vec![input.clone()], vec![])
+                    .create_psbt(PsbtParams::default())?
+                    .inputs
+                    .remove(0),
+            );
+            inputs.push(input);
+        }
+
+        // The second input is a foreign input with an estimate that is too low.
+        let foreign = Input::from_psbt_input(
+            inputs[1].prev_outpoint(),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt_inputs.remove(1),
+            10,
+            None,
+            inputs[1].is_coinbase(),
+            None,
+        )?;
+        inputs[1] = foreign;
+
+        let output = Output::with_script(ScriptBuf::new(), Amount::from_sat(199_000));
+        let selection = Selection::new(inputs, vec![output]);
+        let mut psbt = selection.create_psbt(PsbtParams::default())?;
+        psbt.sign(&Signer(keymap), &secp).expect("signing failed");
+
+        assert!(matches!(
+            selection.check_weight(&psbt),
+            Err(CheckWeightError::NotFinalized(0))
+        ));
+
+        assert!(selection
+            .clone()
+            .into_finalizer()
+            .finalize(&mut psbt)
+            .is_finalized());
+        let check = selection.check_weight(&psbt)?;
+        assert!(!check.is_ok());
+        let underestimated = check.underestimated_inputs().collect::<Vec<_>>();
+        assert_eq!(underestimated.len(), 1);
+        assert_eq!(underestimated[0].input_index, 1);
+        assert!(!check.inputs[0].is_underestimated());
+        assert_eq!(check.fee, Amount::from_sat(1_000));
+        // The unsigned transaction weighs 404 wu, including an empty scriptSig length byte per
+        // input that the satisfaction weights replace, and the segwit marker and flag add 2 wu.
+        assert_eq!(check.inputs[0].estimated, Weight::from_wu(112));
+        assert_eq!(check.inputs[0].actual, Weight::from_wu(112));
+        assert_eq!(check.inputs[1].estimated, Weight::from_wu(10));
+        assert_eq!(check.inputs[1].actual, Weight::from_wu(111));
+        assert_eq!(
+            check.estimated_weight,
+            Weight::from_wu(404 - 2 * 4 + 112 + 10 + 2)
+        );
+        assert_eq!(
+            check.actual_weight,
+            Weight::from_wu(404 - 2 * 4 + 112 + 111 + 2)
+        );
+        assert!(check.actual_feerate() < check.estimated_feerate());
+
+        Ok(())
+    }
+}